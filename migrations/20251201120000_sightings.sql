@@ -0,0 +1,17 @@
+-- Per-chat override of the global similarity threshold
+ALTER TABLE chats ADD COLUMN similarity_threshold SMALLINT;
+
+ALTER TABLE images ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+-- Every detected duplicate, the original stays in images
+CREATE TABLE sightings (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    message_id INTEGER NOT NULL,
+    original_message_id INTEGER NOT NULL,
+    distance SMALLINT NOT NULL,
+    false_positive BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX sightings_chat_id_created_at_idx ON sightings (chat_id, created_at DESC);
@@ -0,0 +1,3 @@
+-- Stable id of the media (Telegram's file_unique_id), the image archive is keyed by it
+ALTER TABLE images ADD COLUMN media_key TEXT;
+ALTER TABLE sightings ADD COLUMN media_key TEXT;
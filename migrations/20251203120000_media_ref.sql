@@ -0,0 +1,2 @@
+-- Platform handle the original can be downloaded again with (Telegram's file_id)
+ALTER TABLE images ADD COLUMN media_ref TEXT;
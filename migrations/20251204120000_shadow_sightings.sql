@@ -0,0 +1,12 @@
+-- Messages where the shadow threshold disagreed with the live one
+CREATE TABLE shadow_sightings (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    message_id INTEGER NOT NULL,
+    original_message_id INTEGER,
+    distance SMALLINT,
+    shadow_threshold SMALLINT NOT NULL,
+    -- Whether the live threshold flagged the message
+    live_flagged BOOLEAN NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,10 @@
+-- Messages some replica has taken on, so replicas sharing the database don't handle one twice
+CREATE TABLE claimed_messages (
+    bot_id BIGINT NOT NULL,
+    chat_id BIGINT NOT NULL,
+    message_id INTEGER NOT NULL,
+    claimed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (bot_id, chat_id, message_id)
+);
+
+CREATE INDEX claimed_messages_claimed_at_idx ON claimed_messages (claimed_at);
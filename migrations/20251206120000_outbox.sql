@@ -0,0 +1,14 @@
+-- Replies waiting to be sent, so they survive crashes and Telegram outages
+CREATE TABLE outbox (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    -- Which bot has to send it, they can't reply in each other's chats
+    bot_id BIGINT NOT NULL,
+    chat_id BIGINT NOT NULL,
+    reply_to INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX outbox_due_idx ON outbox (bot_id, next_attempt_at);
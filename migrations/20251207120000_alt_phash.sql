@@ -0,0 +1,3 @@
+-- Hash of a fixed smaller rendition of photos, Telegram's sizes of one image don't always
+-- hash the same so this gives a second, consistent point of comparison
+ALTER TABLE images ADD COLUMN alt_phash BIGINT;
@@ -0,0 +1,2 @@
+-- The pinned message the bot keeps up to date with the chat's stats
+ALTER TABLE chats ADD COLUMN stats_message_id INTEGER;
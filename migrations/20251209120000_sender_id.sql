@@ -0,0 +1,4 @@
+-- Who posted the image: a user id, or for anonymous admins and posts on behalf of a channel
+-- the (negative) id of the chat it was sent as
+ALTER TABLE images ADD COLUMN sender_id BIGINT;
+ALTER TABLE sightings ADD COLUMN sender_id BIGINT;
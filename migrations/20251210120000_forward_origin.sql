@@ -0,0 +1,4 @@
+-- Where a forwarded image came from: the original chat/channel (or user), and the
+-- message in it when Telegram tells us
+ALTER TABLE images ADD COLUMN forward_from_id BIGINT, ADD COLUMN forward_message_id INTEGER;
+ALTER TABLE sightings ADD COLUMN forward_from_id BIGINT, ADD COLUMN forward_message_id INTEGER;
@@ -0,0 +1,10 @@
+-- Discussion groups and the channels they're linked to. Images posted in the channel
+-- count as originals for reposts in the group.
+CREATE TABLE chat_links (
+    group_id BIGINT PRIMARY KEY,
+    channel_id BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Set when the original is in a linked channel rather than the chat itself
+ALTER TABLE sightings ADD COLUMN original_chat_id BIGINT;
@@ -0,0 +1,3 @@
+-- Images sent behind a spoiler, replies about them must not reveal them
+ALTER TABLE images ADD COLUMN spoiler BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE outbox ADD COLUMN spoiler BOOLEAN NOT NULL DEFAULT FALSE;
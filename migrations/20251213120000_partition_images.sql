@@ -0,0 +1,43 @@
+-- Partition images by chat, one partition per chat, so per-chat queries only touch that
+-- chat's rows. Partitions are created by ensure_image_partition() when a chat is first seen,
+-- images of chats without one yet land in images_default.
+ALTER TABLE images RENAME TO images_unpartitioned;
+ALTER TABLE images_unpartitioned RENAME CONSTRAINT images_pkey TO images_unpartitioned_pkey;
+ALTER TABLE images_unpartitioned RENAME CONSTRAINT images_chat_id_fkey TO images_unpartitioned_chat_id_fkey;
+
+-- The partition key has to be part of the primary key
+CREATE TABLE images (
+    LIKE images_unpartitioned INCLUDING DEFAULTS,
+    PRIMARY KEY (chat_id, id),
+    FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE
+) PARTITION BY LIST (chat_id);
+
+CREATE TABLE images_default PARTITION OF images DEFAULT;
+
+CREATE FUNCTION ensure_image_partition(chat BIGINT) RETURNS VOID AS $$
+DECLARE
+    partition TEXT := 'images_' || replace(chat::TEXT, '-', 'n');
+BEGIN
+    IF to_regclass(partition) IS NOT NULL THEN
+        RETURN;
+    END IF;
+
+    -- Serializes concurrent first sightings of the same chat
+    PERFORM pg_advisory_xact_lock(chat);
+    IF to_regclass(partition) IS NOT NULL THEN
+        RETURN;
+    END IF;
+
+    -- Rows already in the default partition have to move before the new one can be attached
+    EXECUTE format('CREATE TABLE %I (LIKE images INCLUDING DEFAULTS)', partition);
+    EXECUTE format('INSERT INTO %I SELECT * FROM images_default WHERE chat_id = $1', partition)
+        USING chat;
+    DELETE FROM images_default WHERE chat_id = chat;
+    EXECUTE format('ALTER TABLE images ATTACH PARTITION %I FOR VALUES IN (%s)', partition, chat);
+END
+$$ LANGUAGE plpgsql;
+
+SELECT ensure_image_partition(id) FROM chats;
+
+INSERT INTO images SELECT * FROM images_unpartitioned;
+DROP TABLE images_unpartitioned;
@@ -0,0 +1,2 @@
+-- Deleted images are only marked and purged later, so deletions can be undone
+ALTER TABLE images ADD COLUMN deleted_at TIMESTAMPTZ;
@@ -0,0 +1,15 @@
+-- Every action the escalation policy took against a reposter
+CREATE TABLE enforcement_actions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    sender_id BIGINT NOT NULL,
+    message_id INTEGER NOT NULL,
+    -- "delete" or "restrict"
+    action TEXT NOT NULL,
+    -- Reposts by the sender within the policy's window, this one included
+    reposts INTEGER NOT NULL,
+    restricted_until TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX enforcement_actions_chat_id_created_at_idx ON enforcement_actions (chat_id, created_at DESC);
@@ -0,0 +1,4 @@
+-- Admins lifting restrictions are recorded too, with who did it. Their rows have no
+-- repost count.
+ALTER TABLE enforcement_actions ADD COLUMN actor_id BIGINT;
+ALTER TABLE enforcement_actions ALTER COLUMN reposts DROP NOT NULL;
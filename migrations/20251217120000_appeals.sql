@@ -0,0 +1,6 @@
+-- What an enforcement action was based on, shown to admins when it's appealed
+ALTER TABLE enforcement_actions
+    ADD COLUMN original_chat_id BIGINT,
+    ADD COLUMN original_message_id INTEGER,
+    ADD COLUMN distance SMALLINT,
+    ADD COLUMN appealed_at TIMESTAMPTZ;
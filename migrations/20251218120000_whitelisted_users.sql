@@ -0,0 +1,16 @@
+-- Senders whose images are neither indexed nor flagged in a chat. Usernames are for
+-- members added by @name, the Bot API can't turn those into ids.
+CREATE TABLE whitelisted_users (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    chat_id BIGINT NOT NULL,
+    -- Same id space as images.sender_id
+    sender_id BIGINT,
+    -- Lowercase, without the @
+    username TEXT,
+    added_by BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (sender_id IS NOT NULL OR username IS NOT NULL)
+);
+
+CREATE UNIQUE INDEX whitelisted_users_sender_idx ON whitelisted_users (chat_id, sender_id);
+CREATE UNIQUE INDEX whitelisted_users_username_idx ON whitelisted_users (chat_id, username);
@@ -0,0 +1,2 @@
+-- Images whose message turned out to be deleted, they're no longer matched against.
+ALTER TABLE images ADD COLUMN stale_at TIMESTAMPTZ;
@@ -0,0 +1,7 @@
+-- Hashes of (nearly) solid images, e.g. black screenshots, which all match each other. They're
+-- kept but not matched against by default.
+ALTER TABLE images ADD COLUMN low_entropy BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Assumes the default 64-bit hashes, new images are flagged by the bot.
+UPDATE images SET low_entropy = TRUE
+WHERE bit_count(phash::bit(64)) <= 2 OR bit_count(phash::bit(64)) >= 62;
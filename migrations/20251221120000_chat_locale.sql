@@ -0,0 +1,3 @@
+-- How dates are shown in a chat, UTC and ISO dates when not set.
+ALTER TABLE chats ADD COLUMN timezone TEXT;
+ALTER TABLE chats ADD COLUMN locale TEXT;
@@ -0,0 +1,9 @@
+-- When each scheduled job last ran, so restarts pick up where they left off and replicas
+-- don't run a job twice.
+CREATE TABLE scheduled_jobs (
+    name TEXT PRIMARY KEY,
+    -- The time the run was due, not when it actually started
+    last_run_at TIMESTAMPTZ NOT NULL,
+    finished_at TIMESTAMPTZ,
+    last_error TEXT
+);
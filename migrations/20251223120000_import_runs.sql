@@ -0,0 +1,15 @@
+-- One row per importer run, for checking that imports finished and what they brought in.
+CREATE TABLE import_runs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    source TEXT NOT NULL,
+    chat_id BIGINT NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- NULL while running, or if the importer was killed
+    finished_at TIMESTAMPTZ,
+    hashed INT NOT NULL DEFAULT 0,
+    skipped INT NOT NULL DEFAULT 0,
+    failed INT NOT NULL DEFAULT 0,
+    error TEXT
+);
+
+CREATE INDEX import_runs_chat_id_started_at_idx ON import_runs (chat_id, started_at DESC);
@@ -0,0 +1,9 @@
+-- Each message is indexed at most once, re-imports and reprocessing update the existing row.
+-- Of messages already indexed more than once, the first row is kept.
+DELETE FROM images a
+USING images b
+WHERE a.chat_id = b.chat_id
+  AND a.message_id = b.message_id
+  AND (a.created_at, a.id) > (b.created_at, b.id);
+
+CREATE UNIQUE INDEX images_chat_id_message_id_idx ON images (chat_id, message_id);
@@ -0,0 +1,6 @@
+-- Where each image came from: "live" for the bot, "import:<file>" for imports, so a batch can
+-- be pruned or redone on its own. What was indexed before this is of unknown origin.
+ALTER TABLE images ADD COLUMN source TEXT NOT NULL DEFAULT 'unknown';
+ALTER TABLE images ALTER COLUMN source SET DEFAULT 'live';
+
+CREATE INDEX images_source_idx ON images (source);
@@ -0,0 +1,2 @@
+-- Reposts of images younger than this are let off as accidental double-sends
+ALTER TABLE chats ADD COLUMN min_repost_age_secs INTEGER;
@@ -0,0 +1,20 @@
+-- Chats whose images count as originals for each other, mirrored from the config at startup
+CREATE TABLE chat_networks (
+    network TEXT NOT NULL,
+    chat_id BIGINT NOT NULL,
+    PRIMARY KEY (network, chat_id)
+);
+
+CREATE INDEX chat_networks_chat_id_idx ON chat_networks (chat_id);
+
+-- Every chat whose images a chat's new images are matched against
+CREATE FUNCTION match_sources(chat BIGINT) RETURNS TABLE (source BIGINT) AS $$
+    SELECT chat
+    UNION
+    SELECT channel_id FROM chat_links WHERE group_id = chat
+    UNION
+    SELECT other.chat_id
+    FROM chat_networks own
+    JOIN chat_networks other USING (network)
+    WHERE own.chat_id = chat
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,3 @@
+-- When an image was last the original of a repost, for evicting least-recently-matched
+-- images first once a chat is over its quota
+ALTER TABLE images ADD COLUMN last_matched_at TIMESTAMPTZ;
@@ -0,0 +1,15 @@
+-- Everything the bot and admins did, kept after a chat's images are gone so moderation
+-- disputes can still be settled
+CREATE TABLE audit_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    -- NULL for actions across chats, like evicting images over the quota
+    chat_id BIGINT,
+    -- NULL when the bot acted on its own
+    actor_id BIGINT,
+    action TEXT NOT NULL,
+    payload JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX audit_log_chat_id_created_at_idx ON audit_log (chat_id, created_at DESC);
+CREATE INDEX audit_log_created_at_idx ON audit_log (created_at DESC);
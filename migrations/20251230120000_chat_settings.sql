@@ -0,0 +1,5 @@
+-- Per-chat switches of the /settings menu: record duplicates without replying to them, and
+-- whether the escalation policy applies in the chat at all
+ALTER TABLE chats
+    ADD COLUMN silent BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN enforce BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,10 @@
+-- The chats table doubles as the registry of chats the bot is in, kept up to date from
+-- its membership updates. Chats indexed before then have no type and count as added when
+-- they were first seen.
+ALTER TABLE chats
+    ADD COLUMN type TEXT,
+    ADD COLUMN added_at TIMESTAMPTZ,
+    ADD COLUMN removed_at TIMESTAMPTZ;
+
+UPDATE chats SET added_at = created_at;
+ALTER TABLE chats ALTER COLUMN added_at SET DEFAULT NOW();
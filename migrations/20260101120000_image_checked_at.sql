@@ -0,0 +1,6 @@
+-- When the stale check last found the image's message still there, so a sweep can probe
+-- the least recently checked ones first
+ALTER TABLE images ADD COLUMN checked_at TIMESTAMPTZ;
+
+CREATE INDEX images_checked_at_idx ON images (checked_at NULLS FIRST)
+    WHERE deleted_at IS NULL AND stale_at IS NULL;
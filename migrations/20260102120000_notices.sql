@@ -0,0 +1,22 @@
+-- Per-chat cleanup of the bot's duplicate notices, see /settings: deleted after a while,
+-- and/or along with the repost they're about
+ALTER TABLE chats
+    ADD COLUMN notice_lifetime_secs INTEGER,
+    ADD COLUMN delete_notices_with_repost BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Notices sent in chats with either of the above, until they're deleted
+CREATE TABLE notices (
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    message_id INTEGER NOT NULL,
+    -- Only the bot that sent a notice can delete it
+    bot_id BIGINT NOT NULL,
+    -- The repost the notice is about
+    flagged_message_id INTEGER NOT NULL,
+    repost_gone_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (chat_id, message_id)
+);
+
+CREATE INDEX notices_flagged_idx ON notices (chat_id, flagged_message_id);
+
+ALTER TABLE outbox ADD COLUMN flagged_message_id INTEGER;
@@ -0,0 +1,10 @@
+-- Each message is a sighting at most once, so the importer and the bot can both record one
+-- without the other's showing up twice. Of messages already recorded more than once, the
+-- first row is kept.
+DELETE FROM sightings a
+USING sightings b
+WHERE a.chat_id = b.chat_id
+  AND a.message_id = b.message_id
+  AND (a.created_at, a.id) > (b.created_at, b.id);
+
+CREATE UNIQUE INDEX sightings_chat_id_message_id_idx ON sightings (chat_id, message_id);
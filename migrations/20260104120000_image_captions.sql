@@ -0,0 +1,2 @@
+-- The text sent along with images, so they can be found by it.
+ALTER TABLE images ADD COLUMN caption TEXT;
@@ -0,0 +1,6 @@
+-- Full-text search over captions and chat titles. The 'simple' configuration doesn't stem,
+-- chats are in all sorts of languages. Partitions of images get the index too, including
+-- ones created later.
+CREATE INDEX images_caption_search ON images
+    USING GIN (to_tsvector('simple', COALESCE(caption, '')));
+CREATE INDEX chats_title_search ON chats USING GIN (to_tsvector('simple', title));
@@ -0,0 +1,4 @@
+-- What deleted each image: 'forget' for admins, 'stale' for messages found gone and 'prune'
+-- for pruned sources, so undoing one kind of deletion doesn't bring back another's. Images
+-- deleted before this are left without one, and aren't undone by anything.
+ALTER TABLE images ADD COLUMN deleted_by TEXT;
@@ -0,0 +1,3 @@
+-- Lets /why find the repost a duplicate notice is about and download it again: notices are
+-- now kept for every chat until they're too old to delete, along with the repost's file.
+ALTER TABLE sightings ADD COLUMN media_ref TEXT;
@@ -0,0 +1,80 @@
+use crate::config::ArchiveSettings;
+use object_store::ObjectStore;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use std::fs;
+use std::sync::Arc;
+
+pub use object_store::Error;
+
+/// Keeps the original bytes of every downloaded image, keyed by a stable media id
+/// (Telegram's file_unique_id), so they stay available after Telegram stops serving them.
+#[derive(Clone)]
+pub struct Archive {
+    store: Arc<dyn ObjectStore>,
+}
+
+impl Archive {
+    pub fn new(settings: &ArchiveSettings) -> Result<Self, Error> {
+        let store: Arc<dyn ObjectStore> = match settings {
+            ArchiveSettings::Disk { path } => {
+                fs::create_dir_all(path).map_err(|e| Error::Generic {
+                    store: "LocalFileSystem",
+                    source: Box::new(e),
+                })?;
+
+                Arc::new(LocalFileSystem::new_with_prefix(path)?)
+            }
+            #[cfg(feature = "s3")]
+            ArchiveSettings::S3 {
+                bucket,
+                region,
+                endpoint,
+                access_key_id,
+                secret_access_key,
+            } => {
+                let mut builder = object_store::aws::AmazonS3Builder::new()
+                    .with_bucket_name(bucket)
+                    .with_region(region)
+                    .with_access_key_id(access_key_id)
+                    .with_secret_access_key(secret_access_key);
+
+                // S3-compatible services (MinIO, R2, ...) mostly want path-style requests.
+                if let Some(endpoint) = endpoint {
+                    builder = builder
+                        .with_endpoint(endpoint)
+                        .with_allow_http(endpoint.starts_with("http://"));
+                }
+
+                Arc::new(builder.build()?)
+            }
+            #[cfg(not(feature = "s3"))]
+            ArchiveSettings::S3 { .. } => {
+                return Err(Error::NotImplemented);
+            }
+        };
+
+        Ok(Self { store })
+    }
+
+    pub async fn store(&self, key: &str, data: Vec<u8>) -> Result<(), Error> {
+        self.store.put(&path(key), data.into()).await?;
+
+        Ok(())
+    }
+
+    /// Returns `None` if nothing was archived under the key.
+    pub async fn load(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self.store.get(&path(key)).await {
+            Ok(result) => Ok(Some(result.bytes().await?.to_vec())),
+            Err(Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Spreads the objects over subdirectories so no single directory gets huge.
+fn path(key: &str) -> Path {
+    let prefix = key.get(..2).unwrap_or("_");
+    Path::from_iter([prefix, key])
+}
@@ -0,0 +1,55 @@
+//! Record of every reply, deletion, restriction and settings change the bot or an admin
+//! made, so moderation disputes can be settled with what actually happened.
+
+use crate::database;
+use serde_json::Value;
+use sqlx::PgPool;
+use tracing::error;
+
+/// What was done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// A message the bot sent in reply to one.
+    Reply,
+    /// A message the bot deleted from a chat.
+    Delete,
+    Restrict,
+    Unrestrict,
+    /// Images taken out of the index.
+    Forget,
+    /// Images put back into the index.
+    Restore,
+    /// A per-chat setting, the whitelist included.
+    Setting,
+}
+
+impl Action {
+    /// How the action is stored.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Reply => "reply",
+            Action::Delete => "delete",
+            Action::Restrict => "restrict",
+            Action::Unrestrict => "unrestrict",
+            Action::Forget => "forget",
+            Action::Restore => "restore",
+            Action::Setting => "setting",
+        }
+    }
+}
+
+/// Adds an entry to the audit log. `actor_id` is the admin behind the action, `None` if
+/// the bot acted on its own. Errors are only logged, what's done is done either way.
+pub async fn record(
+    pool: &PgPool,
+    chat_id: Option<i64>,
+    actor_id: Option<i64>,
+    action: Action,
+    payload: Value,
+) {
+    if let Err(e) =
+        database::save_audit_entry(pool, chat_id, actor_id, action.name(), &payload).await
+    {
+        error!("Error writing the audit log: {e}");
+    }
+}
@@ -0,0 +1,284 @@
+use crate::archive::{self, Archive};
+use crate::database;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use thiserror::Error;
+
+/// Bumped whenever the layout of the backup file itself changes.
+const FORMAT_VERSION: u32 = 1;
+
+/// Tables in the backup, in an order that satisfies their foreign keys on restore. Claimed
+/// messages and scheduled job runs are only meaningful to running replicas and left out.
+pub const TABLES: [&str; 12] = [
+    "chats",
+    "chat_links",
+    "chat_networks",
+    "images",
+    "sightings",
+    "shadow_sightings",
+    "outbox",
+    "enforcement_actions",
+    "notices",
+    "whitelisted_users",
+    "import_runs",
+    "audit_log",
+];
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("database error")]
+    Database(#[from] sqlx::Error),
+    #[error("archive error")]
+    Archive(#[from] archive::Error),
+    #[error("couldnt parse the manifest")]
+    Manifest(#[from] serde_json::Error),
+    #[error("not a backup: {0}")]
+    Invalid(String),
+    #[error("backup has schema version {backup}, this build has {current}")]
+    SchemaMismatch { backup: i64, current: i64 },
+    #[error("the database already has chats in it, restore into an empty one")]
+    NotEmpty,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    /// Latest migration applied to the database the backup was taken from.
+    schema_version: i64,
+    created_at: DateTime<Utc>,
+    with_images: bool,
+}
+
+/// Writes the bot's tables, and the archived images if `archive` is given, to a zstd
+/// compressed tarball at `out`.
+pub async fn backup(pool: &PgPool, archive: Option<&Archive>, out: &Path) -> Result<(), Error> {
+    let file = File::create(out)?;
+    let mut tar = tar::Builder::new(zstd::Encoder::new(file, 0)?.auto_finish());
+
+    // Every table is read from the same snapshot, so rows the bot writes in the meantime
+    // can't leave the backup with sightings of images it doesn't have.
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+
+    let keys: Vec<String> = match archive {
+        Some(_) => {
+            sqlx::query_scalar(
+                r#"
+                SELECT media_key FROM images WHERE media_key IS NOT NULL
+                UNION
+                SELECT media_key FROM sightings WHERE media_key IS NOT NULL
+                "#,
+            )
+            .fetch_all(&mut *tx)
+            .await?
+        }
+        None => Vec::new(),
+    };
+
+    let manifest = Manifest {
+        format: FORMAT_VERSION,
+        schema_version: database::schema_version(),
+        created_at: Utc::now(),
+        with_images: archive.is_some(),
+    };
+
+    // The manifest goes first so restores can bail out before reading anything else.
+    append(
+        &mut tar,
+        "manifest.json",
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+
+    for table in TABLES {
+        // Partitioned tables can only be copied out through a query.
+        let mut stream = tx
+            .copy_out_raw(&format!(
+                "COPY (SELECT * FROM {table}) TO STDOUT (FORMAT csv, HEADER)"
+            ))
+            .await?;
+
+        let mut csv = Vec::new();
+        while let Some(chunk) = stream.try_next().await? {
+            csv.extend_from_slice(&chunk);
+        }
+
+        append(&mut tar, &format!("tables/{table}.csv"), &csv)?;
+    }
+    tx.commit().await?;
+
+    let mut images = 0;
+    if let Some(archive) = archive {
+        for key in &keys {
+            if let Some(data) = archive.load(key).await? {
+                append(&mut tar, &format!("archive/{key}"), &data)?;
+                images += 1;
+            }
+        }
+    }
+
+    tar.into_inner()?;
+
+    println!(
+        "Backed up {} tables and {images} archived images to {}",
+        TABLES.len(),
+        out.display()
+    );
+
+    Ok(())
+}
+
+/// Loads a backup made by [`backup`] into an empty, migrated database, restoring archived
+/// images too if `archive` is given.
+pub async fn restore(pool: &PgPool, archive: Option<&Archive>, path: &Path) -> Result<(), Error> {
+    let file = File::open(path)?;
+    let mut tar = tar::Archive::new(zstd::Decoder::new(file)?);
+    let mut entries = tar.entries()?;
+
+    let mut next = || -> Result<Option<(String, Vec<u8>)>, Error> {
+        let Some(entry) = entries.next() else {
+            return Ok(None);
+        };
+
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+
+        Ok(Some((name, data)))
+    };
+
+    let manifest: Manifest = match next()? {
+        Some((name, data)) if name == "manifest.json" => serde_json::from_slice(&data)?,
+        _ => return Err(Error::Invalid("no manifest".to_owned())),
+    };
+
+    if manifest.format != FORMAT_VERSION {
+        return Err(Error::Invalid(format!(
+            "unsupported format version {}",
+            manifest.format
+        )));
+    }
+
+    let current = database::schema_version();
+    if manifest.schema_version != current {
+        return Err(Error::SchemaMismatch {
+            backup: manifest.schema_version,
+            current,
+        });
+    }
+
+    let mut tx = pool.begin().await?;
+
+    let chats: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM chats")
+        .fetch_one(&mut *tx)
+        .await?;
+    if chats > 0 {
+        return Err(Error::NotEmpty);
+    }
+
+    let mut images = 0;
+    while let Some((name, data)) = next()? {
+        if let Some(table) = name
+            .strip_prefix("tables/")
+            .and_then(|x| x.strip_suffix(".csv"))
+        {
+            if !TABLES.contains(&table) {
+                return Err(Error::Invalid(format!("unknown table {table}")));
+            }
+
+            // Columns are matched up by name, in case they're ordered differently here.
+            let columns = columns(&mut tx, table, &data).await?;
+
+            let mut copy = tx
+                .copy_in_raw(&format!(
+                    "COPY {table} ({columns}) FROM STDIN (FORMAT csv, HEADER)"
+                ))
+                .await?;
+            copy.send(data).await?;
+            let rows = copy.finish().await?;
+            println!("Restored {rows} rows of {table}");
+
+            // Images go into their chat's partition, which has to exist first.
+            if table == "chats" {
+                sqlx::query("SELECT ensure_image_partition(id) FROM chats")
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        } else if let Some(key) = name.strip_prefix("archive/") {
+            if let Some(archive) = archive {
+                archive.store(key, data).await?;
+                images += 1;
+            }
+        } else {
+            return Err(Error::Invalid(format!("unexpected entry {name}")));
+        }
+    }
+
+    tx.commit().await?;
+
+    if manifest.with_images {
+        println!("Restored {images} archived images");
+    }
+
+    Ok(())
+}
+
+/// The column list of a table's CSV, checked against the table's columns and quoted, so a
+/// crafted header can't inject SQL into the `COPY`.
+async fn columns(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    table: &str,
+    csv: &[u8],
+) -> Result<String, Error> {
+    let known: Vec<String> = sqlx::query_scalar(
+        r#"
+        SELECT column_name::TEXT FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = $1
+        "#,
+    )
+    .bind(table)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let header = csv.split(|x| *x == b'\n').next().unwrap_or_default();
+    let header = String::from_utf8_lossy(header);
+
+    let mut columns = Vec::new();
+    for column in header.trim_end_matches('\r').split(',') {
+        // COPY only quotes names that need it, none of ours do.
+        let column = column.trim_matches('"');
+        if !known.iter().any(|x| x == column) {
+            return Err(Error::Invalid(format!(
+                "unknown column {column} in {table}"
+            )));
+        }
+        columns.push(format!("\"{column}\""));
+    }
+
+    Ok(columns.join(", "))
+}
+
+fn append<W: std::io::Write>(
+    tar: &mut tar::Builder<W>,
+    name: &str,
+    data: &[u8],
+) -> Result<(), Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    header.set_cksum();
+
+    tar.append_data(&mut header, name, data)?;
+
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use crate::config::{HashAlgorithm, HashingSettings};
+use crate::decode;
+use crate::hashing::{self, Hasher};
+use crate::scan;
+use image::DynamicImage;
+use std::fs;
+use std::hint::black_box;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use thiserror::Error;
+
+const ALGORITHMS: [HashAlgorithm; 5] = [
+    HashAlgorithm::Mean,
+    HashAlgorithm::Gradient,
+    HashAlgorithm::VertGradient,
+    HashAlgorithm::DoubleGradient,
+    HashAlgorithm::Blockhash,
+];
+
+const SIZES: [u32; 3] = [4, 6, 8];
+
+/// Queries timed per scan benchmark.
+const SCAN_QUERIES: usize = 100;
+
+/// A scan for the nearest hash, like [`scan::nearest`].
+type Nearest = fn(&[u64], u64, u32) -> Option<(usize, u32)>;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("io error ({path})")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("couldnt decode {path}")]
+    Decode {
+        path: PathBuf,
+        #[source]
+        source: decode::Error,
+    },
+    #[error("{0} should contain exactly two images")]
+    Pair(PathBuf),
+    #[error("couldnt hash image")]
+    Hashing(#[from] hashing::Error),
+}
+
+struct Pair {
+    duplicate: bool,
+    a: DynamicImage,
+    b: DynamicImage,
+}
+
+/// Runs every algorithm and hash size over a labeled dataset and prints precision/recall per
+/// threshold. The dataset has `same/` and `different/` directories, each containing one
+/// directory per pair with exactly two images in it.
+pub fn run(dataset: &Path, base: &HashingSettings) -> Result<(), Error> {
+    let mut pairs = load_pairs(&dataset.join("same"), true)?;
+    pairs.extend(load_pairs(&dataset.join("different"), false)?);
+
+    let duplicates = pairs.iter().filter(|x| x.duplicate).count();
+    println!(
+        "Loaded {} pairs ({duplicates} duplicates, {} different).",
+        pairs.len(),
+        pairs.len() - duplicates
+    );
+
+    for algorithm in ALGORITHMS {
+        for size in SIZES {
+            let settings = HashingSettings {
+                algorithm,
+                hash_width: size,
+                hash_height: size,
+                ..base.clone()
+            };
+
+            let hasher = match Hasher::new(&settings) {
+                Ok(x) => x,
+                // Some combinations don't fit into 64 bits.
+                Err(hashing::Error::HashTooWide(_)) => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let mut distances = Vec::with_capacity(pairs.len());
+            for pair in &pairs {
+                let a = hasher.hash_image(&pair.a)?;
+                let b = hasher.hash_image(&pair.b)?;
+                distances.push(((a ^ b).count_ones(), pair.duplicate));
+            }
+
+            report(&format!("{algorithm:?} {size}x{size}"), &hasher, &distances);
+        }
+    }
+
+    Ok(())
+}
+
+fn report(name: &str, hasher: &Hasher, distances: &[(u32, bool)]) {
+    println!("\n{name} ({} bits)", hasher.bits());
+    println!("  thr  precision  recall     f1");
+
+    let mut best: Option<(u32, f64)> = None;
+
+    for threshold in 0..=hasher.bits() / 2 {
+        let flagged = |d: &&(u32, bool)| d.0 <= threshold;
+        let true_positives = distances.iter().filter(flagged).filter(|x| x.1).count() as f64;
+        let false_positives = distances.iter().filter(flagged).filter(|x| !x.1).count() as f64;
+        let positives = distances.iter().filter(|x| x.1).count() as f64;
+
+        let precision = if true_positives + false_positives > 0.0 {
+            true_positives / (true_positives + false_positives)
+        } else {
+            1.0
+        };
+        let recall = if positives > 0.0 {
+            true_positives / positives
+        } else {
+            0.0
+        };
+        let f1 = if precision + recall > 0.0 {
+            2.0 * precision * recall / (precision + recall)
+        } else {
+            0.0
+        };
+
+        println!("  {threshold:>3}  {precision:>9.3}  {recall:>6.3}  {f1:>5.3}");
+
+        if best.is_none_or(|(_, x)| f1 > x) {
+            best = Some((threshold, f1));
+        }
+    }
+
+    if let Some((threshold, f1)) = best {
+        println!("  best: threshold {threshold} (f1 {f1:.3})");
+    }
+}
+
+fn load_pairs(dir: &Path, duplicate: bool) -> Result<Vec<Pair>, Error> {
+    let mut pairs = Vec::new();
+
+    for entry in read_dir(dir)? {
+        if !entry.is_dir() {
+            continue;
+        }
+
+        let mut files = read_dir(&entry)?;
+        files.retain(|x| x.is_file());
+        files.sort();
+
+        let [a, b] = files.as_slice() else {
+            return Err(Error::Pair(entry));
+        };
+
+        pairs.push(Pair {
+            duplicate,
+            a: open(a)?,
+            b: open(b)?,
+        });
+    }
+
+    Ok(pairs)
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let io_error = |source| Error::Io {
+        path: dir.to_owned(),
+        source,
+    };
+
+    fs::read_dir(dir)
+        .map_err(io_error)?
+        .map(|x| x.map(|x| x.path()).map_err(io_error))
+        .collect()
+}
+
+fn open(path: &Path) -> Result<DynamicImage, Error> {
+    decode::open(path).map_err(|source| Error::Decode {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+/// Times [`scan::nearest`] against [`scan::nearest_portable`] over `entries` random hashes,
+/// checking they agree along the way.
+pub fn scan(entries: usize) {
+    let mut state = 0x2545f4914f6cdd1d_u64;
+    let mut random = move || {
+        // xorshift64, good enough to make up hashes.
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let hashes = (0..entries).map(|_| random()).collect::<Vec<_>>();
+    let queries = (0..SCAN_QUERIES).map(|_| random()).collect::<Vec<_>>();
+
+    let time = |name: &str, nearest: Nearest| {
+        let started = Instant::now();
+        let results = queries
+            .iter()
+            .map(|x| nearest(black_box(&hashes), black_box(*x), 16))
+            .collect::<Vec<_>>();
+        let per_scan = started.elapsed() / SCAN_QUERIES as u32;
+
+        println!("{name:>8}: {per_scan:?} per scan of {entries} hashes");
+        results
+    };
+
+    let portable = time("portable", scan::nearest_portable);
+    let dispatched = time("simd", scan::nearest);
+
+    assert_eq!(
+        portable, dispatched,
+        "SIMD scan disagrees with the portable one"
+    );
+}
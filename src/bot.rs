@@ -1,155 +1,923 @@
-use crate::config::Config;
-use crate::database;
-use anyhow::Result;
-use img_hash::HasherConfig;
+mod alerts;
+mod commands;
+mod escalation;
+mod owner;
+mod permissions;
+mod pinned_stats;
+mod polling;
+mod search;
+mod settings;
+pub mod stale_check;
+
+use alerts::Alerter;
+use anyhow::{Context, Result, bail};
+use dupfinder_tg::archive::Archive;
+use dupfinder_tg::audit;
+use dupfinder_tg::config::{
+    Config, EscalationSettings, QuotaSettings, RepostHandling, ScheduleSettings, TelegramSettings,
+};
+use dupfinder_tg::database;
+use dupfinder_tg::detector::{self, Detector};
+use dupfinder_tg::download_limiter::DownloadLimiter;
+use dupfinder_tg::hashing::Hasher;
+use dupfinder_tg::matching::{Matcher, Outcome};
+use dupfinder_tg::messenger::{ForwardOrigin, IncomingImage, MessageRef, Messenger};
+use dupfinder_tg::notices::Notices;
+use dupfinder_tg::outbox::Outbox;
+use dupfinder_tg::prefilter::Prefilter;
+use dupfinder_tg::scheduler::{Schedule, Scheduler};
+use dupfinder_tg::scripting::Scripts;
+use dupfinder_tg::send_limiter::SendLimiter;
+use dupfinder_tg::webhook::Webhooks;
+use dupfinder_tg::writer::Writer;
+use reqwest::{Proxy, Url};
+use serde_json::json;
 use sqlx::PgPool;
-use std::io::Cursor;
+use std::collections::HashSet;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
 use teloxide::net::Download;
 use teloxide::prelude::*;
+use teloxide::requests::Output;
 use teloxide::sugar::request::RequestReplyExt;
-use tracing::{debug, error, info};
+use teloxide::types::{
+    Chat, ChatMemberUpdated, ExternalReplyInfoKind, FileId, FileMeta, LinkPreviewOptions,
+    MessageId, MessageKind, MessageOrigin, PhotoSize, UpdateKind,
+};
+use teloxide::{ApiError, RequestError};
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
 
 #[derive(Clone)]
 struct BotState {
-    settings: Config,
-    pool: PgPool,
+    detector: Detector,
+    alerter: Option<Alerter>,
+    /// Empty means every chat is allowed.
+    allowed_chats: Arc<HashSet<i64>>,
+    purge_unauthorized: bool,
+    /// Set when replicas share the database, see [`claim`].
+    claim_as: Option<i64>,
+    messenger: TelegramMessenger,
+    index_queried: bool,
+    skip_forwards_from: Arc<HashSet<i64>>,
+    ignore_bots_in: Arc<HashSet<i64>>,
+    escalation: Option<Arc<EscalationSettings>>,
+    /// Users allowed to use every command everywhere, see [`permissions`].
+    owners: Arc<HashSet<i64>>,
+}
+
+pub async fn run(settings: Config, pool: PgPool, hasher: Hasher) -> Result<()> {
+    let hasher = Arc::new(hasher);
+    let mut bots = JoinSet::new();
+    // Shared by all bots, they download over the same connection after all.
+    let downloads = DownloadLimiter::new(&settings.downloads);
+    let read_pool = match &settings.database.read_url {
+        Some(url) => Some(database::init_pool(url).await?),
+        None => None,
+    };
+    // Shared so every bot sees the images the others index.
+    let prefilter = settings.prefilter.then(Prefilter::default);
+    let writer = settings
+        .batch_writes
+        .clone()
+        .map(|x| Writer::spawn(pool.clone(), x));
+    let networks = settings
+        .networks
+        .iter()
+        .map(|x| (x.name.as_str(), x.chats.as_slice()))
+        .collect::<Vec<_>>();
+    database::set_chat_networks(&pool, &networks).await?;
+    let schedules = Schedules::parse(&settings.schedule)?;
+    let mut scheduler = Scheduler::new(pool.clone());
+
+    for bot_settings in settings.bots() {
+        let bot = bot(bot_settings)?;
+        let name = bot_settings
+            .name
+            .clone()
+            .unwrap_or_else(|| "default".to_owned());
+
+        let threshold = settings.threshold(bot_settings, hasher.bits());
+        let mut matcher = Matcher::new(pool.clone(), threshold, hasher.bits());
+        if let Some(writer) = &writer {
+            matcher = matcher.with_writer(writer.clone());
+        }
+        if let Some(read_pool) = &read_pool {
+            matcher = matcher.with_read_pool(read_pool.clone());
+        }
+        if let Some(prefilter) = &prefilter {
+            matcher = matcher.with_prefilter(prefilter.clone());
+        }
+        if settings.purge_stale {
+            matcher = matcher.with_purge_stale();
+        }
+        if settings.match_low_entropy {
+            matcher = matcher.with_low_entropy_matching();
+        }
+
+        let mut detector = detector(&settings, hasher.clone(), matcher)?;
+
+        let messenger = TelegramMessenger {
+            bot: bot.clone(),
+            local_files: bot_settings.local_files,
+            downloads: downloads.clone(),
+            download_timeout: Duration::from_secs(settings.downloads.timeout_secs),
+            sends: SendLimiter::new(&settings.send_limits),
+            max_send_retries: settings.send_limits.max_retries,
+        };
+
+        if settings.outbox {
+            let outbox = Outbox::new(pool.clone(), bot_id(&bot_settings.token));
+            detector = detector.with_outbox(outbox.clone());
+            tokio::spawn(outbox.run(messenger.clone()));
+        }
+
+        let notices = Notices::new(pool.clone(), bot_id(&bot_settings.token));
+        detector = detector.with_notices(notices.clone());
+        tokio::spawn(notices.run(messenger.clone()));
+
+        let alerter = settings
+            .alerts
+            .clone()
+            .map(|settings| Alerter::new(bot.clone(), settings));
+
+        self_test(&bot, &name, alerter.as_ref()).await?;
+
+        let allowed_chats: Arc<HashSet<i64>> =
+            Arc::new(bot_settings.allowed_chats.iter().copied().collect());
+
+        if bot_settings.pinned_stats {
+            let (bot, pool, allowed_chats) =
+                (bot.clone(), pool.clone(), Arc::clone(&allowed_chats));
+            scheduler.add(
+                format!("pinned-stats:{name}"),
+                schedules.pinned_stats.clone(),
+                move || pinned_stats::update_all(bot.clone(), pool.clone(), allowed_chats.clone()),
+            );
+        }
+
+        if let Some(stale_check) = &settings.stale_check {
+            let (bot, pool, stale_check) = (bot.clone(), pool.clone(), stale_check.clone());
+            let purge = settings.purge_stale;
+            let chats = allowed_chats.iter().copied().collect::<Vec<_>>();
+            scheduler.add(
+                format!("stale-check:{name}"),
+                schedules.stale_check.clone(),
+                move || {
+                    let (bot, pool, stale_check, chats) = (
+                        bot.clone(),
+                        pool.clone(),
+                        stale_check.clone(),
+                        chats.clone(),
+                    );
+                    async move {
+                        let result =
+                            stale_check::check(&bot, &pool, &stale_check, purge, &chats).await?;
+                        info!(
+                            "Checked {} indexed messages, {} of them were gone",
+                            result.checked, result.stale
+                        );
+                        anyhow::Ok(())
+                    }
+                },
+            );
+        }
+
+        let state = BotState {
+            detector,
+            alerter,
+            allowed_chats,
+            purge_unauthorized: bot_settings.purge_unauthorized,
+            claim_as: settings.claim_messages.then(|| bot_id(&bot_settings.token)),
+            messenger,
+            index_queried: settings.index_queried,
+            skip_forwards_from: Arc::new(settings.skip_forwards_from.iter().copied().collect()),
+            ignore_bots_in: Arc::new(settings.ignore_bots_in.iter().copied().collect()),
+            escalation: settings.escalation.clone().map(Arc::new),
+            owners: Arc::new(settings.owners.iter().copied().collect()),
+        };
+
+        // Define the command handler (or message handler)
+        // Channel posts are indexed too, they're the originals for reposts in linked groups.
+        let handler = dptree::entry()
+            .branch(
+                Update::filter_message()
+                    .filter(|msg: Message| msg.chat.is_private())
+                    .filter_command::<owner::OwnerCommand>()
+                    .endpoint(owner::handle),
+            )
+            .branch(
+                Update::filter_message()
+                    .filter_command::<commands::Command>()
+                    .endpoint(commands::handle),
+            )
+            .branch(Update::filter_message().endpoint(message_handler))
+            .branch(Update::filter_callback_query().endpoint(callback_handler))
+            .branch(Update::filter_my_chat_member().endpoint(membership_handler))
+            .branch(Update::filter_channel_post().endpoint(message_handler));
+
+        info!("Bot {name} started...");
+
+        let listener = polling::listener(bot.clone(), &settings.polling).await?;
+
+        bots.spawn(async move {
+            Dispatcher::builder(bot, handler)
+                .dependencies(dptree::deps![state])
+                .distribution_function(chat_queue)
+                .enable_ctrlc_handler()
+                .build()
+                .dispatch_with_listener(
+                    listener,
+                    LoggingErrorHandler::with_custom_text("An error from the update listener"),
+                )
+                .await;
+
+            info!("Bot {name} stopped");
+        });
+    }
+
+    if bots.is_empty() {
+        bail!("no bots configured, add a [telegram] or [[bots]] section");
+    }
+
+    if settings.claim_messages {
+        let pool = pool.clone();
+        scheduler.add("prune-claims", schedules.prune_claims, move || {
+            prune_claims(pool.clone())
+        });
+    }
+
+    if let Some(quota) = settings.quota.clone() {
+        let pool = pool.clone();
+        scheduler.add("enforce-quota", schedules.enforce_quota, move || {
+            enforce_quota(pool.clone(), quota.clone())
+        });
+    }
+
+    let after_days = settings.purge_deleted_after_days;
+    scheduler.add("purge-deleted", schedules.purge_deleted, move || {
+        purge_deleted(pool.clone(), after_days)
+    });
+
+    tokio::spawn(scheduler.run());
+
+    while let Some(result) = bots.join_next().await {
+        result?;
+    }
+
+    Ok(())
+}
+
+/// Makes sure the token works and warns about settings that would quietly keep the bot
+/// from ever seeing images.
+async fn self_test(bot: &Bot, name: &str, alerter: Option<&Alerter>) -> Result<()> {
+    let me = bot
+        .get_me()
+        .await
+        .with_context(|| format!("bot {name} couldn't log in, check the token"))?;
+
+    info!("Bot {name} logged in as @{}", me.username());
+
+    if !me.can_read_all_group_messages {
+        let warning = format!(
+            "Privacy mode is enabled for @{username}, so in groups it only sees messages \
+             that reply to or mention it and never gets to check images. Disable it in \
+             @BotFather (/setprivacy), then remove and re-add the bot to existing groups. \
+             Making the bot a group admin works too.",
+            username = me.username()
+        );
+
+        warn!("{warning}");
+        if let Some(alerter) = alerter {
+            alerter.notify(format!("⚠️ {warning}"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds the API client for one bot, with its proxy and API server applied.
+pub fn bot(settings: &TelegramSettings) -> Result<Bot> {
+    let mut bot = match &settings.proxy {
+        Some(proxy) => {
+            let proxy = Proxy::all(proxy).with_context(|| format!("invalid proxy {proxy:?}"))?;
+            let client = teloxide::net::default_reqwest_settings()
+                .proxy(proxy)
+                .build()
+                .context("error building the HTTP client")?;
+
+            Bot::with_client(settings.token.clone(), client)
+        }
+        None => Bot::new(settings.token.clone()),
+    };
+
+    if let Some(url) = &settings.api_url {
+        let url = Url::parse(url).with_context(|| format!("invalid api-url {url:?}"))?;
+        bot = bot.set_api_url(url);
+    }
+
+    Ok(bot)
+}
+
+/// Takes the message for this replica if claiming is enabled, so that of several replicas
+/// receiving the same message (webhooks, or a second token in the same chat) only one replies.
+async fn claim(state: &BotState, msg: &Message) -> bool {
+    let Some(bot_id) = state.claim_as else {
+        return true;
+    };
+
+    let pool = state.detector.matcher().pool();
+    match database::claim_message(pool, bot_id, msg.chat.id.0, msg.id.0).await {
+        Ok(claimed) => {
+            if !claimed {
+                debug!("message {} in {} claimed elsewhere", msg.id, msg.chat.id);
+            }
+            claimed
+        }
+        Err(e) => {
+            // Better a double reply than none at all.
+            error!("Error claiming message: {e}");
+            true
+        }
+    }
 }
 
-pub async fn run(settings: Config, pool: PgPool) -> Result<()> {
-    let bot = Bot::new(settings.telegram.token.clone());
+async fn prune_claims(pool: PgPool) -> Result<()> {
+    let pruned = database::prune_claims(&pool, 24 * 60 * 60).await?;
+    debug!("Pruned {pruned} message claims");
 
-    let state = BotState { pool, settings };
+    Ok(())
+}
 
-    // Define the command handler (or message handler)
-    let handler = Update::filter_message().endpoint(message_handler);
+/// Deleted images can be restored until they're purged here.
+async fn purge_deleted(pool: PgPool, after_days: i64) -> Result<()> {
+    let purged = database::purge_deleted_images(&pool, after_days * 24 * 60 * 60).await?;
+    debug!("Purged {purged} deleted images");
 
-    info!("Bot started...");
+    Ok(())
+}
 
-    Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![state])
-        .enable_ctrlc_handler()
-        .build()
-        .dispatch()
+/// Keeps every chat within `[quota]`.
+async fn enforce_quota(pool: PgPool, quota: QuotaSettings) -> Result<()> {
+    let evicted =
+        database::evict_over_quota(&pool, quota.max_entries_per_chat, quota.eviction).await?;
+    if evicted > 0 {
+        info!("Evicted {evicted} images of chats over their quota");
+        audit::record(
+            &pool,
+            None,
+            None,
+            audit::Action::Forget,
+            json!({ "reason": "quota", "images": evicted }),
+        )
         .await;
+    }
 
     Ok(())
 }
 
+/// The parsed `[schedule]` section.
+struct Schedules {
+    purge_deleted: Schedule,
+    prune_claims: Schedule,
+    stale_check: Schedule,
+    pinned_stats: Schedule,
+    enforce_quota: Schedule,
+}
+
+impl Schedules {
+    fn parse(settings: &ScheduleSettings) -> Result<Self> {
+        let parse = |name: &str, expression: &str| {
+            expression
+                .parse::<Schedule>()
+                .with_context(|| format!("invalid schedule.{name}"))
+        };
+
+        Ok(Self {
+            purge_deleted: parse("purge-deleted", &settings.purge_deleted)?,
+            prune_claims: parse("prune-claims", &settings.prune_claims)?,
+            stale_check: parse("stale-check", &settings.stale_check)?,
+            pinned_stats: parse("pinned-stats", &settings.pinned_stats)?,
+            enforce_quota: parse("enforce-quota", &settings.enforce_quota)?,
+        })
+    }
+}
+
+/// The numeric id in front of the token, which is the bot's user id.
+fn bot_id(token: &str) -> i64 {
+    token
+        .split_once(':')
+        .and_then(|(id, _)| id.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Builds the detector for one bot, everything but the threshold is shared between bots.
+fn detector(settings: &Config, hasher: Arc<Hasher>, matcher: Matcher) -> Result<Detector> {
+    let mut detector =
+        Detector::new(hasher, matcher).with_webhooks(Webhooks::new(settings.webhooks.clone()));
+
+    if let Some(archive) = &settings.archive {
+        detector = detector.with_archive(Archive::new(archive)?);
+    }
+
+    if let Some(threshold) = settings.shadow_similarity_threshold {
+        detector = detector.with_shadow_threshold(threshold);
+    }
+
+    if let Some(verification) = &settings.verification {
+        detector = detector.with_verification(verification.clone());
+    }
+
+    detector = detector.with_repost_handling(settings.own_reposts, settings.young_reposts);
+
+    if settings.delete_double_sends {
+        detector = detector.with_double_send_cleanup();
+    }
+    if settings.aggregate_notices {
+        detector = detector.with_notice_aggregation();
+    }
+    if let Some(bursts) = &settings.burst_summaries {
+        detector =
+            detector.with_burst_summaries(bursts.images, Duration::from_secs(bursts.within_secs));
+    }
+
+    if let Some(path) = &settings.script {
+        detector = detector.with_scripts(Scripts::load(path)?);
+        info!("Loaded script hooks from {}", path.display());
+    }
+
+    Ok(detector)
+}
+
 async fn message_handler(bot: Bot, msg: Message, state: BotState) -> ResponseResult<()> {
-    let chat_id = msg.chat.id.0;
-    let message_id = msg.id.0;
-    let title = msg
-        .chat
-        .title()
-        .or(msg.chat.username())
-        .unwrap_or("<unknown>");
+    if !is_allowed(&state, msg.chat.id) {
+        // Chats the bot was in before they were taken off the list.
+        if !msg.chat.is_private() {
+            leave_unauthorized(&bot, &state, msg.chat.id).await?;
+        }
+        return Ok(());
+    }
+
+    if !claim(&state, &msg).await {
+        return Ok(());
+    }
+
+    if let Some(title) = msg.new_chat_title() {
+        let pool = state.detector.matcher().pool();
+        if let Err(e) = database::set_chat_title(pool, msg.chat.id.0, title).await {
+            error!("Error renaming {}: {e}", msg.chat.id);
+        }
+        return Ok(());
+    }
 
-    if let Some("duplicate?" | "dup?") = msg.text()
-        && let Some(referenced_msg) = msg.reply_to_message()
+    let messenger = &state.messenger;
+
+    if msg.text().is_some_and(is_query)
+        && let Some(image) = replied_image(&msg)
     {
-        let hash = match get_img_hash(&bot, &referenced_msg).await? {
-            Some(x) => x,
-            None => {
-                return Ok(());
+        return match state
+            .detector
+            .query(messenger, message_ref(&msg), image)
+            .await
+        {
+            Ok(_) => {
+                state.alerter.as_ref().inspect(|x| x.db_ok());
+                Ok(())
             }
+            Err(e) => handle_error(&state, &msg, e),
         };
+    }
 
-        return match database::find_closest_match(
-            &state.pool,
-            chat_id,
-            hash,
-            64, // maximum bits in i64
-            Some(referenced_msg.id.0),
-        )
-        .await
-        {
-            Ok(Some(closest_match)) => {
-                bot.send_message(
-                    msg.chat.id,
-                    format!(
-                        "closest match (dst {distance}).\nhttps://t.me/c/{user_chat_id}/{original_msg}",
-                            distance = closest_match.distance,
-                            user_chat_id = convert_telegram_chat_id(chat_id), // gotta convert chat id to user facing so users can click the link
-                            original_msg = closest_match.message_id,
-                    ),
-                )
-                .reply_to(msg.id)
-                .await?;
+    let Some(image) = incoming_image(&msg) else {
+        return Ok(()); // Not an image? Ignore and exit.
+    };
 
+    if state.ignore_bots_in.contains(&msg.chat.id.0) && msg.from.as_ref().is_some_and(|x| x.is_bot)
+    {
+        debug!("Ignoring an image from a bot in {}", msg.chat.id);
+        return Ok(());
+    }
+
+    if whitelisted(&state, &msg).await {
+        debug!(
+            "Ignoring an image from a whitelisted sender in {}",
+            msg.chat.id
+        );
+        return Ok(());
+    }
+
+    if let Some(ForwardOrigin {
+        from_id: Some(from_id),
+        ..
+    }) = image.forward
+        && state.skip_forwards_from.contains(&from_id)
+    {
+        debug!("Ignoring forward from {from_id} in {}", msg.chat.id);
+        return Ok(());
+    }
+
+    match from_linked_channel(&state, &msg, &image).await {
+        Ok(true) => return Ok(()),
+        Ok(false) => (),
+        Err(e) => error!("Database error handling a linked channel post: {e}"),
+    }
+
+    // Asking about an image while sending it, before it gets indexed.
+    if msg.caption().is_some_and(is_query) {
+        return match state
+            .detector
+            .ask(messenger, image, state.index_queried)
+            .await
+        {
+            Ok(_) => {
+                state.alerter.as_ref().inspect(|x| x.db_ok());
                 Ok(())
             }
-            Ok(None) => Ok(()),
-            Err(e) => {
-                error!("Database error: {e}");
-                Ok(())
-            }
+            Err(e) => handle_error(&state, &msg, e),
         };
     }
 
-    let hash = match get_img_hash(&bot, &msg).await? {
-        Some(x) => x,
-        None => {
-            return Ok(());
+    match state.detector.handle(messenger, image).await {
+        Ok(outcome) => {
+            state.alerter.as_ref().inspect(|x| x.db_ok());
+
+            if let (Outcome::Duplicate(closest_match), Some(escalation)) =
+                (outcome, &state.escalation)
+                && state
+                    .detector
+                    .handling(msg.chat.id.0, &closest_match, sender_id(&msg))
+                    .await
+                    .is_ok_and(|x| x == RepostHandling::Flag)
+                && let Err(e) = escalation::enforce(
+                    &messenger.bot,
+                    state.detector.matcher().pool(),
+                    escalation,
+                    &msg,
+                    &closest_match,
+                )
+                .await
+            {
+                error!("Error enforcing the escalation policy: {e:#}");
+            }
+
+            Ok(())
         }
+        Err(e) => handle_error(&state, &msg, e),
+    }
+}
+
+/// Which queue an update is handled in. Updates in the same queue are handled one at a time
+/// in order, so two copies of an image in a chat are never matched at the same time, while
+/// different chats don't wait for each other. A channel post's automatic forward into its
+/// discussion group waits for the post itself, so [`from_linked_channel`] finds it indexed.
+fn chat_queue(update: &Update) -> Option<ChatId> {
+    if let UpdateKind::Message(msg) = &update.kind
+        && msg.is_automatic_forward()
+        && let Some(channel) = &msg.sender_chat
+    {
+        return Some(channel.id);
+    }
+
+    update.chat().map(|x| x.id)
+}
+
+/// Channel posts get forwarded into the channel's discussion group automatically. Links the
+/// two so reposts in the group are matched against the channel, and returns `true` if the
+/// post was already indexed in the channel, so it isn't flagged as a duplicate of itself.
+async fn from_linked_channel(
+    state: &BotState,
+    msg: &Message,
+    image: &IncomingImage<FileId>,
+) -> sqlx::Result<bool> {
+    let Some(channel) = msg
+        .sender_chat
+        .as_ref()
+        .filter(|_| msg.is_automatic_forward())
+    else {
+        return Ok(false);
     };
 
-    let result = match database::find_closest_match(
-        &state.pool,
-        chat_id,
-        hash,
-        state.settings.similarity_threshold,
-        None,
+    let matcher = state.detector.matcher();
+    matcher.link_chats(msg.chat.id.0, channel.id.0).await?;
+
+    let Some(ForwardOrigin {
+        message_id: Some(message_id),
+        ..
+    }) = image.forward
+    else {
+        return Ok(false);
+    };
+
+    Ok(
+        database::image_media(matcher.pool(), channel.id.0, message_id)
+            .await?
+            .is_some(),
     )
-    .await
-    {
-        Ok(x) => x,
-        Err(e) => {
-            error!("Database error: {e}");
-            return Ok(());
-        }
+}
+
+/// Keeps the chat registry up to date as the bot is added to and removed from chats, and
+/// leaves those it isn't allowed in.
+async fn membership_handler(
+    bot: Bot,
+    update: ChatMemberUpdated,
+    state: BotState,
+) -> ResponseResult<()> {
+    let chat = &update.chat;
+    if chat.is_private() {
+        return Ok(());
+    }
+
+    let pool = state.detector.matcher().pool();
+    let result = if !update.new_chat_member.is_present() {
+        info!("Removed from {}", chat.id);
+        database::chat_removed(pool, chat.id.0).await
+    } else if is_allowed(&state, chat.id) {
+        let title = chat.title().unwrap_or_default();
+        info!("Added to {title} ({})", chat.id);
+        database::chat_added(pool, chat.id.0, title, chat_kind(chat)).await
+    } else {
+        return leave_unauthorized(&bot, &state, chat.id).await;
     };
 
-    match result {
-        Some(closest_match) => {
-            bot.send_message(
-                msg.chat.id,
-                format!(
-                    "duplicate image (dst {distance}).\nhttps://t.me/c/{user_chat_id}/{original_msg}",
-                    distance = closest_match.distance,
-                    user_chat_id = convert_telegram_chat_id(chat_id), // gotta convert chat id to user facing so users can click the link
-                    original_msg = closest_match.message_id,
-                ),
+    if let Err(e) = result {
+        error!("Error updating the chat registry for {}: {e}", chat.id);
+    }
+
+    Ok(())
+}
+
+/// How the chat is stored in the registry.
+fn chat_kind(chat: &Chat) -> &'static str {
+    if chat.is_channel() {
+        "channel"
+    } else if chat.is_supergroup() {
+        "supergroup"
+    } else if chat.is_group() {
+        "group"
+    } else {
+        "private"
+    }
+}
+
+/// Whether the bot works in the chat, see `allowed-chats`.
+fn is_allowed(state: &BotState, chat_id: ChatId) -> bool {
+    state.allowed_chats.is_empty() || state.allowed_chats.contains(&chat_id.0)
+}
+
+/// Says why and leaves, deleting what was stored about the chat if configured to.
+async fn leave_unauthorized(bot: &Bot, state: &BotState, chat_id: ChatId) -> ResponseResult<()> {
+    info!("Leaving {chat_id}, it isn't in allowed-chats");
+
+    // Channels only let admins post.
+    if let Err(e) = bot
+        .send_message(
+            chat_id,
+            "This bot only works in the chats its operator allowed, so it's leaving.",
+        )
+        .await
+    {
+        debug!("Couldn't explain leaving {chat_id}: {e}");
+    }
+
+    bot.leave_chat(chat_id).await?;
+
+    if !state.purge_unauthorized {
+        return Ok(());
+    }
+
+    let pool = state.detector.matcher().pool();
+    match database::delete_chat(pool, chat_id.0).await {
+        Ok(0) => (),
+        Ok(images) => {
+            audit::record(
+                pool,
+                Some(chat_id.0),
+                None,
+                audit::Action::Forget,
+                json!({ "reason": "unauthorized", "images": images }),
             )
-            .reply_to(msg.id)
-            .await?;
+            .await;
         }
-        None => {
-            debug!("new image sent to {title} ({chat_id}). adding hash to memory");
+        Err(e) => error!("Error purging {chat_id}: {e}"),
+    }
 
-            match database::save_image(&state.pool, chat_id, title, message_id, hash).await {
-                Ok(()) => (),
-                Err(e) => {
-                    error!("Database error: {e}");
-                    return Ok(());
-                }
-            }
+    Ok(())
+}
+
+async fn callback_handler(bot: Bot, query: CallbackQuery, state: BotState) -> ResponseResult<()> {
+    if let Err(e) = escalation::appeal(&bot, state.detector.matcher().pool(), &query).await {
+        error!("Error handling an appeal: {e:#}");
+    }
+
+    if let Err(e) = settings::press(&bot, &state, &query).await {
+        error!("Error changing a setting: {e:#}");
+    }
+
+    if let Err(e) = search::press(&bot, &state, &query).await {
+        error!("Error forgetting an image: {e:#}");
+    }
+
+    Ok(())
+}
+
+fn is_query(text: &str) -> bool {
+    matches!(text.trim(), "duplicate?" | "dup?")
+}
+
+/// Logs errors with the message they happened on, so they can be traced back from the
+/// error reporting sink too. Telegram errors are still passed on to the dispatcher.
+fn handle_error(
+    state: &BotState,
+    msg: &Message,
+    e: detector::Error<RequestError>,
+) -> ResponseResult<()> {
+    let chat_id = msg.chat.id.0;
+    let message_id = msg.id.0;
+
+    if let Some(alerter) = &state.alerter {
+        match &e {
+            detector::Error::Messenger(_) => alerter.telegram_failed(),
+            detector::Error::Database(_) => alerter.db_failed(),
+            _ => (),
         }
     }
 
+    match e {
+        detector::Error::Messenger(e) => {
+            error!(chat_id, message_id, "Telegram error: {e}");
+            return Err(e);
+        }
+        detector::Error::Hashing(e) => error!(
+            chat_id,
+            message_id,
+            "Error decoding image (msg id: {message_id}) in {title:?} ({chat_id}): {e}",
+            title = msg.chat.title().or(msg.chat.username()),
+        ),
+        detector::Error::Task(e) => error!(chat_id, message_id, "Hashing task failed: {e}"),
+        detector::Error::Database(e) => error!(chat_id, message_id, "Database error: {e}"),
+    }
+
     Ok(())
 }
 
-async fn get_img_hash(bot: &Bot, msg: &Message) -> ResponseResult<Option<i64>> {
-    // Try to extract the file_id
-    let file_id = if let Some(photos) = msg.photo() {
+fn message_ref(msg: &Message) -> MessageRef {
+    MessageRef {
+        chat_id: msg.chat.id.0,
+        message_id: msg.id.0,
+    }
+}
+
+/// The image the message replies to. With Telegram's newer replies that can be a message
+/// of another chat, whose image is then checked against this chat's index as if it had been
+/// sent here.
+fn replied_image(msg: &Message) -> Option<IncomingImage<FileId>> {
+    if let Some(replied) = msg.reply_to_message() {
+        return incoming_image(replied);
+    }
+
+    let MessageKind::Common(common) = &msg.kind else {
+        return None;
+    };
+    let external = common.external_reply.as_ref()?;
+    let (file, alternate) = match &external.kind {
+        ExternalReplyInfoKind::Photo(photos) => (&photos.last()?.file, alternate_of(photos)),
+        ExternalReplyInfoKind::Document(doc)
+            if doc
+                .mime_type
+                .as_ref()
+                .is_some_and(|x| x.type_() == mime::IMAGE) =>
+        {
+            (&doc.file, None)
+        }
+        _ => return None,
+    };
+
+    // Only a message of this chat itself can be indexed here, and left out of the matches.
+    let message_id = match (&external.chat, external.message_id) {
+        (Some(chat), Some(message_id)) if chat.id == msg.chat.id => message_id.0,
+        _ => msg.id.0,
+    };
+
+    Some(IncomingImage {
+        message: MessageRef {
+            chat_id: msg.chat.id.0,
+            message_id,
+        },
+        chat_title: msg
+            .chat
+            .title()
+            .or(msg.chat.username())
+            .unwrap_or("<unknown>")
+            .to_owned(),
+        media: file.id.clone(),
+        alternate: alternate.map(|x| x.id.clone()),
+        media_key: file.unique_id.to_string(),
+        sender_id: forward_origin(&external.origin).from_id,
+        forward: None,
+        spoiler: external.has_media_spoiler,
+        caption: None,
+    })
+}
+
+fn incoming_image(msg: &Message) -> Option<IncomingImage<FileId>> {
+    let file = image_file(msg)?;
+    let title = msg
+        .chat
+        .title()
+        .or(msg.chat.username())
+        .unwrap_or("<unknown>");
+
+    Some(IncomingImage {
+        message: message_ref(msg),
+        chat_title: title.to_owned(),
+        media: file.id.clone(),
+        alternate: alternate_size(msg).map(|x| x.id.clone()),
+        media_key: file.unique_id.to_string(),
+        sender_id: sender_id(msg),
+        forward: msg.forward_origin().map(forward_origin),
+        spoiler: msg.has_media_spoiler(),
+        caption: msg.caption().map(ToOwned::to_owned),
+    })
+}
+
+/// Whether the sender was exempted with /whitelistuser. Errors count as not whitelisted.
+async fn whitelisted(state: &BotState, msg: &Message) -> bool {
+    let Some(sender_id) = sender_id(msg) else {
+        return false;
+    };
+
+    let username = match &msg.sender_chat {
+        Some(chat) => chat.username(),
+        None => msg.from.as_ref().and_then(|x| x.username.as_deref()),
+    };
+
+    let pool = state.detector.matcher().pool();
+    database::is_whitelisted(pool, msg.chat.id.0, sender_id, username)
+        .await
+        .inspect_err(|e| error!("Error checking the whitelist: {e}"))
+        .unwrap_or(false)
+}
+
+/// The chat a message was sent as if any (anonymous admins post as the group itself, channels
+/// as the channel), the user otherwise. Chat ids are negative so the two never collide.
+fn sender_id(msg: &Message) -> Option<i64> {
+    msg.sender_chat
+        .as_ref()
+        .map(|x| x.id.0)
+        .or(msg.from.as_ref().map(|x| x.id.0 as i64))
+}
+
+fn forward_origin(origin: &MessageOrigin) -> ForwardOrigin {
+    match origin {
+        MessageOrigin::User { sender_user, .. } => ForwardOrigin {
+            from_id: Some(sender_user.id.0 as i64),
+            message_id: None,
+        },
+        MessageOrigin::HiddenUser { .. } => ForwardOrigin {
+            from_id: None,
+            message_id: None,
+        },
+        MessageOrigin::Chat { sender_chat, .. } => ForwardOrigin {
+            from_id: Some(sender_chat.id.0),
+            message_id: None,
+        },
+        MessageOrigin::Channel {
+            chat, message_id, ..
+        } => ForwardOrigin {
+            from_id: Some(chat.id.0),
+            message_id: Some(message_id.0),
+        },
+    }
+}
+
+/// Side length of the photo size hashed next to the largest one, Telegram's "m" size.
+const ALTERNATE_SIZE: u32 = 320;
+
+fn alternate_size(msg: &Message) -> Option<&FileMeta> {
+    alternate_of(msg.photo()?)
+}
+
+/// The photo size closest to [`ALTERNATE_SIZE`], unless that's the largest one anyway.
+fn alternate_of(photos: &[PhotoSize]) -> Option<&FileMeta> {
+    let (largest, rest) = photos.split_last()?;
+
+    rest.iter()
+        .min_by_key(|x| x.width.max(x.height).abs_diff(ALTERNATE_SIZE))
+        .filter(|x| x.file.unique_id != largest.file.unique_id)
+        .map(|x| &x.file)
+}
+
+fn image_file(msg: &Message) -> Option<&FileMeta> {
+    if let Some(photos) = msg.photo() {
         // It's a compressed photo (take the largest)
         // We can unwrap safe because the vector is never empty if the field is Some
-        Some(photos.last().unwrap().file.id.clone())
+        Some(&photos.last().unwrap().file)
     } else if let Some(doc) = msg.document() {
         // It's a file/document. Check if it's an image.
         if let Some(mime) = &doc.mime_type {
             if mime.type_() == mime::IMAGE {
-                Some(doc.file.id.clone())
+                Some(&doc.file)
             } else {
                 None // It is a document, but not an image (e.g. PDF)
             }
@@ -159,49 +927,170 @@ async fn get_img_hash(bot: &Bot, msg: &Message) -> ResponseResult<Option<i64>> {
     } else {
         // not photo nor document
         None
-    };
+    }
+}
 
-    let file_id = match file_id {
-        Some(id) => id,
-        None => return Ok(None), // Not an image? Ignore and exit.
-    };
+#[derive(Clone)]
+struct TelegramMessenger {
+    bot: Bot,
+    /// Read files straight from the disk of a local Bot API server.
+    local_files: bool,
+    downloads: DownloadLimiter,
+    download_timeout: Duration,
+    /// Spaces out replies, edits and deletions, per bot as that's what Telegram limits.
+    sends: SendLimiter,
+    max_send_retries: u32,
+}
 
-    debug!("Downloading {file_id}...");
-    let file_info = bot.get_file(file_id).await?;
+impl TelegramMessenger {
+    /// Sends the request once it's its turn, again after a flood limit wears off.
+    async fn send<R: Request<Err = RequestError> + Send + Sync>(
+        &self,
+        chat_id: i64,
+        request: R,
+    ) -> Result<Output<R>, RequestError> {
+        let mut retries = 0;
+        loop {
+            self.sends.wait(chat_id).await;
 
-    let mut image_data = Vec::new();
-    bot.download_file(&file_info.path, &mut image_data).await?;
+            match request.send_ref().await {
+                Err(RequestError::RetryAfter(after)) if retries < self.max_send_retries => {
+                    warn!(
+                        "Flood limited in {chat_id}, sending again in {:?}",
+                        after.duration()
+                    );
+                    self.sends.pause(chat_id, after.duration());
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
 
-    let hash = match calculate_hash(image_data.as_slice()) {
-        Ok(x) => x,
-        Err(e) => {
-            error!(
-                "Error decoding image (msg id: {message_id}) in {title:?} ({chat_id}): {e}",
-                message_id = msg.id.0,
-                title = msg.chat.title().or(msg.chat.username()),
-                chat_id = msg.chat.id.0,
-            );
-            return Ok(None);
+    async fn download_file(&self, file_id: &FileId) -> Result<Vec<u8>, RequestError> {
+        let file_info = self.bot.get_file(file_id.clone()).await?;
+
+        if self.local_files {
+            return tokio::fs::read(&file_info.path)
+                .await
+                .map_err(|e| RequestError::Io(e.into()));
         }
-    };
 
-    Ok(Some(hash))
+        let mut image_data = Vec::new();
+        self.bot
+            .download_file(&file_info.path, &mut image_data)
+            .await?;
+
+        Ok(image_data)
+    }
 }
 
-fn calculate_hash(image: &[u8]) -> Result<i64, image::ImageError> {
-    let image = image::io::Reader::new(Cursor::new(image))
-        .with_guessed_format()?
-        .decode()?;
-    let hasher = HasherConfig::new().to_hasher();
+const NO_LINK_PREVIEW: LinkPreviewOptions = LinkPreviewOptions {
+    is_disabled: true,
+    url: None,
+    prefer_small_media: false,
+    prefer_large_media: false,
+    show_above_text: false,
+};
 
-    let hash = hasher.hash_image(&image);
+impl Messenger for TelegramMessenger {
+    type Media = FileId;
+    type Error = RequestError;
 
-    let Ok(hash): Result<[u8; 8], _> = hash.as_bytes().try_into() else {
-        panic!("Hash was not exactly 8 bytes!");
-    };
-    let hash = i64::from_be_bytes(hash);
+    async fn download(&self, chat_id: i64, file_id: &FileId) -> Result<Vec<u8>, RequestError> {
+        let _permit = self.downloads.acquire(chat_id).await;
+
+        debug!("Downloading {file_id}...");
+        tokio::time::timeout(self.download_timeout, self.download_file(file_id))
+            .await
+            .map_err(|_| {
+                RequestError::Io(Arc::new(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("downloading {file_id} timed out"),
+                )))
+            })?
+    }
+
+    async fn reply(
+        &self,
+        to: MessageRef,
+        text: &str,
+        spoiler: bool,
+    ) -> Result<MessageRef, RequestError> {
+        let mut request = self
+            .bot
+            .send_message(ChatId(to.chat_id), text)
+            .reply_to(MessageId(to.message_id));
+
+        // A preview of the message link would show the image uncovered.
+        if spoiler {
+            request = request.link_preview_options(NO_LINK_PREVIEW);
+        }
+
+        let sent = self.send(to.chat_id, request).await?;
+
+        Ok(MessageRef {
+            chat_id: to.chat_id,
+            message_id: sent.id.0,
+        })
+    }
+
+    async fn edit(
+        &self,
+        message: MessageRef,
+        text: &str,
+        spoiler: bool,
+    ) -> Result<(), RequestError> {
+        let mut request = self.bot.edit_message_text(
+            ChatId(message.chat_id),
+            MessageId(message.message_id),
+            text,
+        );
+        if spoiler {
+            request = request.link_preview_options(NO_LINK_PREVIEW);
+        }
+
+        self.send(message.chat_id, request).await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, message: MessageRef) -> Result<(), RequestError> {
+        let request = self
+            .bot
+            .delete_message(ChatId(message.chat_id), MessageId(message.message_id));
+        self.send(message.chat_id, request).await?;
+
+        Ok(())
+    }
+
+    fn message_link(&self, message: MessageRef) -> Option<String> {
+        message_link(message)
+    }
+
+    fn is_missing_message(error: &RequestError) -> bool {
+        matches!(
+            error,
+            RequestError::Api(
+                ApiError::MessageToReplyNotFound
+                    | ApiError::MessageToForwardNotFound
+                    | ApiError::MessageToCopyNotFound
+            )
+        )
+    }
+}
+
+/// Only supergroups and channels have `t.me/c/` links, basic groups and private chats don't.
+pub fn message_link(message: MessageRef) -> Option<String> {
+    let user_chat_id = convert_telegram_chat_id(message.chat_id); // gotta convert chat id to user facing so users can click the link
+    if user_chat_id == message.chat_id {
+        return None;
+    }
 
-    Ok(hash)
+    Some(format!(
+        "https://t.me/c/{user_chat_id}/{message_id}",
+        message_id = message.message_id,
+    ))
 }
 
 /// Converts a Telegram bot chat ID to its user-facing, positive equivalent
@@ -0,0 +1,105 @@
+use dupfinder_tg::config::AlertSettings;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use tracing::{error, warn};
+
+/// Tells the operator over Telegram when things keep failing, instead of quietly degrading.
+#[derive(Clone)]
+pub struct Alerter {
+    bot: Bot,
+    settings: AlertSettings,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// When the database started failing, reset by the first success.
+    db_failing_since: Option<Instant>,
+    telegram_failures: VecDeque<Instant>,
+    last_db_alert: Option<Instant>,
+    last_telegram_alert: Option<Instant>,
+}
+
+impl Alerter {
+    pub fn new(bot: Bot, settings: AlertSettings) -> Self {
+        Self {
+            bot,
+            settings,
+            state: Default::default(),
+        }
+    }
+
+    pub fn db_ok(&self) {
+        self.state.lock().unwrap().db_failing_since = None;
+    }
+
+    pub fn db_failed(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let since = *state.db_failing_since.get_or_insert(now);
+
+        if now - since < Duration::from_secs(self.settings.db_outage_secs)
+            || !self.cooled_down(state.last_db_alert)
+        {
+            return;
+        }
+
+        state.last_db_alert = Some(now);
+        drop(state);
+
+        self.send(format!(
+            "⚠️ Database has been failing for {} seconds.",
+            (now - since).as_secs()
+        ));
+    }
+
+    pub fn telegram_failed(&self) {
+        let now = Instant::now();
+        let window = Duration::from_secs(60);
+        let mut state = self.state.lock().unwrap();
+
+        state.telegram_failures.push_back(now);
+        while let Some(first) = state.telegram_failures.front()
+            && now - *first > window
+        {
+            state.telegram_failures.pop_front();
+        }
+
+        let failures = state.telegram_failures.len();
+        if failures < self.settings.telegram_failures_per_minute
+            || !self.cooled_down(state.last_telegram_alert)
+        {
+            return;
+        }
+
+        state.last_telegram_alert = Some(now);
+        drop(state);
+
+        self.send(format!(
+            "⚠️ {failures} Telegram API failures (downloads/replies) in the last minute."
+        ));
+    }
+
+    /// Sends a one-off message to the admin chat, bypassing the failure tracking.
+    pub fn notify(&self, text: String) {
+        self.send(text);
+    }
+
+    fn cooled_down(&self, last_alert: Option<Instant>) -> bool {
+        last_alert.is_none_or(|x| x.elapsed() >= Duration::from_secs(self.settings.cooldown_secs))
+    }
+
+    fn send(&self, text: String) {
+        warn!("Alerting admin: {text}");
+
+        let bot = self.bot.clone();
+        let chat_id = ChatId(self.settings.chat_id);
+        tokio::spawn(async move {
+            if let Err(e) = bot.send_message(chat_id, text).await {
+                error!("Couldn't deliver alert: {e}");
+            }
+        });
+    }
+}
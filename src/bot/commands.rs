@@ -0,0 +1,697 @@
+use super::permissions::{self, Role};
+use super::{BotState, escalation, message_ref, replied_image, search, sender_id, settings};
+use dupfinder_tg::audit;
+use dupfinder_tg::database;
+use dupfinder_tg::database::{Reposted, Whitelisted};
+use dupfinder_tg::detector::Explanation;
+use dupfinder_tg::locale::{self, Locale};
+use dupfinder_tg::messenger::{IncomingImage, MessageRef, Messenger};
+use dupfinder_tg::notices;
+use serde_json::{Value, json};
+use sqlx::types::chrono::Utc;
+use teloxide::prelude::*;
+use teloxide::sugar::request::RequestReplyExt;
+use teloxide::types::{FileId, InputFile, MessageId, ParseMode};
+use teloxide::utils::command::BotCommands;
+use teloxide::utils::html;
+use teloxide::{ApiError, RequestError};
+use tracing::{debug, error};
+
+/// Entries in the hall of fame.
+const HALL_OF_FAME_SIZE: i64 = 5;
+
+/// Latest imports listed by /stats.
+const IMPORT_RUNS_SHOWN: i64 = 3;
+
+/// Closest images listed by /why.
+const WHY_CANDIDATES: i64 = 5;
+
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase")]
+pub enum Command {
+    /// Bring back the image an admin forgot last
+    Undo,
+    /// How many of your images were originals and how many reposts
+    MyStats,
+    /// The most reposted images of this chat
+    HallOfFame,
+    /// Index size, duplicates caught and recent imports of this chat
+    Stats,
+    /// Reply to someone's message to lift their restriction
+    Unmute,
+    /// Stop indexing and flagging images of @name, or of the replied to sender
+    WhitelistUser(String),
+    /// Undo /whitelistuser
+    UnwhitelistUser(String),
+    /// Set the timezone dates are shown in, e.g. Europe/Prague, or "reset"
+    Timezone(String),
+    /// Set the date format by locale, e.g. en-US or de, or "reset"
+    Locale(String),
+    /// Let reposts of images younger than this pass, e.g. 10m or 2h, or "off"
+    MinAge(String),
+    /// Reply to a duplicate notice or an image to get the details of its matching by DM
+    Why,
+    /// Change the threshold, silent mode, enforcement, notice cleanup and dates from a menu
+    Settings,
+    /// Find indexed images by the text sent along with them
+    Search(String),
+}
+
+impl Command {
+    /// The least privileged role allowed to use the command.
+    fn role(&self) -> Role {
+        match self {
+            Command::MyStats | Command::HallOfFame | Command::Stats => Role::Member,
+            Command::Undo
+            | Command::Unmute
+            | Command::WhitelistUser(_)
+            | Command::UnwhitelistUser(_)
+            | Command::Timezone(_)
+            | Command::Locale(_)
+            | Command::MinAge(_)
+            | Command::Why
+            | Command::Settings
+            | Command::Search(_) => Role::Admin,
+        }
+    }
+}
+
+pub async fn handle(
+    bot: Bot,
+    msg: Message,
+    command: Command,
+    state: BotState,
+) -> ResponseResult<()> {
+    if !state.allowed_chats.is_empty() && !state.allowed_chats.contains(&msg.chat.id.0) {
+        return Ok(());
+    }
+
+    let required = command.role();
+    if required > Role::Member && permissions::of_sender(&bot, &state, &msg).await? < required {
+        bot.send_message(msg.chat.id, required.denied())
+            .reply_to(msg.id)
+            .await?;
+        return Ok(());
+    }
+
+    let text = match command {
+        Command::Undo => undo(&msg, &state).await,
+        Command::MyStats => my_stats(&msg, &state).await,
+        Command::HallOfFame => return hall_of_fame(&bot, &msg, &state).await,
+        Command::Stats => stats(&msg, &state).await,
+        Command::Unmute => unmute(&bot, &msg, &state).await?,
+        Command::WhitelistUser(target) => whitelist(&msg, &state, &target, true).await,
+        Command::UnwhitelistUser(target) => whitelist(&msg, &state, &target, false).await,
+        Command::Timezone(timezone) => set_timezone(&msg, &state, &timezone).await,
+        Command::Locale(locale) => set_locale(&msg, &state, &locale).await,
+        Command::MinAge(age) => set_min_age(&msg, &state, &age).await,
+        Command::Why => why(&bot, &msg, &state).await?,
+        Command::Settings => return open_settings(&bot, &msg, &state).await,
+        Command::Search(text) => return search(&bot, &msg, &state, &text).await,
+    };
+
+    bot.send_message(msg.chat.id, text).reply_to(msg.id).await?;
+
+    Ok(())
+}
+
+async fn undo(msg: &Message, state: &BotState) -> String {
+    let restored = state
+        .detector
+        .matcher()
+        .undo_last_delete(msg.chat.id.0)
+        .await;
+
+    match restored {
+        Ok(Some(message_id)) => {
+            audit::record(
+                state.detector.matcher().pool(),
+                Some(msg.chat.id.0),
+                sender_id(msg),
+                audit::Action::Restore,
+                json!({ "message_id": message_id }),
+            )
+            .await;
+            format!("Restored the image of message {message_id}.")
+        }
+        Ok(None) => "Nothing to undo.".to_owned(),
+        Err(e) => database_error(state, e),
+    }
+}
+
+async fn unmute(bot: &Bot, msg: &Message, state: &BotState) -> ResponseResult<String> {
+    let Some(user) = msg.reply_to_message().and_then(|x| x.from.as_ref()) else {
+        return Ok("Reply to a message of the user to unmute.".to_owned());
+    };
+
+    let pool = state.detector.matcher().pool();
+    Ok(match escalation::lift(bot, pool, msg, user.id).await {
+        Ok(()) => format!("Unmuted {}.", user.full_name()),
+        Err(e) => {
+            error!("Error lifting a restriction: {e:#}");
+            "Couldn't unmute them, does the bot have the rights to?".to_owned()
+        }
+    })
+}
+
+async fn whitelist(msg: &Message, state: &BotState, target: &str, add: bool) -> String {
+    let Some((who, name)) = whitelist_target(msg, target) else {
+        return "Reply to a message of the user, or give their @name or id.".to_owned();
+    };
+
+    let pool = state.detector.matcher().pool();
+    let chat_id = msg.chat.id.0;
+    let change = json!({ "whitelist": name, "added": add });
+    if add {
+        match database::whitelist_sender(pool, chat_id, &who, sender_id(msg)).await {
+            Ok(()) => {
+                audit_setting(state, msg, change).await;
+                format!("Images from {name} won't be indexed or flagged anymore.")
+            }
+            Err(e) => database_error(state, e),
+        }
+    } else {
+        match database::unwhitelist_sender(pool, chat_id, &who).await {
+            Ok(true) => {
+                audit_setting(state, msg, change).await;
+                format!("Images from {name} will be checked again.")
+            }
+            Ok(false) => format!("{name} wasn't whitelisted."),
+            Err(e) => database_error(state, e),
+        }
+    }
+}
+
+/// The sender of the replied to message if no argument is given, otherwise a @name or an id.
+/// The Bot API has no way of looking up users by name, so those are stored as they are.
+fn whitelist_target(msg: &Message, target: &str) -> Option<(Whitelisted, String)> {
+    let target = target.trim();
+    if target.is_empty() {
+        let reply = msg.reply_to_message()?;
+        let name = match (&reply.sender_chat, &reply.from) {
+            (Some(chat), _) => chat.title().unwrap_or("that channel").to_owned(),
+            (None, Some(user)) => user.full_name(),
+            (None, None) => return None,
+        };
+
+        return Some((Whitelisted::Sender(sender_id(reply)?), name));
+    }
+
+    if let Ok(id) = target.parse() {
+        return Some((Whitelisted::Sender(id), target.to_owned()));
+    }
+
+    let username = target.strip_prefix('@').unwrap_or(target);
+    if username.is_empty()
+        || !username
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || x == '_')
+    {
+        return None;
+    }
+
+    Some((
+        Whitelisted::Username(username.to_ascii_lowercase()),
+        format!("@{username}"),
+    ))
+}
+
+async fn set_timezone(msg: &Message, state: &BotState, timezone: &str) -> String {
+    let timezone = match timezone.trim() {
+        "" => return "Give a timezone like Europe/Prague, or \"reset\" for UTC.".to_owned(),
+        "reset" => None,
+        timezone => match locale::parse_timezone(timezone) {
+            Some(timezone) => Some(timezone.name().to_owned()),
+            None => return format!("Unknown timezone {timezone}."),
+        },
+    };
+
+    let pool = state.detector.matcher().pool();
+    let chat_id = msg.chat.id.0;
+    match database::set_chat_locale(pool, chat_id, Some(timezone.as_deref()), None).await {
+        Ok(()) => {
+            audit_setting(state, msg, json!({ "timezone": timezone })).await;
+            format!(
+                "Dates are now shown in {}.",
+                timezone.as_deref().unwrap_or("UTC")
+            )
+        }
+        Err(e) => database_error(state, e),
+    }
+}
+
+async fn set_locale(msg: &Message, state: &BotState, locale: &str) -> String {
+    let locale = match locale.trim() {
+        "" => return "Give a locale like en-US or de, or \"reset\".".to_owned(),
+        "reset" => None,
+        locale => match locale::parse_locale(locale) {
+            Some(locale) => Some(locale),
+            None => return format!("{locale} doesn't look like a locale."),
+        },
+    };
+
+    let pool = state.detector.matcher().pool();
+    let chat_id = msg.chat.id.0;
+    match database::set_chat_locale(pool, chat_id, None, Some(locale.as_deref())).await {
+        Ok(()) => {
+            audit_setting(state, msg, json!({ "locale": locale })).await;
+            let example = Locale::new(None, locale.as_deref()).format_date(Utc::now());
+            format!("Dates now look like {example}.")
+        }
+        Err(e) => database_error(state, e),
+    }
+}
+
+async fn set_min_age(msg: &Message, state: &BotState, age: &str) -> String {
+    let secs = match age.trim() {
+        "" => return "Give an age like 30s, 10m or 2h, or \"off\".".to_owned(),
+        "off" => None,
+        age => match parse_age(age) {
+            Some(secs) => Some(secs),
+            None => return format!("{age} doesn't look like an age."),
+        },
+    };
+
+    let pool = state.detector.matcher().pool();
+    match database::set_chat_min_repost_age(pool, msg.chat.id.0, secs).await {
+        Ok(()) => {
+            audit_setting(state, msg, json!({ "min_repost_age_secs": secs })).await;
+            match secs {
+                Some(_) => format!("Reposts of images younger than {} now pass.", age.trim()),
+                None => "Reposts are flagged however young the original.".to_owned(),
+            }
+        }
+        Err(e) => database_error(state, e),
+    }
+}
+
+/// Seconds in an age like `90s`, `10m`, `2h` or `1d`, minutes without a unit.
+fn parse_age(age: &str) -> Option<i32> {
+    let (number, unit) = match age.find(|x: char| !x.is_ascii_digit()) {
+        Some(i) => age.split_at(i),
+        None => (age, "m"),
+    };
+
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+
+    number.parse::<i32>().ok()?.checked_mul(unit)
+}
+
+async fn why(bot: &Bot, msg: &Message, state: &BotState) -> ResponseResult<String> {
+    let image = match (replied_image(msg), msg.reply_to_message()) {
+        (Some(image), _) => Some(image),
+        (None, Some(notice)) => flagged_image(state, notice).await,
+        (None, None) => None,
+    };
+    let Some(image) = image else {
+        return Ok("Reply to a duplicate notice or an image.".to_owned());
+    };
+
+    let Some(admin) = &msg.from else {
+        return Ok("Couldn't tell who you are.".to_owned());
+    };
+
+    let explanation = match state
+        .detector
+        .explain(&state.messenger, &image, WHY_CANDIDATES)
+        .await
+    {
+        Ok(explanation) => explanation,
+        Err(e) => {
+            error!("Error explaining a match: {e}");
+            return Ok("Couldn't look into that image, try again later.".to_owned());
+        }
+    };
+
+    let text = explain(state, &explanation);
+    if let Err(e) = bot
+        .send_message(admin.id, text)
+        .parse_mode(ParseMode::Html)
+        .await
+    {
+        debug!("Couldn't DM {}: {e}", admin.id);
+        return Ok("I can't message you, start a chat with me first.".to_owned());
+    }
+
+    // Where the closest image differs, if both images can still be had.
+    if let Some(closest) = explanation.candidates.first() {
+        let original = MessageRef {
+            chat_id: closest.chat_id,
+            message_id: closest.message_id,
+        };
+
+        match state
+            .detector
+            .heatmap(&state.messenger, &image, original)
+            .await
+        {
+            Ok(Some(png)) => {
+                bot.send_photo(admin.id, InputFile::memory(png))
+                    .caption(format!(
+                        "Where it differs from message {}, brighter is more different.",
+                        original.message_id
+                    ))
+                    .await?;
+            }
+            Ok(None) => (),
+            Err(e) => error!("Error rendering a heatmap: {e}"),
+        }
+    }
+
+    Ok("Sent you the details.".to_owned())
+}
+
+/// The repost a duplicate notice is about. Telegram doesn't say what a replied message
+/// itself replies to, and notices reply to the original where it can't be linked to, so
+/// it's looked up among the bot's notices.
+async fn flagged_image(state: &BotState, notice: &Message) -> Option<IncomingImage<FileId>> {
+    let pool = state.detector.matcher().pool();
+    let flagged = match notices::flagged(pool, message_ref(notice)).await {
+        Ok(flagged) => flagged?,
+        Err(e) => {
+            error!("Error looking up a notice: {e}");
+            return None;
+        }
+    };
+
+    Some(IncomingImage {
+        message: MessageRef {
+            chat_id: notice.chat.id.0,
+            message_id: flagged.message_id,
+        },
+        chat_title: notice.chat.title().unwrap_or_default().to_owned(),
+        media: FileId(flagged.media_ref?),
+        alternate: None,
+        media_key: flagged.media_key.unwrap_or_default(),
+        sender_id: flagged.sender_id,
+        forward: None,
+        spoiler: false,
+        caption: None,
+    })
+}
+
+/// The hashes involved, the closest images and how their hashes differ, in HTML.
+fn explain(state: &BotState, explanation: &Explanation) -> String {
+    let hasher = state.detector.hasher();
+    let matcher = state.detector.matcher();
+    let bits = hasher.bits();
+
+    let mut text = format!(
+        "<b>{algorithm:?}</b> hashes of {bits} bits, threshold {threshold} ({similarity:.0}% similar)\n\
+         Image: <code>{hash:016x}</code>",
+        algorithm = hasher.algorithm(),
+        threshold = explanation.threshold,
+        similarity = matcher.similarity(explanation.threshold),
+        hash = explanation.hash,
+    );
+    if let Some(alt_hash) = explanation.alt_hash {
+        text.push_str(&format!(", alternate <code>{alt_hash:016x}</code>"));
+    }
+    if hasher.is_low_entropy(explanation.hash) && !matcher.matches_low_entropy() {
+        text.push_str("\nLow-entropy, so it isn't matched at all.");
+    }
+
+    if explanation.candidates.is_empty() {
+        text.push_str("\n\nNothing indexed to compare against.");
+    }
+
+    for (i, candidate) in explanation.candidates.iter().enumerate() {
+        let message = MessageRef {
+            chat_id: candidate.chat_id,
+            message_id: candidate.message_id,
+        };
+        let name = match state.messenger.message_link(message) {
+            Some(link) => format!(
+                "<a href=\"{}\">message {}</a>",
+                html::escape(&link),
+                message.message_id
+            ),
+            None => format!("message {}", message.message_id),
+        };
+        let verdict = match (
+            candidate.low_entropy,
+            candidate.distance <= explanation.threshold,
+        ) {
+            (true, _) if !matcher.matches_low_entropy() => "low-entropy, not matched",
+            (_, true) => "a match",
+            (_, false) => "over the threshold",
+        };
+
+        text.push_str(&format!(
+            "\n\n{n}. {name}: distance {distance} ({similarity:.0}% similar), {verdict}\n\
+             <code>{phash:016x}</code>\n<pre>{diff}</pre>",
+            n = i + 1,
+            distance = candidate.distance,
+            similarity = matcher.similarity(candidate.distance),
+            phash = candidate.phash,
+            diff = bit_diff(explanation.hash, candidate.phash, bits),
+        ));
+    }
+
+    text
+}
+
+/// The differing bits of two hashes as a grid, 8 bits to a row from the most significant.
+fn bit_diff(a: i64, b: i64, bits: u32) -> String {
+    let diff = (a ^ b) as u64;
+    (0..bits)
+        .map(|i| {
+            if diff & (1 << (63 - i)) != 0 {
+                '█'
+            } else {
+                '·'
+            }
+        })
+        .collect::<Vec<_>>()
+        .chunks(8)
+        .map(|row| row.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn my_stats(msg: &Message, state: &BotState) -> String {
+    let Some(sender_id) = sender_id(msg) else {
+        return "Couldn't tell who you are.".to_owned();
+    };
+
+    let pool = state.detector.matcher().pool();
+    let stats = match database::sender_stats(pool, msg.chat.id.0, sender_id).await {
+        Ok(stats) => stats,
+        Err(e) => return database_error(state, e),
+    };
+
+    let posted = stats.originals + stats.reposts;
+    if posted == 0 {
+        return "You haven't posted any images here yet.".to_owned();
+    }
+
+    format!(
+        "You posted {posted} images here, {reposts} of them reposts.\nOriginality score: {score:.0}%",
+        reposts = stats.reposts,
+        score = stats.originals as f64 / posted as f64 * 100.0,
+    )
+}
+
+async fn stats(msg: &Message, state: &BotState) -> String {
+    let chat_id = msg.chat.id.0;
+    let matcher = state.detector.matcher();
+    let pool = matcher.pool();
+
+    let result = async {
+        let chat = database::chat_stats(pool)
+            .await?
+            .into_iter()
+            .find(|x| x.id == chat_id);
+        let imports = database::import_runs(pool, Some(chat_id), IMPORT_RUNS_SHOWN).await?;
+        let tables = database::table_stats(pool).await?;
+        let locale = matcher.locale(chat_id).await?;
+
+        sqlx::Result::Ok((chat, imports, tables, locale))
+    }
+    .await;
+
+    let (chat, imports, tables, locale) = match result {
+        Ok(result) => result,
+        Err(e) => return database_error(state, e),
+    };
+
+    let Some(chat) = chat else {
+        return "Nothing has been indexed here yet.".to_owned();
+    };
+
+    let mut text = format!(
+        "📊 {} images indexed, {} duplicates caught.",
+        chat.images, chat.sightings
+    );
+
+    if let (Some(oldest), Some(newest)) = (chat.oldest, chat.newest) {
+        text.push_str(&format!(
+            "\nOldest from {}, newest from {}.",
+            locale.format_date(oldest),
+            locale.format_date(newest)
+        ));
+    }
+
+    let bytes = tables.iter().map(|x| x.total_bytes).sum::<i64>();
+    let dead_rows = tables.iter().map(|x| x.dead_rows).sum::<i64>();
+    text.push_str(&format!(
+        "\nDatabase: {:.1} MB across all chats, {dead_rows} dead rows awaiting vacuum.",
+        bytes as f64 / 1e6
+    ));
+
+    for run in imports {
+        let outcome = match (&run.finished_at, &run.error) {
+            (None, _) => "running or interrupted".to_owned(),
+            (Some(_), Some(error)) => format!("failed: {error}"),
+            (Some(finished_at), None) => {
+                format!("took {}s", (*finished_at - run.started_at).num_seconds())
+            }
+        };
+
+        text.push_str(&format!(
+            "\nImport of {date}: {hashed} hashed, {failed} failed, {skipped} skipped, {outcome}",
+            date = locale.format_date(run.started_at),
+            hashed = run.hashed,
+            failed = run.failed,
+            skipped = run.skipped,
+        ));
+    }
+
+    text
+}
+
+/// Sends the settings menu, see [`settings::menu`].
+async fn open_settings(bot: &Bot, msg: &Message, state: &BotState) -> ResponseResult<()> {
+    let text = match settings::menu(state, msg.chat.id.0).await {
+        Ok(Some((text, keyboard))) => {
+            bot.send_message(msg.chat.id, text)
+                .reply_markup(keyboard)
+                .reply_to(msg.id)
+                .await?;
+            return Ok(());
+        }
+        Ok(None) => "Nothing has been indexed here yet, so there's nothing to set.".to_owned(),
+        Err(e) => database_error(state, e),
+    };
+
+    bot.send_message(msg.chat.id, text).reply_to(msg.id).await?;
+
+    Ok(())
+}
+
+/// Sends the images found by their caption, see [`search::results`].
+async fn search(bot: &Bot, msg: &Message, state: &BotState, text: &str) -> ResponseResult<()> {
+    let text = match text.trim() {
+        "" => "Give some text to look for in captions.".to_owned(),
+        text => match search::results(state, msg.chat.id.0, text).await {
+            Ok(Some((results, keyboard))) => {
+                bot.send_message(msg.chat.id, results)
+                    .parse_mode(ParseMode::Html)
+                    .reply_markup(keyboard)
+                    .reply_to(msg.id)
+                    .await?;
+                return Ok(());
+            }
+            Ok(None) => format!("No indexed image here has “{text}” in its caption."),
+            Err(e) => database_error(state, e),
+        },
+    };
+
+    bot.send_message(msg.chat.id, text).reply_to(msg.id).await?;
+
+    Ok(())
+}
+
+/// Posts the most reposted images, each as a forward of the original or, if that's gone,
+/// the archived copy.
+async fn hall_of_fame(bot: &Bot, msg: &Message, state: &BotState) -> ResponseResult<()> {
+    let pool = state.detector.matcher().pool();
+    let text = match database::most_reposted(pool, msg.chat.id.0, HALL_OF_FAME_SIZE).await {
+        Ok(entries) if entries.is_empty() => "Nothing has been reposted here yet.".to_owned(),
+        Ok(entries) => {
+            bot.send_message(msg.chat.id, "🏆 Most reposted images")
+                .reply_to(msg.id)
+                .await?;
+
+            for (rank, entry) in entries.iter().enumerate() {
+                hall_of_fame_entry(bot, msg.chat.id, state, rank + 1, entry).await?;
+            }
+
+            return Ok(());
+        }
+        Err(e) => database_error(state, e),
+    };
+
+    bot.send_message(msg.chat.id, text).reply_to(msg.id).await?;
+
+    Ok(())
+}
+
+async fn hall_of_fame_entry(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &BotState,
+    rank: usize,
+    entry: &Reposted,
+) -> ResponseResult<()> {
+    let caption = format!("#{rank}: reposted {} times", entry.reposts);
+
+    bot.send_message(chat_id, &caption).await?;
+    match bot
+        .forward_message(chat_id, ChatId(entry.chat_id), MessageId(entry.message_id))
+        .await
+    {
+        Ok(_) => return Ok(()),
+        Err(RequestError::Api(ApiError::MessageToForwardNotFound)) => {
+            let original = MessageRef {
+                chat_id: entry.chat_id,
+                message_id: entry.message_id,
+            };
+            if let Err(e) = state.detector.matcher().mark_stale(original).await {
+                error!("Error marking {} stale: {e}", entry.message_id);
+            }
+        }
+        Err(e) => debug!("Couldn't forward {}: {e}", entry.message_id),
+    }
+
+    let (Some(archive), Some(key)) = (state.detector.archive(), &entry.media_key) else {
+        return Ok(());
+    };
+
+    match archive.load(key).await {
+        Ok(Some(data)) => {
+            bot.send_photo(chat_id, InputFile::memory(data))
+                .has_spoiler(entry.spoiler)
+                .await?;
+        }
+        Ok(None) => (),
+        Err(e) => error!("Error loading {key} from the archive: {e}"),
+    }
+
+    Ok(())
+}
+
+/// Records an admin's change of one of the chat's settings in the audit log.
+async fn audit_setting(state: &BotState, msg: &Message, change: Value) {
+    audit::record(
+        state.detector.matcher().pool(),
+        Some(msg.chat.id.0),
+        sender_id(msg),
+        audit::Action::Setting,
+        change,
+    )
+    .await;
+}
+
+pub fn database_error(state: &BotState, e: sqlx::Error) -> String {
+    error!("Database error handling a command: {e}");
+    state.alerter.as_ref().inspect(|x| x.db_failed());
+
+    "Something went wrong, try again later.".to_owned()
+}
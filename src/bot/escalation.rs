@@ -0,0 +1,267 @@
+use super::{message_link, message_ref, sender_id};
+use anyhow::Result;
+use chrono::{TimeDelta, Utc};
+use dupfinder_tg::audit;
+use dupfinder_tg::config::{EnforcementAction, EscalationSettings};
+use dupfinder_tg::database::{self, ClosestMatch, EnforcementRecord};
+use dupfinder_tg::messenger::MessageRef;
+use dupfinder_tg::notices;
+use serde_json::json;
+use sqlx::PgPool;
+use sqlx::types::Uuid;
+use teloxide::prelude::*;
+use teloxide::types::{ChatPermissions, InlineKeyboardButton, InlineKeyboardMarkup};
+use tracing::{debug, info, warn};
+
+/// Callback data of the appeal button, followed by the enforcement action's id.
+const APPEAL_PREFIX: &str = "appeal:";
+
+/// Applies the escalation policy to a message that was just flagged as a repost, once its
+/// sender has reposted often enough.
+pub async fn enforce(
+    bot: &Bot,
+    pool: &PgPool,
+    settings: &EscalationSettings,
+    msg: &Message,
+    closest_match: &ClosestMatch,
+) -> Result<()> {
+    let Some(sender_id) = sender_id(msg) else {
+        return Ok(());
+    };
+
+    let chat_id = msg.chat.id.0;
+    // Switched off in /settings.
+    if database::chat_settings(pool, chat_id)
+        .await?
+        .is_some_and(|x| !x.enforce)
+    {
+        return Ok(());
+    }
+
+    let window_secs = settings.window_days * 24 * 60 * 60;
+    let reposts = database::recent_reposts(pool, chat_id, sender_id, window_secs).await?;
+
+    let Some(step) = settings.step(chat_id, reposts) else {
+        return Ok(());
+    };
+
+    bot.delete_message(msg.chat.id, msg.id).await?;
+    notices::repost_gone(pool, message_ref(msg)).await?;
+    audit::record(
+        pool,
+        Some(chat_id),
+        None,
+        audit::Action::Delete,
+        json!({
+            "message_id": msg.id.0,
+            "sender_id": sender_id,
+            "reason": "escalation",
+            "reposts": reposts,
+            "original_chat_id": closest_match.chat_id,
+            "original_message_id": closest_match.message_id,
+            "distance": closest_match.distance,
+        }),
+    )
+    .await;
+
+    // Posts sent as a chat can't be restricted, only deleted.
+    let mut restricted_until = None;
+    if let EnforcementAction::Restrict { hours } = step.action
+        && msg.sender_chat.is_none()
+        && let Some(user) = &msg.from
+        && can_restrict(bot, msg.chat.id, user.id).await?
+    {
+        let until = Utc::now() + TimeDelta::hours(hours.into());
+        bot.restrict_chat_member(msg.chat.id, user.id, ChatPermissions::empty())
+            .until_date(until)
+            .await?;
+
+        restricted_until = Some(until);
+        audit::record(
+            pool,
+            Some(chat_id),
+            None,
+            audit::Action::Restrict,
+            json!({ "user_id": user.id.0, "until": until.to_rfc3339(), "reposts": reposts }),
+        )
+        .await;
+    }
+
+    info!(
+        chat_id,
+        sender_id,
+        message_id = msg.id.0,
+        reposts,
+        action = step.action.name(),
+        "enforcement action taken"
+    );
+
+    let id = database::save_enforcement(
+        pool,
+        &EnforcementRecord {
+            chat_id,
+            sender_id,
+            message_id: msg.id.0,
+            action: step.action.name(),
+            reposts: Some(reposts),
+            restricted_until,
+            actor_id: None,
+            original: Some((closest_match.message(), closest_match.distance)),
+        },
+    )
+    .await?;
+
+    let what = match restricted_until {
+        Some(until) => format!(
+            "deleted and muted until {}",
+            until.format("%Y-%m-%d %H:%M UTC")
+        ),
+        None => "deleted".to_owned(),
+    };
+    let appeal = InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        "Appeal",
+        format!("{APPEAL_PREFIX}{id}"),
+    )]]);
+
+    bot.send_message(
+        msg.chat.id,
+        format!(
+            "Repost #{reposts} of the last {} days {what}.",
+            settings.window_days
+        ),
+    )
+    .reply_markup(appeal)
+    .await?;
+
+    Ok(())
+}
+
+/// Handles a press of the appeal button: sends the evidence to the chat's admins. Only the
+/// sender the action was taken against can appeal, and only once.
+pub async fn appeal(bot: &Bot, pool: &PgPool, query: &CallbackQuery) -> Result<()> {
+    let Some(id) = query
+        .data
+        .as_deref()
+        .and_then(|x| x.strip_prefix(APPEAL_PREFIX))
+        .and_then(|x| Uuid::parse_str(x).ok())
+    else {
+        return Ok(());
+    };
+
+    let Some(appeal) = database::appeal(pool, id, query.from.id.0 as i64).await? else {
+        bot.answer_callback_query(query.id.clone())
+            .text("Only the sender can appeal, and only once.")
+            .await?;
+        return Ok(());
+    };
+
+    let chat_id = ChatId(appeal.chat_id);
+    let original = match (appeal.original_chat_id, appeal.original_message_id) {
+        (Some(chat_id), Some(message_id)) => message_link(MessageRef {
+            chat_id,
+            message_id,
+        })
+        .unwrap_or_else(|| format!("message {message_id}")),
+        _ => "unknown".to_owned(),
+    };
+    let evidence = format!(
+        "⚖️ {name} appeals the {action} of their message {message_id} in {title}.\n\
+         Original: {original} (dst {distance})\n\
+         Reposts in the window: {reposts}, enforcement actions so far: {enforcements}",
+        name = query.from.full_name(),
+        action = appeal.action,
+        message_id = appeal.message_id,
+        title = query
+            .message
+            .as_ref()
+            .and_then(|x| x.chat().title())
+            .unwrap_or("the chat"),
+        distance = appeal.distance.map_or("?".to_owned(), |x| x.to_string()),
+        reposts = appeal.reposts.map_or("?".to_owned(), |x| x.to_string()),
+        enforcements = appeal.enforcements,
+    );
+
+    // Admins only get messages if they've started the bot before.
+    let mut notified = 0;
+    for admin in bot.get_chat_administrators(chat_id).await? {
+        if admin.user.is_bot {
+            continue;
+        }
+
+        match bot.send_message(admin.user.id, &evidence).await {
+            Ok(_) => notified += 1,
+            Err(e) => debug!("Couldn't send the appeal to admin {}: {e}", admin.user.id),
+        }
+    }
+
+    // Nobody reachable privately, ask in the chat itself.
+    if notified == 0 {
+        bot.send_message(chat_id, format!("{evidence}\nAdmins, please take a look."))
+            .await?;
+    }
+
+    bot.answer_callback_query(query.id.clone())
+        .text("Your appeal was sent to the admins.")
+        .await?;
+
+    Ok(())
+}
+
+/// Whether the bot has the rights to restrict the user, who mustn't be an admin.
+async fn can_restrict(bot: &Bot, chat_id: ChatId, user_id: UserId) -> Result<bool> {
+    let me = bot.get_me().await?;
+    if !bot
+        .get_chat_member(chat_id, me.id)
+        .await?
+        .can_restrict_members()
+    {
+        warn!("Can't restrict reposters in {chat_id}, the bot isn't allowed to restrict members");
+        return Ok(false);
+    }
+
+    if bot.get_chat_member(chat_id, user_id).await?.is_privileged() {
+        info!("Not restricting {user_id} in {chat_id}, they're an admin");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Lifts a restriction, on an admin's request, and records who did it.
+pub async fn lift(bot: &Bot, pool: &PgPool, msg: &Message, user_id: UserId) -> Result<()> {
+    bot.restrict_chat_member(msg.chat.id, user_id, ChatPermissions::all())
+        .await?;
+
+    let actor_id = msg.from.as_ref().map(|x| x.id.0 as i64);
+    info!(
+        chat_id = msg.chat.id.0,
+        user_id = user_id.0,
+        actor_id,
+        "restriction lifted"
+    );
+    audit::record(
+        pool,
+        Some(msg.chat.id.0),
+        actor_id,
+        audit::Action::Unrestrict,
+        json!({ "user_id": user_id.0 }),
+    )
+    .await;
+
+    database::save_enforcement(
+        pool,
+        &EnforcementRecord {
+            chat_id: msg.chat.id.0,
+            sender_id: user_id.0 as i64,
+            message_id: msg.id.0,
+            action: "unrestrict",
+            reposts: None,
+            restricted_until: None,
+            actor_id,
+            original: None,
+        },
+    )
+    .await?;
+
+    Ok(())
+}
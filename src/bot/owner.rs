@@ -0,0 +1,160 @@
+use super::BotState;
+use super::commands::database_error;
+use super::permissions::{self, Role};
+use dupfinder_tg::database;
+use teloxide::prelude::*;
+use teloxide::sugar::request::RequestReplyExt;
+use teloxide::utils::command::BotCommands;
+use tracing::info;
+
+/// Telegram rejects longer messages.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Commands owners can DM the bot to look after every chat it's in.
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase")]
+pub enum OwnerCommand {
+    /// Every indexed chat with its image and duplicate counts
+    Chats,
+    /// Leave a chat by id
+    Leave(String),
+    /// Totals over every chat and the size of the database
+    GlobalStats,
+    /// Send a message to the alert chat
+    Announce(String),
+}
+
+pub async fn handle(
+    bot: Bot,
+    msg: Message,
+    command: OwnerCommand,
+    state: BotState,
+) -> ResponseResult<()> {
+    if permissions::of_sender(&bot, &state, &msg).await? < Role::Owner {
+        bot.send_message(msg.chat.id, Role::Owner.denied())
+            .reply_to(msg.id)
+            .await?;
+        return Ok(());
+    }
+
+    let text = match command {
+        OwnerCommand::Chats => chats(&state).await,
+        OwnerCommand::Leave(chat) => leave(&bot, &chat).await,
+        OwnerCommand::GlobalStats => global_stats(&state).await,
+        OwnerCommand::Announce(text) => announce(&state, &text),
+    };
+
+    for chunk in chunks(&text) {
+        bot.send_message(msg.chat.id, chunk).await?;
+    }
+
+    Ok(())
+}
+
+async fn chats(state: &BotState) -> String {
+    let pool = state.detector.matcher().pool();
+    let chats = match database::chat_stats(pool).await {
+        Ok(chats) => chats,
+        Err(e) => return database_error(state, e),
+    };
+
+    if chats.is_empty() {
+        return "Nothing has been indexed yet.".to_owned();
+    }
+
+    chats
+        .iter()
+        .map(|x| {
+            let mut line = format!(
+                "{title} ({id}): {images} images, {duplicates} duplicates",
+                title = x.title,
+                id = x.id,
+                images = x.images,
+                duplicates = x.sightings - x.false_positives,
+            );
+            if let Some(removed_at) = x.removed_at {
+                line.push_str(&format!(", left {}", removed_at.format("%Y-%m-%d")));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn leave(bot: &Bot, chat: &str) -> String {
+    let Ok(chat_id) = chat.trim().parse() else {
+        return "Give the id of the chat to leave, see /chats.".to_owned();
+    };
+
+    match bot.leave_chat(ChatId(chat_id)).await {
+        Ok(_) => {
+            info!("Left {chat_id} as told by an owner");
+            format!("Left {chat_id}, its images stay indexed.")
+        }
+        Err(e) => format!("Couldn't leave {chat_id}: {e}"),
+    }
+}
+
+async fn global_stats(state: &BotState) -> String {
+    let pool = state.detector.matcher().pool();
+    let chats = match database::chat_stats(pool).await {
+        Ok(chats) => chats,
+        Err(e) => return database_error(state, e),
+    };
+    let tables = match database::table_stats(pool).await {
+        Ok(tables) => tables,
+        Err(e) => return database_error(state, e),
+    };
+
+    let images = chats.iter().map(|x| x.images).sum::<i64>();
+    let sightings = chats.iter().map(|x| x.sightings).sum::<i64>();
+    let false_positives = chats.iter().map(|x| x.false_positives).sum::<i64>();
+    let bytes = tables.iter().map(|x| x.total_bytes).sum::<i64>();
+
+    format!(
+        "{chats} chats, {images} images\n\
+         {duplicates} duplicates caught, {false_positives} false positives\n\
+         Database: {megabytes:.1} MB",
+        chats = chats.len(),
+        duplicates = sightings - false_positives,
+        megabytes = bytes as f64 / 1e6,
+    )
+}
+
+fn announce(state: &BotState, text: &str) -> String {
+    let text = text.trim();
+    if text.is_empty() {
+        return "Give the text to announce.".to_owned();
+    }
+
+    match &state.alerter {
+        Some(alerter) => {
+            alerter.notify(format!("📢 {text}"));
+            "Announced.".to_owned()
+        }
+        None => "No alert chat is configured, see [alerts] in the config.".to_owned(),
+    }
+}
+
+/// Splits the text at line breaks into messages Telegram accepts, lines themselves are
+/// short enough.
+fn chunks(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    for line in text.lines() {
+        if !chunk.is_empty() && chunk.chars().count() + line.chars().count() >= MAX_MESSAGE_LEN {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+
+        if !chunk.is_empty() {
+            chunk.push('\n');
+        }
+        chunk.push_str(line);
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
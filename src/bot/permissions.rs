@@ -0,0 +1,66 @@
+use super::BotState;
+use teloxide::prelude::*;
+
+/// Who's allowed to do what, each role can do everything the ones before it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Member,
+    /// Admins of the chat the command was sent in.
+    Admin,
+    /// Users listed under `owners` in the config, in every chat.
+    Owner,
+}
+
+impl Role {
+    /// The reply to someone trying what only this role can do.
+    pub fn denied(self) -> &'static str {
+        match self {
+            Role::Member => "You can't do that.",
+            Role::Admin => "Only admins can do that.",
+            Role::Owner => "Only the bot's owners can do that.",
+        }
+    }
+}
+
+/// The role of the message's sender in its chat. Anonymous admins post as the chat itself,
+/// and in private chats the user is in charge anyway.
+pub async fn of_sender(bot: &Bot, state: &BotState, msg: &Message) -> ResponseResult<Role> {
+    if let Some(user) = &msg.from
+        && state.owners.contains(&(user.id.0 as i64))
+    {
+        return Ok(Role::Owner);
+    }
+
+    if msg.chat.is_private()
+        || msg
+            .sender_chat
+            .as_ref()
+            .is_some_and(|x| x.id == msg.chat.id)
+    {
+        return Ok(Role::Admin);
+    }
+
+    match &msg.from {
+        Some(user) => of_user(bot, state, msg.chat.id, user.id).await,
+        None => Ok(Role::Member),
+    }
+}
+
+/// The role of a user in a chat, e.g. of one pressing a button.
+pub async fn of_user(
+    bot: &Bot,
+    state: &BotState,
+    chat_id: ChatId,
+    user_id: UserId,
+) -> ResponseResult<Role> {
+    if state.owners.contains(&(user_id.0 as i64)) {
+        return Ok(Role::Owner);
+    }
+
+    let member = bot.get_chat_member(chat_id, user_id).await?;
+    Ok(if member.is_privileged() {
+        Role::Admin
+    } else {
+        Role::Member
+    })
+}
@@ -0,0 +1,61 @@
+use dupfinder_tg::database::{self, PinnedStats};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::MessageId;
+use teloxide::{ApiError, RequestError};
+use tracing::{debug, error};
+
+/// Keeps a pinned stats message in every chat up to date, editing it rather than
+/// posting new ones.
+pub async fn update_all(
+    bot: Bot,
+    pool: PgPool,
+    allowed_chats: Arc<HashSet<i64>>,
+) -> anyhow::Result<()> {
+    let chats = database::pinned_stats(&pool).await?;
+
+    for chat in chats {
+        if !allowed_chats.is_empty() && !allowed_chats.contains(&chat.chat_id) {
+            continue;
+        }
+
+        if let Err(e) = update(&bot, &pool, &chat).await {
+            error!("Error updating the stats message in {}: {e}", chat.chat_id);
+        }
+    }
+
+    Ok(())
+}
+
+async fn update(bot: &Bot, pool: &PgPool, chat: &PinnedStats) -> anyhow::Result<()> {
+    let chat_id = ChatId(chat.chat_id);
+    let text = format!(
+        "📊 Repost stats: {} indexed, {} duplicates caught this month",
+        chat.images, chat.sightings_this_month
+    );
+
+    if let Some(message_id) = chat.stats_message_id {
+        match bot
+            .edit_message_text(chat_id, MessageId(message_id), &text)
+            .await
+        {
+            Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => return Ok(()),
+            // Someone deleted it, post a new one.
+            Err(RequestError::Api(ApiError::MessageToEditNotFound)) => {
+                debug!("Stats message in {chat_id} is gone, posting a new one")
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let message = bot.send_message(chat_id, text).await?;
+    database::set_stats_message(pool, chat.chat_id, message.id.0).await?;
+
+    bot.pin_chat_message(chat_id, message.id)
+        .disable_notification(true)
+        .await?;
+
+    Ok(())
+}
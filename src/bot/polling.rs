@@ -0,0 +1,71 @@
+use anyhow::{Context, Result};
+use dupfinder_tg::config::PollingSettings;
+use std::time::Duration;
+use teloxide::RequestError;
+use teloxide::prelude::*;
+use teloxide::stop::StopToken;
+use teloxide::types::AllowedUpdate;
+use teloxide::update_listeners::{AsUpdateStream, Polling, UpdateListener};
+
+/// Update types the handlers take, asked for unless others are configured. Channel posts
+/// are the originals for reposts in linked groups.
+const HANDLED_UPDATES: [AllowedUpdate; 4] = [
+    AllowedUpdate::Message,
+    AllowedUpdate::ChannelPost,
+    AllowedUpdate::CallbackQuery,
+    AllowedUpdate::MyChatMember,
+];
+
+/// Long polling as configured, for the configured update types or [`HANDLED_UPDATES`].
+pub async fn listener(bot: Bot, settings: &PollingSettings) -> Result<Configured> {
+    let allowed_updates = match &settings.allowed_updates {
+        Some(names) => names
+            .iter()
+            .map(|x| update_kind(x))
+            .collect::<Result<Vec<_>>>()?,
+        None => HANDLED_UPDATES.to_vec(),
+    };
+
+    let mut polling = Polling::builder(bot)
+        .timeout(Duration::from_secs(settings.timeout_secs))
+        .allowed_updates(allowed_updates)
+        .delete_webhook()
+        .await;
+    if settings.drop_pending_updates {
+        polling = polling.drop_pending_updates();
+    }
+
+    Ok(Configured {
+        listener: polling.build(),
+    })
+}
+
+fn update_kind(name: &str) -> Result<AllowedUpdate> {
+    serde_json::from_value(serde_json::Value::String(name.to_owned()))
+        .with_context(|| format!("{name:?} isn't an update type"))
+}
+
+/// A listener that ignores the dispatcher's hint, which would be every update type any
+/// handler could take, to keep the ones it was built with.
+pub struct Configured {
+    listener: Polling<Bot>,
+}
+
+impl UpdateListener for Configured {
+    type Err = RequestError;
+
+    fn stop_token(&mut self) -> StopToken {
+        self.listener.stop_token()
+    }
+
+    fn hint_allowed_updates(&mut self, _: &mut dyn Iterator<Item = AllowedUpdate>) {}
+}
+
+impl<'a> AsUpdateStream<'a> for Configured {
+    type StreamErr = RequestError;
+    type Stream = <Polling<Bot> as AsUpdateStream<'a>>::Stream;
+
+    fn as_stream(&'a mut self) -> Self::Stream {
+        self.listener.as_stream()
+    }
+}
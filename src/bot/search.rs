@@ -0,0 +1,135 @@
+use super::BotState;
+use super::permissions::{self, Role};
+use anyhow::Result;
+use dupfinder_tg::audit;
+use dupfinder_tg::database;
+use dupfinder_tg::messenger::{MessageRef, Messenger};
+use serde_json::json;
+use sqlx::types::Uuid;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use teloxide::utils::html;
+
+/// Callback data of the forget buttons, followed by the image's id.
+const FORGET_PREFIX: &str = "forget:";
+
+/// Images listed by /search.
+const RESULTS_SHOWN: i64 = 10;
+
+/// Longer captions are cut short in the results.
+const CAPTION_SHOWN: usize = 80;
+
+/// Forget buttons to a row.
+const BUTTONS_PER_ROW: usize = 5;
+
+/// The chat's latest images with `text` in their caption as HTML, and buttons to forget each
+/// one. `None` if there are none.
+pub async fn results(
+    state: &BotState,
+    chat_id: i64,
+    text: &str,
+) -> sqlx::Result<Option<(String, InlineKeyboardMarkup)>> {
+    let matcher = state.detector.matcher();
+    let found = database::search_captions(matcher.pool(), chat_id, text, RESULTS_SHOWN).await?;
+    if found.is_empty() {
+        return Ok(None);
+    }
+
+    let locale = matcher.locale(chat_id).await?;
+    let mut lines = vec![format!("🔎 Images captioned with “{}”", html::escape(text))];
+    for (i, image) in found.iter().enumerate() {
+        let message = MessageRef {
+            chat_id,
+            message_id: image.message_id,
+        };
+        let name = match state.messenger.message_link(message) {
+            Some(link) => format!(
+                "<a href=\"{}\">message {}</a>",
+                html::escape(&link),
+                image.message_id
+            ),
+            None => format!("message {}", image.message_id),
+        };
+
+        lines.push(format!(
+            "{n}. {name}, {date}: {caption}",
+            n = i + 1,
+            date = locale.format_date(image.created_at),
+            caption = html::escape(&shorten(&image.caption)),
+        ));
+    }
+
+    let buttons = found
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            InlineKeyboardButton::callback(
+                format!("🗑 {}", i + 1),
+                format!("{FORGET_PREFIX}{}", image.id),
+            )
+        })
+        .collect::<Vec<_>>();
+    let keyboard = InlineKeyboardMarkup::new(buttons.chunks(BUTTONS_PER_ROW).map(|x| x.to_vec()));
+
+    Ok(Some((lines.join("\n"), keyboard)))
+}
+
+/// Handles a press of one of the forget buttons, taking the image out of the index until
+/// someone runs /undo. Only admins can forget images.
+pub async fn press(bot: &Bot, state: &BotState, query: &CallbackQuery) -> Result<()> {
+    let Some(id) = query
+        .data
+        .as_deref()
+        .and_then(|x| x.strip_prefix(FORGET_PREFIX))
+        .and_then(|x| Uuid::parse_str(x).ok())
+    else {
+        return Ok(());
+    };
+
+    let Some(message) = &query.message else {
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+
+    if permissions::of_user(bot, state, chat_id, query.from.id).await? < Role::Admin {
+        bot.answer_callback_query(query.id.clone())
+            .text(Role::Admin.denied())
+            .await?;
+        return Ok(());
+    }
+
+    let pool = state.detector.matcher().pool();
+    let text = match database::delete_image(pool, chat_id.0, id).await? {
+        Some(forgotten) => {
+            audit::record(
+                pool,
+                Some(forgotten.chat_id),
+                Some(query.from.id.0 as i64),
+                audit::Action::Forget,
+                json!({ "message_id": forgotten.message_id, "via": "search" }),
+            )
+            .await;
+
+            format!(
+                "Forgot message {}, /undo brings it back.",
+                forgotten.message_id
+            )
+        }
+        None => "It's forgotten already.".to_owned(),
+    };
+
+    bot.answer_callback_query(query.id.clone())
+        .text(text)
+        .await?;
+
+    Ok(())
+}
+
+/// The caption on one line, cut short at [`CAPTION_SHOWN`] characters.
+fn shorten(caption: &str) -> String {
+    let caption = caption.split_whitespace().collect::<Vec<_>>().join(" ");
+    match caption.char_indices().nth(CAPTION_SHOWN) {
+        Some((i, _)) => format!("{}…", &caption[..i]),
+        None => caption,
+    }
+}
@@ -0,0 +1,245 @@
+use super::BotState;
+use super::permissions::{self, Role};
+use anyhow::Result;
+use dupfinder_tg::audit;
+use dupfinder_tg::database;
+use serde_json::json;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+/// Callback data of the menu's buttons, followed by what to change.
+const SETTINGS_PREFIX: &str = "settings:";
+
+/// Locales the language button cycles through, `None` being ISO dates. Others can still be
+/// set with /locale.
+const LOCALES: [Option<&str>; 8] = [
+    None,
+    Some("en"),
+    Some("en-us"),
+    Some("de"),
+    Some("fr"),
+    Some("es"),
+    Some("ru"),
+    Some("ja"),
+];
+
+/// Lifetimes of duplicate notices the notices button cycles through, `None` keeping them.
+/// Telegram doesn't let bots delete anything older than two days.
+const NOTICE_LIFETIMES: [Option<i32>; 5] = [
+    None,
+    Some(60),
+    Some(10 * 60),
+    Some(60 * 60),
+    Some(24 * 60 * 60),
+];
+
+/// The text and buttons of the chat's settings menu, or `None` if nothing was indexed in it
+/// yet and there's nowhere to keep its settings.
+pub async fn menu(
+    state: &BotState,
+    chat_id: i64,
+) -> sqlx::Result<Option<(String, InlineKeyboardMarkup)>> {
+    let matcher = state.detector.matcher();
+    let Some(settings) = database::chat_settings(matcher.pool(), chat_id).await? else {
+        return Ok(None);
+    };
+
+    let threshold = matcher.threshold(chat_id).await?;
+    let text = format!(
+        "⚙️ Settings\n\
+         Threshold: {threshold} ({similarity:.0}% similar){default}\n\
+         Silent mode: {silent}\n\
+         Enforcement: {enforcement}\n\
+         Notices: {notices}\n\
+         Dates: {locale}",
+        similarity = matcher.similarity(threshold),
+        default = match settings.similarity_threshold {
+            Some(_) => "",
+            None => ", the default",
+        },
+        silent = match settings.silent {
+            true => "on, duplicates are only recorded",
+            false => "off",
+        },
+        enforcement = match (settings.enforce, &state.escalation) {
+            (_, None) => "no escalation policy configured",
+            (true, Some(_)) => "escalation policy applies",
+            (false, Some(_)) => "replies only",
+        },
+        notices = match (
+            settings.notice_lifetime_secs,
+            settings.delete_notices_with_repost
+        ) {
+            (None, false) => "kept".to_owned(),
+            (None, true) => "deleted with the repost".to_owned(),
+            (Some(secs), false) => format!("deleted after {}", lifetime(secs)),
+            (Some(secs), true) => format!("deleted after {} or with the repost", lifetime(secs)),
+        },
+        locale = settings.locale.as_deref().unwrap_or("ISO"),
+    );
+
+    let on_off = |x: bool| if x { "on" } else { "off" };
+    let button = |label: String, change: &str| {
+        InlineKeyboardButton::callback(label, format!("{SETTINGS_PREFIX}{change}"))
+    };
+    let keyboard = InlineKeyboardMarkup::new([
+        vec![
+            button("➖ Stricter".to_owned(), "threshold-down"),
+            button("➕ Looser".to_owned(), "threshold-up"),
+        ],
+        vec![button(
+            format!("Silent mode: {}", on_off(settings.silent)),
+            "silent",
+        )],
+        vec![button(
+            format!("Enforcement: {}", on_off(settings.enforce)),
+            "enforce",
+        )],
+        vec![
+            button(
+                format!(
+                    "Delete notices: {}",
+                    settings
+                        .notice_lifetime_secs
+                        .map_or("never".to_owned(), lifetime)
+                ),
+                "notice-lifetime",
+            ),
+            button(
+                format!(
+                    "With repost: {}",
+                    on_off(settings.delete_notices_with_repost)
+                ),
+                "notice-with-repost",
+            ),
+        ],
+        vec![button(
+            format!("Dates: {}", settings.locale.as_deref().unwrap_or("ISO")),
+            "locale",
+        )],
+    ]);
+
+    Ok(Some((text, keyboard)))
+}
+
+/// Handles a press of one of the menu's buttons, changing the setting and updating the menu.
+/// Only admins can change anything.
+pub async fn press(bot: &Bot, state: &BotState, query: &CallbackQuery) -> Result<()> {
+    let Some(change) = query
+        .data
+        .as_deref()
+        .and_then(|x| x.strip_prefix(SETTINGS_PREFIX))
+    else {
+        return Ok(());
+    };
+
+    let Some(message) = &query.message else {
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+
+    if permissions::of_user(bot, state, chat_id, query.from.id).await? < Role::Admin {
+        bot.answer_callback_query(query.id.clone())
+            .text("Only admins can change settings.")
+            .await?;
+        return Ok(());
+    }
+
+    let matcher = state.detector.matcher();
+    let pool = matcher.pool();
+    let Some(settings) = database::chat_settings(pool, chat_id.0).await? else {
+        return Ok(());
+    };
+
+    let changed = match change {
+        "threshold-down" | "threshold-up" => {
+            let threshold = matcher.threshold(chat_id.0).await?;
+            let threshold = match change {
+                "threshold-up" => threshold
+                    .saturating_add(1)
+                    .min(state.detector.hasher().bits() as u8),
+                _ => threshold.saturating_sub(1),
+            };
+            database::set_chat_threshold(pool, chat_id.0, Some(threshold)).await?;
+            json!({ "threshold": threshold })
+        }
+        "silent" => {
+            database::set_chat_silent(pool, chat_id.0, !settings.silent).await?;
+            json!({ "silent": !settings.silent })
+        }
+        "enforce" => {
+            database::set_chat_enforce(pool, chat_id.0, !settings.enforce).await?;
+            json!({ "enforce": !settings.enforce })
+        }
+        "notice-lifetime" | "notice-with-repost" => {
+            let (mut lifetime, mut with_repost) = (
+                settings.notice_lifetime_secs,
+                settings.delete_notices_with_repost,
+            );
+            match change {
+                "notice-lifetime" => lifetime = next_lifetime(lifetime),
+                _ => with_repost = !with_repost,
+            }
+            database::set_chat_notice_cleanup(pool, chat_id.0, lifetime, with_repost).await?;
+            json!({ "notice_lifetime_secs": lifetime, "delete_notices_with_repost": with_repost })
+        }
+        "locale" => {
+            let locale = next_locale(settings.locale.as_deref());
+            database::set_chat_locale(pool, chat_id.0, None, Some(locale)).await?;
+            json!({ "locale": locale })
+        }
+        _ => return Ok(()),
+    };
+
+    audit::record(
+        pool,
+        Some(chat_id.0),
+        Some(query.from.id.0 as i64),
+        audit::Action::Setting,
+        changed,
+    )
+    .await;
+
+    if let Some((text, keyboard)) = menu(state, chat_id.0).await? {
+        bot.edit_message_text(chat_id, message.id(), text)
+            .reply_markup(keyboard)
+            .await?;
+    }
+
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    Ok(())
+}
+
+/// The lifetime after `current` in [`NOTICE_LIFETIMES`], the first one if it isn't listed.
+fn next_lifetime(current: Option<i32>) -> Option<i32> {
+    let next = NOTICE_LIFETIMES
+        .iter()
+        .position(|x| *x == current)
+        .map_or(0, |i| (i + 1) % NOTICE_LIFETIMES.len());
+
+    NOTICE_LIFETIMES[next]
+}
+
+/// `secs` in the largest unit that divides it, e.g. `10m`.
+fn lifetime(secs: i32) -> String {
+    if secs % (24 * 60 * 60) == 0 {
+        format!("{}d", secs / (24 * 60 * 60))
+    } else if secs % (60 * 60) == 0 {
+        format!("{}h", secs / (60 * 60))
+    } else if secs % 60 == 0 {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+/// The locale after `current` in [`LOCALES`], the first one if it isn't listed.
+fn next_locale(current: Option<&str>) -> Option<&'static str> {
+    let next = LOCALES
+        .iter()
+        .position(|x| *x == current)
+        .map_or(0, |i| (i + 1) % LOCALES.len());
+
+    LOCALES[next]
+}
@@ -0,0 +1,71 @@
+use anyhow::Result;
+use dupfinder_tg::config::StaleCheckSettings;
+use dupfinder_tg::database;
+use dupfinder_tg::notices;
+use sqlx::PgPool;
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::MessageId;
+use teloxide::{ApiError, RequestError};
+use tracing::debug;
+
+/// Between two probes, the staging chat gets a forward and a delete each time.
+const PROBE_DELAY: Duration = Duration::from_secs(1);
+
+pub struct StaleCheck {
+    pub checked: usize,
+    pub stale: usize,
+}
+
+/// Forwards a sample of the indexed messages in `chats` (all if empty) to the staging chat,
+/// marking the ones that no longer exist stale. Messages that can't be forwarded for other
+/// reasons, e.g. protected content, are left alone.
+pub async fn check(
+    bot: &Bot,
+    pool: &PgPool,
+    settings: &StaleCheckSettings,
+    purge: bool,
+    chats: &[i64],
+) -> Result<StaleCheck> {
+    let staging = ChatId(settings.staging_chat_id);
+    let sample = database::sample_live_images(pool, chats, settings.sample, settings.sweep).await?;
+
+    let mut result = StaleCheck {
+        checked: 0,
+        stale: 0,
+    };
+
+    for message in sample {
+        let probe = bot
+            .forward_message(
+                staging,
+                ChatId(message.chat_id),
+                MessageId(message.message_id),
+            )
+            .disable_notification(true)
+            .await;
+
+        match probe {
+            Ok(forward) => {
+                result.checked += 1;
+                bot.delete_message(staging, forward.id).await?;
+                database::mark_checked(pool, message).await?;
+            }
+            Err(RequestError::Api(ApiError::MessageToForwardNotFound)) => {
+                result.checked += 1;
+                result.stale += 1;
+                debug!("{} in {} is gone", message.message_id, message.chat_id);
+                database::mark_stale(pool, message, purge).await?;
+                notices::repost_gone(pool, message).await?;
+            }
+            Err(e) => debug!(
+                "Couldn't probe {} in {}: {e}",
+                message.message_id, message.chat_id
+            ),
+        }
+
+        tokio::time::sleep(PROBE_DELAY).await;
+    }
+
+    Ok(result)
+}
@@ -1,24 +1,720 @@
+use crate::matching;
 use serde::Deserialize;
+use std::path::PathBuf;
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
 pub struct DatabaseSettings {
     pub url: String,
+    /// Read-only replica the match queries go to, writes still go to `url`.
+    pub read_url: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
 pub struct TelegramSettings {
+    /// Only used to tell the bots apart in logs.
+    pub name: Option<String>,
     pub token: String,
+    /// Chats the bot works in, empty means all of them. It leaves any other chat it's
+    /// added to.
+    #[serde(default)]
+    pub allowed_chats: Vec<i64>,
+    /// Also delete whatever was stored about a chat the bot leaves for not being allowed.
+    #[serde(default)]
+    pub purge_unauthorized: bool,
+    /// Overrides the global `similarity-threshold` for this bot's chats.
+    pub similarity_threshold: Option<u8>,
+    /// Overrides the global `min-similarity` for this bot's chats.
+    pub min_similarity: Option<u8>,
+    /// A self-hosted telegram-bot-api server, which unlike the official one can serve
+    /// files over 20 MB.
+    pub api_url: Option<String>,
+    /// The API server runs with `--local` on this machine and hands out file paths on
+    /// disk instead of serving downloads.
+    #[serde(default)]
+    pub local_files: bool,
+    /// `http://`, `https://` or `socks5://` proxy to reach the Bot API through.
+    pub proxy: Option<String>,
+    /// Keep a pinned stats message in every chat up to date, needs the pin permission.
+    #[serde(default)]
+    pub pinned_stats: bool,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub database: DatabaseSettings,
-    pub telegram: TelegramSettings,
+    pub telegram: Option<TelegramSettings>,
+    /// Additional bots served by the same process and database.
+    #[serde(default)]
+    pub bots: Vec<TelegramSettings>,
     #[serde(default = "default_similarity_threshold")]
     pub similarity_threshold: u8,
+    /// How alike two images must be in percent, replaces `similarity-threshold` when set.
+    pub min_similarity: Option<u8>,
+    /// Evaluated next to the live threshold, disagreements are logged and stored but never acted on.
+    pub shadow_similarity_threshold: Option<u8>,
+    #[serde(default)]
+    pub hashing: HashingSettings,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookSettings>,
+    /// Rhai script defining `on_duplicate(ctx)` and/or `on_new_image(ctx)` hooks.
+    pub script: Option<PathBuf>,
+    pub dashboard: Option<DashboardSettings>,
+    pub archive: Option<ArchiveSettings>,
+    pub verification: Option<VerificationSettings>,
+    #[serde(default)]
+    pub logging: LoggingSettings,
+    pub alerts: Option<AlertSettings>,
+    /// Claim every message in the database before handling it, for running several replicas.
+    #[serde(default)]
+    pub claim_messages: bool,
+    /// Persist duplicate replies and send them from a background task with retries.
+    #[serde(default)]
+    pub outbox: bool,
+    #[serde(default)]
+    pub downloads: DownloadSettings,
+    /// How the bots get their updates from Telegram.
+    #[serde(default)]
+    pub polling: PollingSettings,
+    /// How fast each bot sends replies and notices, within Telegram's limits by default.
+    #[serde(default)]
+    pub send_limits: SendLimitSettings,
+    /// Index images sent with a `dup?` caption when they turn out not to be duplicates.
+    #[serde(default)]
+    pub index_queried: bool,
+    /// Forwards from these chats are ignored, e.g. a group's own linked channel.
+    #[serde(default)]
+    pub skip_forwards_from: Vec<i64>,
+    /// Telegram user ids allowed to use every command in every chat, admin or not.
+    #[serde(default)]
+    pub owners: Vec<i64>,
+    /// Images sent by bots are ignored in these chats, e.g. ones fed by RSS or mirror bots.
+    #[serde(default)]
+    pub ignore_bots_in: Vec<i64>,
+    /// Deleted images can be restored for this long before they're gone for good.
+    #[serde(default = "default_purge_deleted_after_days")]
+    pub purge_deleted_after_days: i64,
+    /// Delete images whose message turned out to be gone, instead of only no longer matching
+    /// against them.
+    #[serde(default)]
+    pub purge_stale: bool,
+    /// Match (nearly) solid images too, which otherwise are only indexed.
+    #[serde(default)]
+    pub match_low_entropy: bool,
+    /// What to do when someone reposts their own image.
+    #[serde(default)]
+    pub own_reposts: RepostHandling,
+    /// Delete an image someone sent again within a minute, which is usually a client glitch,
+    /// where the bot is allowed to.
+    #[serde(default)]
+    pub delete_double_sends: bool,
+    /// Edit the notice about someone's repost to list their further reposts of the next
+    /// minute, instead of replying to each.
+    #[serde(default)]
+    pub aggregate_notices: bool,
+    /// Give the duplicates among a burst of images, e.g. someone dumping a folder of photos,
+    /// a single notice instead of one each.
+    pub burst_summaries: Option<BurstSettings>,
+    /// What to do with reposts of images younger than the chat's minimum age, see /minage.
+    #[serde(default = "default_young_reposts")]
+    pub young_reposts: RepostHandling,
+    /// Check now and then whether indexed messages still exist.
+    pub stale_check: Option<StaleCheckSettings>,
+    /// When the periodic jobs run.
+    #[serde(default)]
+    pub schedule: ScheduleSettings,
+    /// Rule out matches with in-memory Bloom filters before querying the database. Only
+    /// safe when this process is the only one indexing images.
+    #[serde(default)]
+    pub prefilter: bool,
+    /// Stricter actions against users who keep reposting.
+    pub escalation: Option<EscalationSettings>,
+    /// Index new images from a background task in batches instead of inline.
+    pub batch_writes: Option<BatchWriteSettings>,
+    /// Groups of chats, e.g. a network of channels, whose images count as originals for
+    /// reposts in any of the others.
+    #[serde(default)]
+    pub networks: Vec<NetworkSettings>,
+    /// Caps how many images are kept per chat.
+    pub quota: Option<QuotaSettings>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct BurstSettings {
+    /// This many images in a chat within `within-secs` of each other are a burst.
+    #[serde(default = "default_burst_images")]
+    pub images: usize,
+    #[serde(default = "default_burst_within_secs")]
+    pub within_secs: u64,
+}
+
+fn default_burst_images() -> usize {
+    10
+}
+
+fn default_burst_within_secs() -> u64 {
+    30
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct NetworkSettings {
+    pub name: String,
+    pub chats: Vec<i64>,
+}
+
+/// Reposts are always replied to, the steps add to that once a sender reaches their number
+/// of reposts within the window.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct EscalationSettings {
+    #[serde(default = "default_escalation_window_days")]
+    pub window_days: i64,
+    pub steps: Vec<EscalationStep>,
+    /// Chats that follow their own steps instead.
+    #[serde(default)]
+    pub chats: Vec<ChatEscalation>,
+}
+
+impl EscalationSettings {
+    /// The step a sender with this many reposts in the chat has reached, if any.
+    pub fn step(&self, chat_id: i64, reposts: i64) -> Option<&EscalationStep> {
+        let steps = self
+            .chats
+            .iter()
+            .find(|x| x.chat_id == chat_id)
+            .map_or(&self.steps, |x| &x.steps);
+
+        steps
+            .iter()
+            .filter(|x| x.reposts <= reposts)
+            .max_by_key(|x| x.reposts)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ChatEscalation {
+    pub chat_id: i64,
+    pub steps: Vec<EscalationStep>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct EscalationStep {
+    pub reposts: i64,
+    #[serde(flatten)]
+    pub action: EnforcementAction,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(
+    rename_all = "kebab-case",
+    rename_all_fields = "kebab-case",
+    tag = "action"
+)]
+pub enum EnforcementAction {
+    /// Delete the repost.
+    Delete,
+    /// Delete the repost and keep the sender from posting for a while, needs the bot to be
+    /// an admin allowed to restrict members.
+    Restrict { hours: u32 },
+}
+
+impl EnforcementAction {
+    pub fn name(self) -> &'static str {
+        match self {
+            EnforcementAction::Delete => "delete",
+            EnforcementAction::Restrict { .. } => "restrict",
+        }
+    }
+}
+
+fn default_escalation_window_days() -> i64 {
+    7
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct BatchWriteSettings {
+    #[serde(default = "default_max_batch")]
+    pub max_batch: usize,
+    /// How long the writer waits for more images before writing a partial batch.
+    #[serde(default = "default_max_batch_delay_ms")]
+    pub max_delay_ms: u64,
+    /// Images waiting to be written. Handlers wait once it's full.
+    #[serde(default = "default_write_queue_size")]
+    pub queue_size: usize,
+}
+
+fn default_max_batch() -> usize {
+    100
+}
+
+fn default_max_batch_delay_ms() -> u64 {
+    200
+}
+
+fn default_write_queue_size() -> usize {
+    1000
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct DownloadSettings {
+    /// Shared by all bots.
+    #[serde(default = "default_max_concurrent_downloads")]
+    pub max_concurrent: usize,
+    /// Out of `max_concurrent`, for the media of a single chat. A chat's own images are
+    /// handled one at a time anyway, this bounds the originals the other chats of its network
+    /// fetch from it for verification.
+    #[serde(default = "default_max_downloads_per_chat")]
+    pub max_per_chat: usize,
+    #[serde(default = "default_download_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        Self {
+            max_concurrent: default_max_concurrent_downloads(),
+            max_per_chat: default_max_downloads_per_chat(),
+            timeout_secs: default_download_timeout_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct PollingSettings {
+    /// How long each request waits for new updates, has to stay below the HTTP client's 17s
+    /// timeout.
+    #[serde(default = "default_polling_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Skip the updates that piled up while the bot was down instead of working through them.
+    #[serde(default)]
+    pub drop_pending_updates: bool,
+    /// Update types to ask for, as named by the Bot API, e.g. `message` or `callback_query`.
+    /// Every type the bot handles if left out. Without `my_chat_member` the bot doesn't
+    /// notice being added to or removed from chats.
+    pub allowed_updates: Option<Vec<String>>,
+}
+
+impl Default for PollingSettings {
+    fn default() -> Self {
+        Self {
+            timeout_secs: default_polling_timeout_secs(),
+            drop_pending_updates: false,
+            allowed_updates: None,
+        }
+    }
+}
+
+fn default_polling_timeout_secs() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct SendLimitSettings {
+    /// Messages a second over all chats.
+    #[serde(default = "default_sends_per_second")]
+    pub per_second: u32,
+    /// Messages a minute to each group or channel.
+    #[serde(default = "default_sends_per_group_per_minute")]
+    pub per_group_per_minute: u32,
+    /// How often a send Telegram flood-limited is tried again after the wait it asked for.
+    #[serde(default = "default_send_retries")]
+    pub max_retries: u32,
+}
+
+impl Default for SendLimitSettings {
+    fn default() -> Self {
+        Self {
+            per_second: default_sends_per_second(),
+            per_group_per_minute: default_sends_per_group_per_minute(),
+            max_retries: default_send_retries(),
+        }
+    }
+}
+
+fn default_sends_per_second() -> u32 {
+    30
+}
+
+fn default_sends_per_group_per_minute() -> u32 {
+    20
+}
+
+fn default_send_retries() -> u32 {
+    3
+}
+
+fn default_max_concurrent_downloads() -> usize {
+    8
+}
+
+fn default_max_downloads_per_chat() -> usize {
+    2
+}
+
+fn default_download_timeout_secs() -> u64 {
+    60
+}
+
+impl Config {
+    /// Largest distance a bot treats as a duplicate for hashes of `bits` bits. The bot's own
+    /// settings win over the global ones, and a similarity over a threshold.
+    pub fn threshold(&self, bot: &TelegramSettings, bits: u32) -> u8 {
+        bot.min_similarity
+            .map(|x| matching::max_distance(x, bits))
+            .or(bot.similarity_threshold)
+            .unwrap_or_else(|| self.global_threshold(bits))
+    }
+
+    /// Like [`Config::threshold`], for when no bot is involved.
+    pub fn global_threshold(&self, bits: u32) -> u8 {
+        self.min_similarity
+            .map(|x| matching::max_distance(x, bits))
+            .unwrap_or(self.similarity_threshold)
+    }
+
+    /// All configured bots, `[telegram]` first.
+    pub fn bots(&self) -> impl Iterator<Item = &TelegramSettings> {
+        self.telegram.iter().chain(&self.bots)
+    }
+}
+
+fn default_purge_deleted_after_days() -> i64 {
+    30
+}
+
+fn default_young_reposts() -> RepostHandling {
+    RepostHandling::Ignore
 }
 
 fn default_similarity_threshold() -> u8 {
     5
 }
+
+/// Images beyond the maximum are deleted for good by the `enforce-quota` job.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct QuotaSettings {
+    pub max_entries_per_chat: i64,
+    #[serde(default)]
+    pub eviction: EvictionPolicy,
+}
+
+/// Which images go first once a chat is over its quota.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum EvictionPolicy {
+    #[default]
+    OldestFirst,
+    /// Those that haven't been the original of a repost for the longest, or ever.
+    LeastRecentlyMatched,
+}
+
+/// How a kind of repost is treated. The sighting is recorded either way, only the reply
+/// differs. Ordered from strictest to most lenient.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RepostHandling {
+    /// Like any other duplicate.
+    #[default]
+    Flag,
+    /// A short reminder without escalation.
+    Soften,
+    /// No reply and no escalation.
+    Ignore,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct LoggingSettings {
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Exports spans over OTLP/HTTP, needs the `otel` cargo feature.
+    pub otlp: Option<OtlpSettings>,
+    /// Reports panics and logged errors, needs the `sentry` cargo feature.
+    pub sentry: Option<SentrySettings>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SentrySettings {
+    /// Any Sentry-compatible DSN (Sentry, GlitchTip, ...).
+    pub dsn: String,
+    pub environment: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct OtlpSettings {
+    /// Full traces endpoint, e.g. `http://localhost:4318/v1/traces`.
+    pub endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+
+fn default_service_name() -> String {
+    "dupfinder-tg".to_owned()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct WebhookSettings {
+    pub url: String,
+    /// Sent in the `X-Dupfinder-Secret` header so the receiver can verify the sender.
+    pub secret: Option<String>,
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_webhook_timeout() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(
+    rename_all = "kebab-case",
+    rename_all_fields = "kebab-case",
+    tag = "backend"
+)]
+pub enum ArchiveSettings {
+    Disk {
+        path: PathBuf,
+    },
+    /// Any S3-compatible bucket, needs the `s3` cargo feature.
+    S3 {
+        bucket: String,
+        region: String,
+        endpoint: Option<String>,
+        access_key_id: String,
+        secret_access_key: String,
+    },
+}
+
+/// Matches close to the threshold get double-checked by comparing the actual pixels.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct VerificationSettings {
+    /// Matches with a distance within this many bits of the threshold are verified.
+    #[serde(default = "default_verification_margin")]
+    pub margin: u8,
+    /// Minimum structural similarity (SSIM, 0 to 1) for a verified match to count.
+    #[serde(default = "default_min_similarity")]
+    pub min_similarity: f64,
+}
+
+fn default_verification_margin() -> u8 {
+    2
+}
+
+fn default_min_similarity() -> f64 {
+    0.75
+}
+
+/// Five field cron expressions in UTC, see [`crate::scheduler::Schedule`].
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct ScheduleSettings {
+    /// Purging images deleted longer ago than `purge-deleted-after-days`.
+    pub purge_deleted: String,
+    /// Forgetting old message claims, with `claim-messages`.
+    pub prune_claims: String,
+    /// Probing for deleted originals, with `[stale-check]`.
+    pub stale_check: String,
+    /// Updating pinned stats messages, for bots with `pinned-stats`.
+    pub pinned_stats: String,
+    /// Evicting images of chats over their quota, with `[quota]`.
+    pub enforce_quota: String,
+}
+
+impl Default for ScheduleSettings {
+    fn default() -> Self {
+        Self {
+            purge_deleted: "15 * * * *".to_owned(),
+            prune_claims: "45 * * * *".to_owned(),
+            stale_check: "30 3 * * *".to_owned(),
+            pinned_stats: "0 * * * *".to_owned(),
+            enforce_quota: "50 * * * *".to_owned(),
+        }
+    }
+}
+
+/// Originals are probed by forwarding them to a private chat and deleting the forward again.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct StaleCheckSettings {
+    /// Chat nobody else needs to see the forwards in, e.g. a private group with just the bot.
+    pub staging_chat_id: i64,
+    /// Images probed per run.
+    #[serde(default = "default_stale_check_sample")]
+    pub sample: i64,
+    /// Probe the least recently checked images instead of a random sample, so that every
+    /// image gets its turn.
+    #[serde(default)]
+    pub sweep: bool,
+}
+
+fn default_stale_check_sample() -> i64 {
+    200
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct AlertSettings {
+    /// User or chat the bot messages when things go wrong. The user has to /start the bot first.
+    pub chat_id: i64,
+    #[serde(default = "default_db_outage_secs")]
+    pub db_outage_secs: u64,
+    #[serde(default = "default_telegram_failures_per_minute")]
+    pub telegram_failures_per_minute: usize,
+    /// Minimum time between two alerts of the same kind.
+    #[serde(default = "default_alert_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+fn default_db_outage_secs() -> u64 {
+    60
+}
+
+fn default_telegram_failures_per_minute() -> usize {
+    10
+}
+
+fn default_alert_cooldown_secs() -> u64 {
+    30 * 60
+}
+
+/// The dashboard uses HTTP basic auth, put it behind a TLS-terminating proxy if it's
+/// reachable from outside.
+#[derive(Debug, Deserialize, Clone)]
+pub struct DashboardSettings {
+    pub listen: String,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashAlgorithm {
+    Mean,
+    Gradient,
+    VertGradient,
+    DoubleGradient,
+    Blockhash,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+/// Changing anything here makes new hashes incomparable with the ones already stored,
+/// so existing chats have to be re-imported afterwards.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct HashingSettings {
+    pub algorithm: HashAlgorithm,
+    pub hash_width: u32,
+    pub hash_height: u32,
+    pub resize_filter: ResizeFilter,
+    pub preproc_dct: bool,
+    /// Files larger than this many bytes are not decoded at all.
+    pub max_file_size: u64,
+    /// Images wider or taller than this are not hashed.
+    pub max_dimension: u32,
+    /// Downscale and grayscale images before hashing, so recompressed copies (e.g. a photo
+    /// and the same image sent as a file) hash closer together.
+    pub normalize: bool,
+    /// Longest side images are downscaled to when normalizing.
+    pub normalize_size: u32,
+    /// Cleanup applied before anything else, including `normalize`.
+    pub preprocess: PreprocessSettings,
+}
+
+/// Steps run on images before hashing, all off by default.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct PreprocessSettings {
+    /// Crop away uniformly colored borders, e.g. ones added around screenshots.
+    pub crop_borders: bool,
+    /// Crop away a mostly uniform bar at the bottom with a logo or handle in it.
+    pub strip_watermark_bar: bool,
+    /// How far apart per channel colors can be and still count as the same border color.
+    pub border_tolerance: u8,
+    /// Crop away black bars above and below (or beside) the picture.
+    pub remove_letterbox: bool,
+    pub grayscale: bool,
+    /// Even out overall brightness by bringing the mean to the middle with a gamma curve.
+    pub normalize_gamma: bool,
+    /// Resize to a fixed square with a fixed filter, so the hasher's own resizing always
+    /// starts from the same size.
+    pub resize: Option<PreprocessResize>,
+}
+
+impl Default for PreprocessSettings {
+    fn default() -> Self {
+        Self {
+            crop_borders: false,
+            strip_watermark_bar: false,
+            border_tolerance: 16,
+            remove_letterbox: false,
+            grayscale: false,
+            normalize_gamma: false,
+            resize: None,
+        }
+    }
+}
+
+impl PreprocessSettings {
+    pub fn is_enabled(&self) -> bool {
+        self.crop_borders
+            || self.strip_watermark_bar
+            || self.remove_letterbox
+            || self.grayscale
+            || self.normalize_gamma
+            || self.resize.is_some()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct PreprocessResize {
+    pub size: u32,
+    pub filter: ResizeFilter,
+}
+
+impl Default for HashingSettings {
+    fn default() -> Self {
+        Self {
+            algorithm: HashAlgorithm::Gradient,
+            hash_width: 8,
+            hash_height: 8,
+            resize_filter: ResizeFilter::Lanczos3,
+            preproc_dct: false,
+            max_file_size: 50 * 1024 * 1024,
+            max_dimension: 16384,
+            normalize: false,
+            normalize_size: 256,
+            preprocess: PreprocessSettings::default(),
+        }
+    }
+}
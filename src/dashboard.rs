@@ -0,0 +1,550 @@
+use crate::audit;
+use crate::config::DashboardSettings;
+use crate::database;
+use axum::Router;
+use axum::extract::{Form, Path, Query, Request, State};
+use axum::http::{Method, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use sqlx::types::Uuid;
+use std::fmt::Write;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+const PAGE_SIZE: i64 = 100;
+
+#[derive(Clone)]
+struct DashboardState {
+    pool: PgPool,
+    /// Expected value of the Authorization header.
+    authorization: Arc<str>,
+    /// Width of the hashes, which no threshold can go past.
+    bits: u32,
+}
+
+/// Serves the admin dashboard until the process exits.
+pub async fn run(settings: DashboardSettings, pool: PgPool, bits: u32) -> std::io::Result<()> {
+    let credentials = format!("{}:{}", settings.username, settings.password);
+    let state = DashboardState {
+        pool,
+        authorization: format!("Basic {}", BASE64.encode(credentials)).into(),
+        bits,
+    };
+
+    let app = Router::new()
+        .route("/", get(index))
+        .route("/chats/{chat_id}/threshold", post(set_threshold))
+        .route("/chats/{chat_id}/images", get(images))
+        .route("/chats/{chat_id}/images/{id}/delete", post(delete_image))
+        .route("/chats/{chat_id}/images/{id}/deleted", get(deleted_image))
+        .route("/chats/{chat_id}/images/{id}/restore", post(restore_image))
+        .route("/search", get(search))
+        .route("/detections", get(detections))
+        .route("/detections/{id}/false-positive", post(set_false_positive))
+        .route("/audit", get(audit_log))
+        .route("/metrics", get(metrics))
+        .layer(middleware::from_fn_with_state(state.clone(), auth))
+        .with_state(state);
+
+    let listener = TcpListener::bind(&settings.listen).await?;
+    info!("Dashboard listening on {}", settings.listen);
+
+    axum::serve(listener, app).await
+}
+
+async fn auth(State(state): State<DashboardState>, request: Request, next: Next) -> Response {
+    // Compared in constant time, so how long a guess takes doesn't tell how much of it is right.
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .is_some_and(|x| x.as_bytes().ct_eq(state.authorization.as_bytes()).into());
+
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"dupfinder-tg\"")],
+        )
+            .into_response();
+    }
+
+    // Browsers send the credentials along with forms posted from any other site, so changes
+    // are only taken from the dashboard's own pages.
+    if request.method() != Method::GET && !same_origin(&request) {
+        return (StatusCode::FORBIDDEN, "cross-origin request").into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Whether the request comes from a page of the dashboard itself, going by its Origin header,
+/// or its Referer for browsers that leave the former out.
+fn same_origin(request: &Request) -> bool {
+    let headers = request.headers();
+    let Some(host) = headers.get(header::HOST).and_then(|x| x.to_str().ok()) else {
+        return false;
+    };
+
+    headers
+        .get(header::ORIGIN)
+        .or_else(|| headers.get(header::REFERER))
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.split_once("://"))
+        .is_some_and(|(_, rest)| rest.split('/').next() == Some(host))
+}
+
+struct Error(sqlx::Error);
+
+impl From<sqlx::Error> for Error {
+    fn from(value: sqlx::Error) -> Self {
+        Self(value)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        error!("Database error: {}", self.0);
+        (StatusCode::INTERNAL_SERVER_ERROR, "database error").into_response()
+    }
+}
+
+async fn index(State(state): State<DashboardState>) -> Result<Html<String>, Error> {
+    let chats = database::chat_stats(&state.pool).await?;
+
+    let mut body = String::from(
+        "<h1>Chats</h1><table><tr><th>Chat</th><th>Id</th><th>Images</th><th>Low-entropy</th><th>Duplicates</th>\
+         <th>False positives</th><th>Threshold</th></tr>",
+    );
+
+    for chat in chats {
+        let threshold = chat
+            .similarity_threshold
+            .map(|x| x.to_string())
+            .unwrap_or_default();
+
+        let _ = write!(
+            body,
+            "<tr><td><a href=\"/chats/{id}/images\">{title}</a>{left}</td><td>{id}</td><td>{images}</td><td>{low_entropy}</td>\
+             <td><a href=\"/detections?chat_id={id}\">{sightings}</a></td><td>{false_positives}</td>\
+             <td><form method=\"post\" action=\"/chats/{id}/threshold\">\
+             <input name=\"threshold\" size=\"3\" value=\"{threshold}\" placeholder=\"default\">\
+             <button>Set</button></form></td></tr>",
+            id = chat.id,
+            title = escape(&chat.title),
+            left = chat.removed_at.map_or("", |_| " (left)"),
+            images = chat.images,
+            low_entropy = chat.low_entropy,
+            sightings = chat.sightings,
+            false_positives = chat.false_positives,
+        );
+    }
+
+    body.push_str(
+        "</table><p><a href=\"/detections\">Recent detections</a></p>\
+         <form action=\"/search\"><input name=\"q\" placeholder=\"Captions and chat titles\">\
+         <button>Search</button></form>",
+    );
+
+    Ok(page("dupfinder-tg", &body))
+}
+
+/// Database size and per-chat counts in the Prometheus text format.
+async fn metrics(State(state): State<DashboardState>) -> Result<Response, Error> {
+    let tables = database::table_stats(&state.pool).await?;
+    let chats = database::chat_stats(&state.pool).await?;
+
+    let mut body = String::new();
+    let mut metric = |name: &str, help: &str, kind: &str| {
+        let _ = writeln!(
+            body,
+            "# HELP dupfinder_{name} {help}\n# TYPE dupfinder_{name} {kind}"
+        );
+    };
+    metric(
+        "table_bytes",
+        "Size of the table on disk, indexes included.",
+        "gauge",
+    );
+    metric("table_index_bytes", "Size of the table's indexes.", "gauge");
+    metric("table_rows", "Estimated live rows of the table.", "gauge");
+    metric(
+        "table_dead_rows",
+        "Rows of the table awaiting vacuum.",
+        "gauge",
+    );
+    metric("chat_images", "Indexed images of the chat.", "gauge");
+    metric("chat_duplicates", "Duplicates caught in the chat.", "gauge");
+    metric(
+        "chat_oldest_image_timestamp_seconds",
+        "When the chat's oldest indexed image was indexed.",
+        "gauge",
+    );
+    metric(
+        "chat_newest_image_timestamp_seconds",
+        "When the chat's newest indexed image was indexed.",
+        "gauge",
+    );
+
+    for table in tables {
+        let name = table.name;
+        let _ = writeln!(
+            body,
+            "dupfinder_table_bytes{{table=\"{name}\"}} {}",
+            table.total_bytes
+        );
+        let _ = writeln!(
+            body,
+            "dupfinder_table_index_bytes{{table=\"{name}\"}} {}",
+            table.index_bytes
+        );
+        let _ = writeln!(
+            body,
+            "dupfinder_table_rows{{table=\"{name}\"}} {}",
+            table.live_rows
+        );
+        let _ = writeln!(
+            body,
+            "dupfinder_table_dead_rows{{table=\"{name}\"}} {}",
+            table.dead_rows
+        );
+    }
+
+    for chat in chats {
+        let id = chat.id;
+        let _ = writeln!(
+            body,
+            "dupfinder_chat_images{{chat_id=\"{id}\"}} {}",
+            chat.images
+        );
+        let _ = writeln!(
+            body,
+            "dupfinder_chat_duplicates{{chat_id=\"{id}\"}} {}",
+            chat.sightings
+        );
+        if let Some(oldest) = chat.oldest {
+            let _ = writeln!(
+                body,
+                "dupfinder_chat_oldest_image_timestamp_seconds{{chat_id=\"{id}\"}} {}",
+                oldest.timestamp()
+            );
+        }
+        if let Some(newest) = chat.newest {
+            let _ = writeln!(
+                body,
+                "dupfinder_chat_newest_image_timestamp_seconds{{chat_id=\"{id}\"}} {}",
+                newest.timestamp()
+            );
+        }
+    }
+
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response())
+}
+
+#[derive(Deserialize)]
+struct ThresholdForm {
+    threshold: String,
+}
+
+async fn set_threshold(
+    State(state): State<DashboardState>,
+    Path(chat_id): Path<i64>,
+    Form(form): Form<ThresholdForm>,
+) -> Result<Response, Error> {
+    // An empty field goes back to the global default.
+    let threshold = match form.threshold.trim() {
+        "" => None,
+        x => match x.parse::<u8>() {
+            Ok(x) if u32::from(x) <= state.bits => Some(x),
+            _ => return Ok((StatusCode::BAD_REQUEST, "invalid threshold").into_response()),
+        },
+    };
+
+    database::set_chat_threshold(&state.pool, chat_id, threshold).await?;
+    audit::record(
+        &state.pool,
+        Some(chat_id),
+        None,
+        audit::Action::Setting,
+        json!({ "threshold": threshold, "via": "dashboard" }),
+    )
+    .await;
+
+    Ok(Redirect::to("/").into_response())
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+    #[serde(default)]
+    page: i64,
+}
+
+async fn images(
+    State(state): State<DashboardState>,
+    Path(chat_id): Path<i64>,
+    Query(query): Query<PageQuery>,
+) -> Result<Html<String>, Error> {
+    let page_number = query.page.max(0);
+    let images =
+        database::list_images(&state.pool, chat_id, PAGE_SIZE, page_number * PAGE_SIZE).await?;
+
+    let mut body = format!(
+        "<h1>Images in {chat_id}</h1><form action=\"/search\"><input name=\"q\">\
+         <input type=\"hidden\" name=\"chat_id\" value=\"{chat_id}\"><button>Search</button></form><table><tr><th>Message</th><th>Hash</th><th>Indexed</th><th></th></tr>"
+    );
+
+    for image in &images {
+        let _ = write!(
+            body,
+            "<tr><td>{message_id}</td><td><code>{hash:016x}</code></td><td>{created_at}</td>\
+             <td><form method=\"post\" action=\"/chats/{chat_id}/images/{id}/delete\"><button>Delete</button></form></td></tr>",
+            message_id = image.message_id,
+            hash = image.phash,
+            created_at = image.created_at.format("%Y-%m-%d %H:%M"),
+            id = image.id,
+        );
+    }
+
+    body.push_str("</table><p>");
+    if page_number > 0 {
+        let _ = write!(
+            body,
+            "<a href=\"?page={prev}\">Previous</a> ",
+            prev = page_number - 1
+        );
+    }
+    if images.len() as i64 == PAGE_SIZE {
+        let _ = write!(
+            body,
+            "<a href=\"?page={next}\">Next</a>",
+            next = page_number + 1
+        );
+    }
+    body.push_str("</p>");
+
+    Ok(page("Images", &body))
+}
+
+async fn delete_image(
+    State(state): State<DashboardState>,
+    Path((chat_id, id)): Path<(i64, Uuid)>,
+) -> Result<Redirect, Error> {
+    if let Some(message) = database::delete_image(&state.pool, chat_id, id).await? {
+        audit::record(
+            &state.pool,
+            Some(message.chat_id),
+            None,
+            audit::Action::Forget,
+            json!({ "message_id": message.message_id, "via": "dashboard" }),
+        )
+        .await;
+    }
+
+    Ok(Redirect::to(&format!(
+        "/chats/{chat_id}/images/{id}/deleted"
+    )))
+}
+
+async fn deleted_image(Path((chat_id, id)): Path<(i64, Uuid)>) -> Html<String> {
+    page(
+        "Image deleted",
+        &format!(
+            "<h1>Image deleted</h1><form method=\"post\" action=\"/chats/{chat_id}/images/{id}/restore\">\
+             <button>Undo</button></form>"
+        ),
+    )
+}
+
+async fn restore_image(
+    State(state): State<DashboardState>,
+    Path((chat_id, id)): Path<(i64, Uuid)>,
+) -> Result<Response, Error> {
+    match database::restore_image(&state.pool, chat_id, id).await? {
+        Some(message) => {
+            audit::record(
+                &state.pool,
+                Some(message.chat_id),
+                None,
+                audit::Action::Restore,
+                json!({ "message_id": message.message_id, "via": "dashboard" }),
+            )
+            .await;
+            Ok(Redirect::to(&format!("/chats/{chat_id}/images")).into_response())
+        }
+        None => Ok((StatusCode::NOT_FOUND, "already purged").into_response()),
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    chat_id: Option<i64>,
+}
+
+async fn search(
+    State(state): State<DashboardState>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Html<String>, Error> {
+    let mut body = format!(
+        "<h1>Search</h1><form><input name=\"q\" value=\"{q}\" size=\"40\">{chat}\
+         <button>Search</button></form>",
+        q = escape(&query.q),
+        chat = query
+            .chat_id
+            .map(|x| format!("<input type=\"hidden\" name=\"chat_id\" value=\"{x}\">"))
+            .unwrap_or_default(),
+    );
+
+    if query.q.trim().is_empty() {
+        return Ok(page("Search", &body));
+    }
+
+    let images = database::search_archive(&state.pool, query.chat_id, &query.q, PAGE_SIZE).await?;
+    if images.is_empty() {
+        body.push_str("<p>Nothing found.</p>");
+        return Ok(page("Search", &body));
+    }
+
+    body.push_str(
+        "<table><tr><th>Indexed</th><th>Chat</th><th>Message</th><th>Caption</th><th></th></tr>",
+    );
+    for image in &images {
+        let _ = write!(
+            body,
+            "<tr><td>{created_at}</td><td><a href=\"/chats/{chat_id}/images\">{title}</a></td>\
+             <td>{message_id}</td><td>{caption}</td>\
+             <td><form method=\"post\" action=\"/chats/{chat_id}/images/{id}/delete\"><button>Delete</button></form></td></tr>",
+            created_at = image.created_at.format("%Y-%m-%d %H:%M"),
+            chat_id = image.chat_id,
+            title = escape(&image.chat_title),
+            message_id = image.message_id,
+            caption = escape(image.caption.as_deref().unwrap_or_default()),
+            id = image.id,
+        );
+    }
+    body.push_str("</table>");
+
+    Ok(page("Search", &body))
+}
+
+#[derive(Deserialize)]
+struct DetectionsQuery {
+    chat_id: Option<i64>,
+}
+
+async fn detections(
+    State(state): State<DashboardState>,
+    Query(query): Query<DetectionsQuery>,
+) -> Result<Html<String>, Error> {
+    let sightings = database::recent_sightings(&state.pool, query.chat_id, PAGE_SIZE).await?;
+
+    let mut body = String::from(
+        "<h1>Recent detections</h1><table><tr><th>When</th><th>Chat</th><th>Message</th>\
+         <th>Original</th><th>Distance</th><th>False positive</th></tr>",
+    );
+
+    for sighting in sightings {
+        let _ = write!(
+            body,
+            "<tr><td>{created_at}</td><td>{chat_id}</td><td>{message_id}</td><td>{original}</td>\
+             <td>{distance}</td><td><form method=\"post\" action=\"/detections/{id}/false-positive\">\
+             <input type=\"hidden\" name=\"value\" value=\"{toggled}\">\
+             <button>{label}</button></form></td></tr>",
+            created_at = sighting.created_at.format("%Y-%m-%d %H:%M"),
+            chat_id = sighting.chat_id,
+            message_id = sighting.message_id,
+            original = sighting.original_message_id,
+            distance = sighting.distance,
+            id = sighting.id,
+            toggled = !sighting.false_positive,
+            label = if sighting.false_positive {
+                "yes (undo)"
+            } else {
+                "no (mark)"
+            },
+        );
+    }
+
+    body.push_str("</table>");
+
+    Ok(page("Detections", &body))
+}
+
+#[derive(Deserialize)]
+struct FalsePositiveForm {
+    value: bool,
+}
+
+async fn set_false_positive(
+    State(state): State<DashboardState>,
+    Path(id): Path<Uuid>,
+    Form(form): Form<FalsePositiveForm>,
+) -> Result<Redirect, Error> {
+    database::set_false_positive(&state.pool, id, form.value).await?;
+
+    Ok(Redirect::to("/detections"))
+}
+
+async fn audit_log(
+    State(state): State<DashboardState>,
+    Query(query): Query<DetectionsQuery>,
+) -> Result<Html<String>, Error> {
+    let entries = database::audit_log(&state.pool, query.chat_id, PAGE_SIZE).await?;
+
+    let mut body = String::from(
+        "<h1>Audit log</h1><table><tr><th>When</th><th>Chat</th><th>Actor</th><th>Action</th>\
+         <th>Details</th></tr>",
+    );
+
+    for entry in entries {
+        let _ = write!(
+            body,
+            "<tr><td>{created_at}</td><td>{chat_id}</td><td>{actor}</td><td>{action}</td>\
+             <td><code>{payload}</code></td></tr>",
+            created_at = entry.created_at.format("%Y-%m-%d %H:%M:%S"),
+            chat_id = entry.chat_id.map(|x| x.to_string()).unwrap_or_default(),
+            actor = entry
+                .actor_id
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "bot".to_owned()),
+            action = escape(&entry.action),
+            payload = escape(&entry.payload.to_string()),
+        );
+    }
+
+    body.push_str("</table>");
+
+    Ok(page("Audit log", &body))
+}
+
+fn page(title: &str, body: &str) -> Html<String> {
+    Html(format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{title}</title>\
+         <style>body{{font-family:sans-serif}}td,th{{padding:2px 8px;text-align:left}}</style>\
+         </head><body><p><a href=\"/\">Chats</a> | <a href=\"/detections\">Detections</a> | <a href=\"/search\">Search</a> | <a href=\"/audit\">Audit log</a></p>{body}</body></html>",
+        title = escape(title),
+    ))
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
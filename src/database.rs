@@ -1,5 +1,10 @@
+use crate::config::EvictionPolicy;
+use crate::messenger::{ForwardOrigin, MessageRef};
 use anyhow::{Context, Result};
-use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::postgres::{PgConnection, PgPool, PgPoolOptions};
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
 
 pub async fn init_pool(database_url: &str) -> Result<PgPool> {
     PgPoolOptions::new()
@@ -9,72 +14,1977 @@ pub async fn init_pool(database_url: &str) -> Result<PgPool> {
         .context("Failed to connect to Postgres Database")
 }
 
+pub async fn migrate(pool: &PgPool) -> Result<()> {
+    sqlx::migrate!("./migrations")
+        .run(pool)
+        .await
+        .context("Failed to run database migrations")
+}
+
+/// Versions of the migrations this build ships with that haven't been applied yet.
+pub async fn pending_migrations(pool: &PgPool) -> sqlx::Result<Vec<i64>> {
+    // The table doesn't exist before the first migration.
+    let applied: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version")
+            .fetch_all(pool)
+            .await
+            .or_else(|e| match &e {
+                sqlx::Error::Database(db) if db.code().as_deref() == Some("42P01") => {
+                    Ok(Vec::new())
+                }
+                _ => Err(e),
+            })?;
+
+    Ok(sqlx::migrate!("./migrations")
+        .iter()
+        .map(|x| x.version)
+        .filter(|x| !applied.contains(x))
+        .collect())
+}
+
+/// Version of the latest migration this build ships with.
+pub fn schema_version() -> i64 {
+    sqlx::migrate!("./migrations")
+        .iter()
+        .map(|x| x.version)
+        .max()
+        .unwrap_or_default()
+}
+
+/// The database server's clock.
+pub async fn now(pool: &PgPool) -> sqlx::Result<DateTime<Utc>> {
+    sqlx::query_scalar("SELECT NOW()").fetch_one(pool).await
+}
+
+#[derive(Clone, sqlx::FromRow)]
 pub struct ClosestMatch {
+    /// The chat itself, the channel linked to it or another chat of its network.
+    pub chat_id: i64,
+    /// Title of the original's chat, if it's known.
+    pub chat_title: Option<String>,
     pub message_id: i32,
+    #[sqlx(try_from = "i32")]
     pub distance: u8,
+    /// The original was sent behind a spoiler.
+    pub spoiler: bool,
+    /// When the original was indexed.
+    pub created_at: DateTime<Utc>,
+    pub sender_id: Option<i64>,
+}
+
+impl ClosestMatch {
+    pub fn message(&self) -> MessageRef {
+        MessageRef {
+            chat_id: self.chat_id,
+            message_id: self.message_id,
+        }
+    }
+
+    /// Whether `sender_id` sent the original too.
+    pub fn sent_by(&self, sender_id: Option<i64>) -> bool {
+        sender_id.is_some() && sender_id == self.sender_id
+    }
 }
 
-/// Returns the closest match to the hash, but not the excluded message id if given.
+/// Returns the closest match to the hash among the chat's images and those of its linked
+/// channel and network, but not the excluded message of the chat if given. With an `alt_hash`, the
+/// distance is the smaller of the two hash pairs. Low-entropy images are only considered
+/// with `include_low_entropy`.
 pub async fn find_closest_match(
-    pool: &PgPool,
+    conn: &mut PgConnection,
     chat_id: i64,
     hash: i64,
+    alt_hash: Option<i64>,
     threshold: u8,
     exclude_message_id: Option<i32>,
+    include_low_entropy: bool,
 ) -> sqlx::Result<Option<ClosestMatch>> {
+    let sources = match_sources(&mut *conn, chat_id).await?;
+
+    // LEAST ignores NULLs, which covers images without an alternate hash on either side.
     let record = sqlx::query!(
         r#"
         SELECT
-            message_id,
-            bit_count( (phash # $1)::bit(64) ) as distance
-        FROM images
-        WHERE chat_id = $2
-            AND bit_count( (phash # $1)::bit(64) ) <= $3
-            AND ($4::INT IS NULL OR message_id != $4)
-        ORDER BY distance ASC, message_id ASC
+            chat_id AS "chat_id!",
+            (SELECT title FROM chats WHERE id = candidates.chat_id) AS "chat_title?",
+            message_id AS "message_id!",
+            distance AS "distance!",
+            spoiler AS "spoiler!",
+            created_at AS "created_at!",
+            sender_id AS "sender_id?"
+        FROM (
+            SELECT
+                chat_id,
+                message_id,
+                spoiler,
+                created_at,
+                sender_id,
+                LEAST(
+                    bit_count( (phash # $1)::bit(64) ),
+                    bit_count( (alt_phash # $5)::bit(64) )
+                )::INT as distance
+            FROM images
+            WHERE chat_id = ANY($7::BIGINT[])
+                AND deleted_at IS NULL
+                AND stale_at IS NULL
+                AND ($6 OR NOT low_entropy)
+                AND ($4::INT IS NULL OR chat_id != $2 OR message_id != $4)
+        ) candidates
+        WHERE distance <= $3
+        ORDER BY distance ASC, (chat_id = $2) ASC, message_id ASC
         LIMIT 1
         "#,
         hash,
         chat_id,
         threshold as i32,
-        exclude_message_id
+        exclude_message_id,
+        alt_hash,
+        include_low_entropy,
+        &sources
     )
-    .fetch_optional(pool)
+    .fetch_optional(&mut *conn)
     .await?;
 
     Ok(record.map(|r| ClosestMatch {
+        chat_id: r.chat_id,
+        chat_title: r.chat_title,
         message_id: r.message_id,
-        distance: r.distance.unwrap() as u8,
+        distance: r.distance as u8,
+        spoiler: r.spoiler,
+        created_at: r.created_at,
+        sender_id: r.sender_id,
     }))
 }
 
-pub async fn save_image(
-    pool: &PgPool,
+/// Advisory lock key space of [`lock_chat_index`], apart from the single key ones like
+/// `ensure_image_partition`'s.
+const INDEX_LOCK_SPACE: i32 = 1;
+
+/// Holds off anyone else checking a new image of the chat against its index and inserting it
+/// until the transaction ends, so of two copies sent at the same time the second sees the
+/// first. Unrelated chats can share a lock, which only makes them wait for each other.
+pub async fn lock_chat_index(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     chat_id: i64,
-    chat_title: &str,
-    message_id: i32,
-    phash: i64,
 ) -> sqlx::Result<()> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1, $2)")
+        .bind(INDEX_LOCK_SPACE)
+        .bind((chat_id ^ (chat_id >> 32)) as i32)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// An indexed image near a hash, see [`nearest_images`].
+#[derive(Debug, sqlx::FromRow)]
+pub struct Candidate {
+    pub chat_id: i64,
+    pub message_id: i32,
+    pub phash: i64,
+    pub alt_phash: Option<i64>,
+    #[sqlx(try_from = "i32")]
+    pub distance: u8,
+    pub low_entropy: bool,
+}
+
+/// The `limit` images closest to the hash among those [`find_closest_match`] considers, in
+/// the same order, regardless of any threshold and low-entropy ones included.
+pub async fn nearest_images(
+    conn: &mut PgConnection,
+    chat_id: i64,
+    hash: i64,
+    alt_hash: Option<i64>,
+    exclude_message_id: Option<i32>,
+    limit: i64,
+) -> sqlx::Result<Vec<Candidate>> {
+    let sources = match_sources(&mut *conn, chat_id).await?;
+
+    sqlx::query_as(
+        r#"
+        SELECT
+            chat_id,
+            message_id,
+            phash,
+            alt_phash,
+            LEAST(
+                bit_count( (phash # $1)::bit(64) ),
+                bit_count( (alt_phash # $4)::bit(64) )
+            )::INT as distance,
+            low_entropy
+        FROM images
+        WHERE chat_id = ANY($6)
+            AND deleted_at IS NULL
+            AND stale_at IS NULL
+            AND ($3::INT IS NULL OR chat_id != $2 OR message_id != $3)
+        ORDER BY distance ASC, (chat_id = $2) ASC, message_id ASC
+        LIMIT $5
+        "#,
+    )
+    .bind(hash)
+    .bind(chat_id)
+    .bind(exclude_message_id)
+    .bind(alt_hash)
+    .bind(limit)
+    .bind(&sources)
+    .fetch_all(&mut *conn)
+    .await
+}
+
+/// The chats whose images count as originals for the chat's, itself included. The images
+/// queries take these as a list rather than a subquery, so Postgres only plans the partitions
+/// of those chats.
+async fn match_sources<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    chat_id: i64,
+) -> sqlx::Result<Vec<i64>> {
+    sqlx::query_scalar("SELECT source FROM match_sources($1)")
+        .bind(chat_id)
+        .fetch_all(executor)
+        .await
+}
+
+/// Every hash [`find_closest_match`] compares against for the chat, alternates included,
+/// along with the chats they come from.
+pub async fn chat_hashes(pool: &PgPool, chat_id: i64) -> sqlx::Result<(Vec<i64>, Vec<i64>)> {
+    let sources = match_sources(pool, chat_id).await?;
+
+    let hashes = sqlx::query_scalar(
+        r#"
+        SELECT phash FROM images WHERE chat_id = ANY($1) AND deleted_at IS NULL
+        UNION ALL
+        SELECT alt_phash FROM images
+        WHERE chat_id = ANY($1) AND alt_phash IS NOT NULL AND deleted_at IS NULL
+        "#,
+    )
+    .bind(&sources)
+    .fetch_all(pool)
+    .await?;
+
+    Ok((sources, hashes))
+}
+
+/// Replaces the networks of chats matched against each other with the configured ones.
+pub async fn set_chat_networks(pool: &PgPool, networks: &[(&str, &[i64])]) -> sqlx::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM chat_networks")
+        .execute(&mut *tx)
+        .await?;
+
+    for (network, chat_ids) in networks {
+        sqlx::query(
+            r#"
+            INSERT INTO chat_networks (network, chat_id)
+            SELECT $1, * FROM UNNEST($2::BIGINT[])
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(network)
+        .bind(chat_ids)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await
+}
+
+/// Remembers that `group_id` is the discussion group of `channel_id`. Returns `false` if
+/// they were already linked.
+pub async fn link_chats(pool: &PgPool, group_id: i64, channel_id: i64) -> sqlx::Result<bool> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO chat_links (group_id, channel_id)
+        VALUES ($1, $2)
+        ON CONFLICT (group_id) DO UPDATE SET channel_id = EXCLUDED.channel_id
+        WHERE chat_links.channel_id != EXCLUDED.channel_id
+        "#,
+    )
+    .bind(group_id)
+    .bind(channel_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// An image about to be added to the index.
+pub struct NewImage<'a> {
+    pub chat_id: i64,
+    /// Only stored if the chat isn't known yet, see [`set_chat_title`].
+    pub chat_title: &'a str,
+    pub message_id: i32,
+    pub phash: i64,
+    /// Hash of a smaller rendition, see [`crate::messenger::IncomingImage::alternate`].
+    pub alt_phash: Option<i64>,
+    pub media_key: Option<&'a str>,
+    pub media_ref: Option<&'a str>,
+    /// See [`crate::messenger::IncomingImage::sender_id`].
+    pub sender_id: Option<i64>,
+    pub forward: Option<ForwardOrigin>,
+    pub spoiler: bool,
+    /// See [`crate::hashing::Hasher::is_low_entropy`].
+    pub low_entropy: bool,
+    /// Where the image came from, [`LIVE_SOURCE`] or an [`import_source`].
+    pub source: &'a str,
+    /// See [`crate::messenger::IncomingImage::caption`].
+    pub caption: Option<&'a str>,
+}
+
+/// Source of images the bot indexed as they were sent.
+pub const LIVE_SOURCE: &str = "live";
+
+/// Source of images imported from `name`.
+pub fn import_source(name: &str) -> String {
+    format!("import:{name}")
+}
+
+/// Takes a connection rather than anything to acquire one from, as a generic `Acquire` keeps
+/// the bot's handler futures from being `Send`.
+pub async fn save_image(conn: &mut PgConnection, image: &NewImage<'_>) -> sqlx::Result<()> {
+    save_images(conn, std::slice::from_ref(image)).await
+}
+
+/// Indexes the images in one statement, adding the chats they're in if those aren't known yet.
+/// No message may be among them twice, as an upsert can't touch the same row twice.
+///
+/// Inserting an image that's already indexed updates it, as when an export is imported again.
+/// A message that comes up again evidently still exists, so it isn't stale anymore. The source
+/// stays the first one, so pruning an import never takes live images with it. Imports leave
+/// images the bot indexed live alone, as those have details an export lacks, like spoilers
+/// and the alternative hash.
+pub async fn save_images(conn: &mut PgConnection, images: &[NewImage<'_>]) -> sqlx::Result<()> {
+    let mut chat_ids = images.iter().map(|x| x.chat_id).collect::<Vec<_>>();
+    chat_ids.sort();
+    chat_ids.dedup();
+    for chat_id in chat_ids {
+        ensure_partition(&mut *conn, chat_id).await?;
+    }
+
     sqlx::query!(
         r#"
-        -- First, ensure the chat exists or update its title
+        -- First, ensure the chats exist, their titles are kept up to date elsewhere
         WITH ensure_chat AS (
             INSERT INTO chats (id, title)
-            VALUES ($1, $2)
-            ON CONFLICT (id) DO UPDATE
-            SET title = EXCLUDED.title
+            SELECT DISTINCT ON (id) id, title FROM UNNEST($1::BIGINT[], $2::TEXT[]) AS x (id, title)
+            ON CONFLICT (id) DO NOTHING
+        )
+        -- Then, insert the image records
+        INSERT INTO images (
+            chat_id, message_id, phash, alt_phash, media_key, media_ref, sender_id,
+            forward_from_id, forward_message_id, spoiler, low_entropy, source, caption
         )
-        -- Then, insert the image record
-        INSERT INTO images (chat_id, message_id, phash)
-        VALUES ($1, $3, $4)
+        SELECT * FROM UNNEST(
+            $1::BIGINT[], $3::INTEGER[], $4::BIGINT[], $5::BIGINT[], $6::TEXT[], $7::TEXT[],
+            $8::BIGINT[], $9::BIGINT[], $10::INTEGER[], $11::BOOLEAN[], $12::BOOLEAN[],
+            $13::TEXT[], $14::TEXT[]
+        )
+        ON CONFLICT (chat_id, message_id) DO UPDATE SET
+            phash = EXCLUDED.phash,
+            alt_phash = EXCLUDED.alt_phash,
+            media_key = COALESCE(EXCLUDED.media_key, images.media_key),
+            media_ref = COALESCE(EXCLUDED.media_ref, images.media_ref),
+            sender_id = COALESCE(EXCLUDED.sender_id, images.sender_id),
+            forward_from_id = COALESCE(EXCLUDED.forward_from_id, images.forward_from_id),
+            forward_message_id = COALESCE(EXCLUDED.forward_message_id, images.forward_message_id),
+            spoiler = EXCLUDED.spoiler,
+            low_entropy = EXCLUDED.low_entropy,
+            caption = COALESCE(EXCLUDED.caption, images.caption),
+            source = CASE WHEN images.deleted_at IS NULL THEN images.source ELSE EXCLUDED.source END,
+            stale_at = NULL,
+            deleted_at = NULL,
+            deleted_by = NULL
+        WHERE images.source <> 'live' OR EXCLUDED.source = 'live' OR images.deleted_at IS NOT NULL
+        "#,
+        &column(images, |x| x.chat_id),
+        &column(images, |x| x.chat_title) as &[&str],
+        &column(images, |x| x.message_id),
+        &column(images, |x| x.phash),
+        &column(images, |x| x.alt_phash) as &[Option<_>],
+        &column(images, |x| x.media_key) as &[Option<_>],
+        &column(images, |x| x.media_ref) as &[Option<_>],
+        &column(images, |x| x.sender_id) as &[Option<_>],
+        &column(images, |x| x.forward.and_then(|x| x.from_id)) as &[Option<_>],
+        &column(images, |x| x.forward.and_then(|x| x.message_id)) as &[Option<_>],
+        &column(images, |x| x.spoiler),
+        &column(images, |x| x.low_entropy),
+        &column(images, |x| x.source) as &[&str],
+        &column(images, |x| x.caption) as &[Option<_>]
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// One column of the images for `UNNEST`.
+fn column<'a, T>(images: &[NewImage<'a>], f: impl Fn(&NewImage<'a>) -> T) -> Vec<T> {
+    images.iter().map(f).collect()
+}
+
+/// Creates the chat's partition of the images table unless it has one already.
+pub async fn ensure_partition<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    chat_id: i64,
+) -> sqlx::Result<()> {
+    sqlx::query("SELECT ensure_image_partition($1)")
+        .bind(chat_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes everything stored about the chat, its images, sightings, settings and queued
+/// replies, along with its partition. Returns how many images it had.
+pub async fn delete_chat(pool: &PgPool, chat_id: i64) -> sqlx::Result<u64> {
+    let mut tx = pool.begin().await?;
+    let images = delete_chat_in(&mut tx, chat_id).await?;
+    tx.commit().await?;
+
+    Ok(images)
+}
+
+async fn delete_chat_in(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    chat_id: i64,
+) -> sqlx::Result<u64> {
+    let images = sqlx::query("DELETE FROM images WHERE chat_id = $1")
+        .bind(chat_id)
+        .execute(&mut **tx)
+        .await?
+        .rows_affected();
+
+    // Sightings and enforcement actions go with the chat.
+    sqlx::query("DELETE FROM chats WHERE id = $1")
+        .bind(chat_id)
+        .execute(&mut **tx)
+        .await?;
+
+    for table in [
+        "outbox",
+        "whitelisted_users",
+        "claimed_messages",
+        "import_runs",
+    ] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE chat_id = $1"))
+            .bind(chat_id)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    sqlx::query("DELETE FROM chat_links WHERE group_id = $1 OR channel_id = $1")
+        .bind(chat_id)
+        .execute(&mut **tx)
+        .await?;
+
+    // Named like in `ensure_image_partition`, which creates it again if the chat comes back.
+    let partition = format!("images_{}", chat_id.to_string().replace('-', "n"));
+    sqlx::query(&format!("DROP TABLE IF EXISTS {partition}"))
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(images)
+}
+
+/// Moves the images, sightings and import history stored under chat `from` to chat `to`, all
+/// at once, for when an export was imported with the wrong chat id. Of messages both have,
+/// the ones of `to` are kept, and so are its title and settings if it's known already. What
+/// else was stored about `from` is deleted with it. Returns how many images were moved.
+pub async fn remap_chat(pool: &PgPool, from: i64, to: i64) -> sqlx::Result<u64> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO chats (id, title)
+        SELECT $2, title FROM chats WHERE id = $1
+        ON CONFLICT (id) DO NOTHING
+        "#,
+    )
+    .bind(from)
+    .bind(to)
+    .execute(&mut *tx)
+    .await?;
+    ensure_partition(&mut *tx, to).await?;
+
+    for table in ["images", "sightings"] {
+        sqlx::query(&format!(
+            r#"
+            DELETE FROM {table} a
+            USING {table} b
+            WHERE a.chat_id = $1 AND b.chat_id = $2 AND a.message_id = b.message_id
+            "#
+        ))
+        .bind(from)
+        .bind(to)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    // Rows of the images table move to the partition of `to` on their own.
+    let images = sqlx::query("UPDATE images SET chat_id = $2 WHERE chat_id = $1")
+        .bind(from)
+        .bind(to)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    for table in [
+        "sightings",
+        "shadow_sightings",
+        "enforcement_actions",
+        "import_runs",
+        "audit_log",
+    ] {
+        sqlx::query(&format!(
+            "UPDATE {table} SET chat_id = $2 WHERE chat_id = $1"
+        ))
+        .bind(from)
+        .bind(to)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    // Sightings only record originals in another chat, enforcement actions every one.
+    sqlx::query(
+        "UPDATE sightings SET original_chat_id = NULLIF($2, chat_id) WHERE original_chat_id = $1",
+    )
+    .bind(from)
+    .bind(to)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("UPDATE enforcement_actions SET original_chat_id = $2 WHERE original_chat_id = $1")
+        .bind(from)
+        .bind(to)
+        .execute(&mut *tx)
+        .await?;
+
+    delete_chat_in(&mut tx, from).await?;
+    tx.commit().await?;
+
+    Ok(images)
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ImageMedia {
+    pub media_key: Option<String>,
+    pub media_ref: Option<String>,
+}
+
+/// Where the indexed image's bytes can be found again, if anywhere.
+pub async fn image_media(
+    pool: &PgPool,
+    chat_id: i64,
+    message_id: i32,
+) -> sqlx::Result<Option<ImageMedia>> {
+    sqlx::query_as(
+        "SELECT media_key, media_ref FROM images WHERE chat_id = $1 AND message_id = $2 LIMIT 1",
+    )
+    .bind(chat_id)
+    .bind(message_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Records that the bot was added to the chat, or is back in it.
+pub async fn chat_added(pool: &PgPool, chat_id: i64, title: &str, kind: &str) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO chats (id, title, type)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (id) DO UPDATE
+        SET title = EXCLUDED.title, type = EXCLUDED.type, added_at = NOW(), removed_at = NULL
+        "#,
+    )
+    .bind(chat_id)
+    .bind(title)
+    .bind(kind)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Stores the chat's new title, the one shown everywhere from then on.
+pub async fn set_chat_title(pool: &PgPool, chat_id: i64, title: &str) -> sqlx::Result<()> {
+    sqlx::query("UPDATE chats SET title = $2 WHERE id = $1")
+        .bind(chat_id)
+        .bind(title)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Records that the bot left or was removed from the chat. What was indexed in it stays.
+pub async fn chat_removed(pool: &PgPool, chat_id: i64) -> sqlx::Result<()> {
+    sqlx::query("UPDATE chats SET removed_at = NOW() WHERE id = $1")
+        .bind(chat_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Ids of the chat's messages that are indexed or recorded as sightings, as of any source.
+/// Deleted images don't count, so importing again brings back the ones of a pruned source.
+pub async fn known_message_ids(pool: &PgPool, chat_id: i64) -> sqlx::Result<HashSet<i32>> {
+    let ids: Vec<i32> = sqlx::query_scalar(
+        r#"
+        SELECT message_id FROM images WHERE chat_id = $1 AND deleted_at IS NULL
+        UNION
+        SELECT message_id FROM sightings WHERE chat_id = $1
+        "#,
+    )
+    .bind(chat_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(ids.into_iter().collect())
+}
+
+pub async fn chat_title(pool: &PgPool, chat_id: i64) -> sqlx::Result<Option<String>> {
+    sqlx::query_scalar("SELECT title FROM chats WHERE id = $1")
+        .bind(chat_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Returns the chat's own similarity threshold, if one was set.
+pub async fn chat_threshold(pool: &PgPool, chat_id: i64) -> sqlx::Result<Option<u8>> {
+    let threshold: Option<Option<i16>> =
+        sqlx::query_scalar("SELECT similarity_threshold FROM chats WHERE id = $1")
+            .bind(chat_id)
+            .fetch_optional(pool)
+            .await?;
+
+    Ok(threshold.flatten().map(|x| x as u8))
+}
+
+/// The chat's timezone and locale, as set with [`set_chat_locale`].
+pub async fn chat_locale(
+    pool: &PgPool,
+    chat_id: i64,
+) -> sqlx::Result<(Option<String>, Option<String>)> {
+    let row: Option<(Option<String>, Option<String>)> =
+        sqlx::query_as("SELECT timezone, locale FROM chats WHERE id = $1")
+            .bind(chat_id)
+            .fetch_optional(pool)
+            .await?;
+
+    Ok(row.unwrap_or_default())
+}
+
+/// Sets whichever of the two is given, `Some(None)` resets it to the default.
+pub async fn set_chat_locale(
+    pool: &PgPool,
+    chat_id: i64,
+    timezone: Option<Option<&str>>,
+    locale: Option<Option<&str>>,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE chats SET
+            timezone = CASE WHEN $2 THEN $3 ELSE timezone END,
+            locale = CASE WHEN $4 THEN $5 ELSE locale END
+        WHERE id = $1
+        "#,
+    )
+    .bind(chat_id)
+    .bind(timezone.is_some())
+    .bind(timezone.flatten())
+    .bind(locale.is_some())
+    .bind(locale.flatten())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// What the /settings menu shows and changes.
+#[derive(Debug, sqlx::FromRow)]
+pub struct ChatSettings {
+    pub similarity_threshold: Option<i16>,
+    /// Duplicates are recorded but not replied to.
+    pub silent: bool,
+    /// The escalation policy applies.
+    pub enforce: bool,
+    pub locale: Option<String>,
+    /// Duplicate notices are deleted after this long, see [`crate::notices`].
+    pub notice_lifetime_secs: Option<i32>,
+    /// Duplicate notices are deleted along with the repost.
+    pub delete_notices_with_repost: bool,
+}
+
+/// `None` if nothing was indexed in the chat yet.
+pub async fn chat_settings(pool: &PgPool, chat_id: i64) -> sqlx::Result<Option<ChatSettings>> {
+    sqlx::query_as(
+        r#"
+        SELECT similarity_threshold, silent, enforce, locale, notice_lifetime_secs,
+            delete_notices_with_repost
+        FROM chats WHERE id = $1
+        "#,
+    )
+    .bind(chat_id)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn set_chat_notice_cleanup(
+    pool: &PgPool,
+    chat_id: i64,
+    lifetime_secs: Option<i32>,
+    with_repost: bool,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "UPDATE chats SET notice_lifetime_secs = $2, delete_notices_with_repost = $3 WHERE id = $1",
+    )
+    .bind(chat_id)
+    .bind(lifetime_secs)
+    .bind(with_repost)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_chat_silent(pool: &PgPool, chat_id: i64, silent: bool) -> sqlx::Result<()> {
+    sqlx::query("UPDATE chats SET silent = $2 WHERE id = $1")
+        .bind(chat_id)
+        .bind(silent)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn set_chat_enforce(pool: &PgPool, chat_id: i64, enforce: bool) -> sqlx::Result<()> {
+    sqlx::query("UPDATE chats SET enforce = $2 WHERE id = $1")
+        .bind(chat_id)
+        .bind(enforce)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn chat_min_repost_age(pool: &PgPool, chat_id: i64) -> sqlx::Result<Option<i32>> {
+    let age: Option<Option<i32>> =
+        sqlx::query_scalar("SELECT min_repost_age_secs FROM chats WHERE id = $1")
+            .bind(chat_id)
+            .fetch_optional(pool)
+            .await?;
+
+    Ok(age.flatten())
+}
+
+/// Only affects chats that already have something indexed, like [`set_chat_locale`].
+pub async fn set_chat_min_repost_age(
+    pool: &PgPool,
+    chat_id: i64,
+    secs: Option<i32>,
+) -> sqlx::Result<()> {
+    sqlx::query("UPDATE chats SET min_repost_age_secs = $2 WHERE id = $1")
+        .bind(chat_id)
+        .bind(secs)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn set_chat_threshold(
+    pool: &PgPool,
+    chat_id: i64,
+    threshold: Option<u8>,
+) -> sqlx::Result<()> {
+    sqlx::query("UPDATE chats SET similarity_threshold = $2 WHERE id = $1")
+        .bind(chat_id)
+        .bind(threshold.map(i16::from))
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Records the image as a duplicate of the match. A message that's a sighting already, e.g.
+/// one the importer and the bot both came across, is left as it is.
+pub async fn save_sighting<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    image: &NewImage<'_>,
+    closest_match: &ClosestMatch,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        WITH matched AS (
+            UPDATE images SET last_matched_at = NOW()
+            WHERE chat_id = $10 AND message_id = $3
+        )
+        INSERT INTO sightings (
+            chat_id, message_id, original_message_id, distance, media_key, sender_id,
+            forward_from_id, forward_message_id, original_chat_id, media_ref
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $11)
+        ON CONFLICT (chat_id, message_id) DO NOTHING
+        "#,
+    )
+    .bind(image.chat_id)
+    .bind(image.message_id)
+    .bind(closest_match.message_id)
+    .bind(closest_match.distance as i16)
+    .bind(image.media_key)
+    .bind(image.sender_id)
+    .bind(image.forward.and_then(|x| x.from_id))
+    .bind(image.forward.and_then(|x| x.message_id))
+    .bind((closest_match.chat_id != image.chat_id).then_some(closest_match.chat_id))
+    .bind(closest_match.chat_id)
+    .bind(image.media_ref)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Records a message the shadow threshold judged differently than the live one.
+pub async fn save_shadow_sighting(
+    pool: &PgPool,
+    image: &NewImage<'_>,
+    shadow_match: Option<&ClosestMatch>,
+    shadow_threshold: u8,
+    live_flagged: bool,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO shadow_sightings
+            (chat_id, message_id, original_message_id, distance, shadow_threshold, live_flagged)
+        VALUES ($1, $2, $3, $4, $5, $6)
         "#,
-        chat_id,
-        chat_title,
-        message_id,
-        phash
     )
+    .bind(image.chat_id)
+    .bind(image.message_id)
+    .bind(shadow_match.map(|x| x.message_id))
+    .bind(shadow_match.map(|x| x.distance as i16))
+    .bind(shadow_threshold as i16)
+    .bind(live_flagged)
     .execute(pool)
     .await?;
 
     Ok(())
 }
+
+/// Claims a message for this replica. Returns `false` if another one already did.
+pub async fn claim_message(
+    pool: &PgPool,
+    bot_id: i64,
+    chat_id: i64,
+    message_id: i32,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO claimed_messages (bot_id, chat_id, message_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(bot_id)
+    .bind(chat_id)
+    .bind(message_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// What an import run did with the export's messages.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ImportCounts {
+    /// Hashed and indexed.
+    pub hashed: i32,
+    /// Not images, or indexed already.
+    pub skipped: i32,
+    /// Images that couldn't be read or hashed.
+    pub failed: i32,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ImportRun {
+    pub id: Uuid,
+    pub source: String,
+    pub chat_id: i64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub hashed: i32,
+    pub skipped: i32,
+    pub failed: i32,
+    pub error: Option<String>,
+}
+
+pub async fn start_import_run(pool: &PgPool, source: &str, chat_id: i64) -> sqlx::Result<Uuid> {
+    sqlx::query_scalar("INSERT INTO import_runs (source, chat_id) VALUES ($1, $2) RETURNING id")
+        .bind(source)
+        .bind(chat_id)
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn finish_import_run(
+    pool: &PgPool,
+    id: Uuid,
+    counts: &ImportCounts,
+    error: Option<&str>,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE import_runs
+        SET finished_at = NOW(), hashed = $2, skipped = $3, failed = $4, error = $5
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .bind(counts.hashed)
+    .bind(counts.skipped)
+    .bind(counts.failed)
+    .bind(error)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The latest import runs, of one chat or all of them.
+pub async fn import_runs(
+    pool: &PgPool,
+    chat_id: Option<i64>,
+    limit: i64,
+) -> sqlx::Result<Vec<ImportRun>> {
+    sqlx::query_as(
+        r#"
+        SELECT id, source, chat_id, started_at, finished_at, hashed, skipped, failed, error
+        FROM import_runs
+        WHERE $1::BIGINT IS NULL OR chat_id = $1
+        ORDER BY started_at DESC
+        LIMIT $2
+        "#,
+    )
+    .bind(chat_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+/// When each scheduled job last ran, by name.
+pub async fn job_runs(pool: &PgPool) -> sqlx::Result<HashMap<String, DateTime<Utc>>> {
+    let rows: Vec<(String, DateTime<Utc>)> =
+        sqlx::query_as("SELECT name, last_run_at FROM scheduled_jobs")
+            .fetch_all(pool)
+            .await?;
+
+    Ok(rows.into_iter().collect())
+}
+
+/// Takes the run of the job due at `due`, returning `false` if it already ran for that
+/// time or later.
+pub async fn claim_job_run(pool: &PgPool, name: &str, due: DateTime<Utc>) -> sqlx::Result<bool> {
+    let claimed: Option<String> = sqlx::query_scalar(
+        r#"
+        INSERT INTO scheduled_jobs (name, last_run_at) VALUES ($1, $2)
+        ON CONFLICT (name) DO UPDATE
+        SET last_run_at = EXCLUDED.last_run_at, finished_at = NULL
+        WHERE scheduled_jobs.last_run_at < EXCLUDED.last_run_at
+        RETURNING name
+        "#,
+    )
+    .bind(name)
+    .bind(due)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(claimed.is_some())
+}
+
+pub async fn finish_job_run(pool: &PgPool, name: &str, error: Option<&str>) -> sqlx::Result<()> {
+    sqlx::query("UPDATE scheduled_jobs SET finished_at = NOW(), last_error = $2 WHERE name = $1")
+        .bind(name)
+        .bind(error)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Forgets claims older than `max_age_secs`, Telegram doesn't redeliver updates that old anyway.
+pub async fn prune_claims(pool: &PgPool, max_age_secs: i64) -> sqlx::Result<u64> {
+    let result = sqlx::query(
+        "DELETE FROM claimed_messages WHERE claimed_at < NOW() - make_interval(secs => $1)",
+    )
+    .bind(max_age_secs as f64)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ChatStats {
+    pub id: i64,
+    pub title: String,
+    /// `group`, `supergroup` or `channel`, unknown for chats the bot hasn't been added to
+    /// since that was recorded.
+    pub kind: Option<String>,
+    /// When the bot left or was removed from the chat, if it's not in it anymore.
+    pub removed_at: Option<DateTime<Utc>>,
+    pub similarity_threshold: Option<i16>,
+    pub images: i64,
+    pub low_entropy: i64,
+    pub sightings: i64,
+    pub false_positives: i64,
+    /// When the oldest and newest indexed images were indexed.
+    pub oldest: Option<DateTime<Utc>>,
+    pub newest: Option<DateTime<Utc>>,
+}
+
+pub async fn chat_stats(pool: &PgPool) -> sqlx::Result<Vec<ChatStats>> {
+    sqlx::query_as(
+        r#"
+        SELECT
+            c.id,
+            c.title,
+            c.type AS kind,
+            c.removed_at,
+            c.similarity_threshold,
+            (SELECT COUNT(*) FROM images i WHERE i.chat_id = c.id AND i.deleted_at IS NULL) AS images,
+            (SELECT COUNT(*) FROM images i
+                WHERE i.chat_id = c.id AND i.deleted_at IS NULL AND i.low_entropy) AS low_entropy,
+            (SELECT COUNT(*) FROM sightings s WHERE s.chat_id = c.id) AS sightings,
+            (SELECT COUNT(*) FROM sightings s WHERE s.chat_id = c.id AND s.false_positive) AS false_positives,
+            (SELECT MIN(created_at) FROM images i WHERE i.chat_id = c.id AND i.deleted_at IS NULL) AS oldest,
+            (SELECT MAX(created_at) FROM images i WHERE i.chat_id = c.id AND i.deleted_at IS NULL) AS newest
+        FROM chats c
+        ORDER BY c.title
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Number of sightings at each distance, of one chat or all of them, false positives aside.
+pub async fn sighting_distances(
+    pool: &PgPool,
+    chat_id: Option<i64>,
+) -> sqlx::Result<Vec<(i16, i64)>> {
+    sqlx::query_as(
+        r#"
+        SELECT distance, COUNT(*) FROM sightings
+        WHERE ($1::BIGINT IS NULL OR chat_id = $1) AND NOT false_positive
+        GROUP BY distance
+        ORDER BY distance
+        "#,
+    )
+    .bind(chat_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Images indexed and duplicates detected in a month.
+#[derive(Debug, sqlx::FromRow)]
+pub struct MonthlyGrowth {
+    pub month: DateTime<Utc>,
+    pub images: i64,
+    pub sightings: i64,
+}
+
+/// Month by month, oldest first, of one chat or all of them. Deleted images still count for
+/// the month they were indexed in.
+pub async fn monthly_growth(
+    pool: &PgPool,
+    chat_id: Option<i64>,
+) -> sqlx::Result<Vec<MonthlyGrowth>> {
+    sqlx::query_as(
+        r#"
+        SELECT
+            month,
+            COALESCE(i.images, 0) AS images,
+            COALESCE(s.sightings, 0) AS sightings
+        FROM (
+            SELECT date_trunc('month', created_at) AS month, COUNT(*) AS images
+            FROM images
+            WHERE $1::BIGINT IS NULL OR chat_id = $1
+            GROUP BY 1
+        ) i
+        FULL JOIN (
+            SELECT date_trunc('month', created_at) AS month, COUNT(*) AS sightings
+            FROM sightings
+            WHERE ($1::BIGINT IS NULL OR chat_id = $1) AND NOT false_positive
+            GROUP BY 1
+        ) s USING (month)
+        ORDER BY month
+        "#,
+    )
+    .bind(chat_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Size and health of a table, partitions included.
+#[derive(Debug, sqlx::FromRow)]
+pub struct TableStats {
+    pub name: String,
+    /// On disk, indexes and TOAST included.
+    pub total_bytes: i64,
+    pub index_bytes: i64,
+    /// Estimated by Postgres' statistics rather than counted.
+    pub live_rows: i64,
+    /// Rows deleted or updated but not vacuumed yet, which bloat the table and its indexes.
+    pub dead_rows: i64,
+}
+
+/// Every table of the schema, largest first.
+pub async fn table_stats(pool: &PgPool) -> sqlx::Result<Vec<TableStats>> {
+    // Partitions of the images table are summed up under it.
+    sqlx::query_as(
+        r#"
+        SELECT
+            COALESCE(parent.relname, c.relname)::TEXT AS name,
+            SUM(pg_total_relation_size(c.oid))::BIGINT AS total_bytes,
+            SUM(pg_indexes_size(c.oid))::BIGINT AS index_bytes,
+            SUM(s.n_live_tup)::BIGINT AS live_rows,
+            SUM(s.n_dead_tup)::BIGINT AS dead_rows
+        FROM pg_stat_user_tables s
+        JOIN pg_class c ON c.oid = s.relid
+        LEFT JOIN pg_inherits i ON i.inhrelid = c.oid
+        LEFT JOIN pg_class parent ON parent.oid = i.inhparent
+        WHERE s.schemaname = current_schema()
+        GROUP BY 1
+        ORDER BY 2 DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct PinnedStats {
+    pub chat_id: i64,
+    pub stats_message_id: Option<i32>,
+    pub images: i64,
+    /// Sightings since the start of the month, not counting false positives.
+    pub sightings_this_month: i64,
+}
+
+pub async fn pinned_stats(pool: &PgPool) -> sqlx::Result<Vec<PinnedStats>> {
+    sqlx::query_as(
+        r#"
+        SELECT
+            c.id AS chat_id,
+            c.stats_message_id,
+            (SELECT COUNT(*) FROM images i WHERE i.chat_id = c.id AND i.deleted_at IS NULL) AS images,
+            (SELECT COUNT(*) FROM sightings s
+                WHERE s.chat_id = c.id
+                    AND NOT s.false_positive
+                    AND s.created_at >= date_trunc('month', NOW())) AS sightings_this_month
+        FROM chats c
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn set_stats_message(pool: &PgPool, chat_id: i64, message_id: i32) -> sqlx::Result<()> {
+    sqlx::query("UPDATE chats SET stats_message_id = $2 WHERE id = $1")
+        .bind(chat_id)
+        .bind(message_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct SenderStats {
+    /// Images that were new when they were posted.
+    pub originals: i64,
+    /// Posts detected as duplicates, not counting false positives.
+    pub reposts: i64,
+}
+
+/// What the sender (see [`crate::messenger::IncomingImage::sender_id`]) posted in the chat.
+pub async fn sender_stats(
+    pool: &PgPool,
+    chat_id: i64,
+    sender_id: i64,
+) -> sqlx::Result<SenderStats> {
+    sqlx::query_as(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM images
+                WHERE chat_id = $1 AND sender_id = $2 AND deleted_at IS NULL) AS originals,
+            (SELECT COUNT(*) FROM sightings
+                WHERE chat_id = $1 AND sender_id = $2 AND NOT false_positive) AS reposts
+        "#,
+    )
+    .bind(chat_id)
+    .bind(sender_id)
+    .fetch_one(pool)
+    .await
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Sighting {
+    pub id: Uuid,
+    pub chat_id: i64,
+    pub message_id: i32,
+    pub original_message_id: i32,
+    pub distance: i16,
+    pub false_positive: bool,
+    pub media_key: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Most recent sightings, optionally only in one chat.
+pub async fn recent_sightings(
+    pool: &PgPool,
+    chat_id: Option<i64>,
+    limit: i64,
+) -> sqlx::Result<Vec<Sighting>> {
+    sqlx::query_as(
+        r#"
+        SELECT id, chat_id, message_id, original_message_id, distance, false_positive, media_key,
+            created_at
+        FROM sightings
+        WHERE ($1::BIGINT IS NULL OR chat_id = $1)
+        ORDER BY created_at DESC
+        LIMIT $2
+        "#,
+    )
+    .bind(chat_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn set_false_positive(pool: &PgPool, id: Uuid, false_positive: bool) -> sqlx::Result<()> {
+    sqlx::query("UPDATE sightings SET false_positive = $2 WHERE id = $1")
+        .bind(id)
+        .bind(false_positive)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Image {
+    pub id: Uuid,
+    pub chat_id: i64,
+    pub message_id: i32,
+    pub phash: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn list_images(
+    pool: &PgPool,
+    chat_id: i64,
+    limit: i64,
+    offset: i64,
+) -> sqlx::Result<Vec<Image>> {
+    sqlx::query_as(
+        r#"
+        SELECT id, chat_id, message_id, phash, created_at
+        FROM images
+        WHERE chat_id = $1 AND deleted_at IS NULL
+        ORDER BY message_id DESC
+        LIMIT $2 OFFSET $3
+        "#,
+    )
+    .bind(chat_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct CaptionMatch {
+    pub id: Uuid,
+    pub message_id: i32,
+    pub caption: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The chat's indexed images whose caption contains `text`, ignoring case, newest first.
+pub async fn search_captions(
+    pool: &PgPool,
+    chat_id: i64,
+    text: &str,
+    limit: i64,
+) -> sqlx::Result<Vec<CaptionMatch>> {
+    // Taken literally, not as a pattern.
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    sqlx::query_as(
+        r#"
+        SELECT id, message_id, caption, created_at
+        FROM images
+        WHERE chat_id = $1 AND deleted_at IS NULL AND caption ILIKE '%' || $2 || '%'
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#,
+    )
+    .bind(chat_id)
+    .bind(escaped)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct SearchResult {
+    pub id: Uuid,
+    pub chat_id: i64,
+    pub chat_title: String,
+    pub message_id: i32,
+    pub caption: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Indexed images whose caption or chat title match `query`, in the syntax of web search
+/// engines (`"exact phrase"`, `or`, `-excluded`). Best matches of caption and title together
+/// come first, then the newest images.
+pub async fn search_archive(
+    pool: &PgPool,
+    chat_id: Option<i64>,
+    query: &str,
+    limit: i64,
+) -> sqlx::Result<Vec<SearchResult>> {
+    // One branch per match, an OR across both tables would keep either index from being used.
+    sqlx::query_as(
+        r#"
+        SELECT found.*
+        FROM (
+            SELECT i.id, i.chat_id, c.title AS chat_title, i.message_id, i.caption, i.created_at
+            FROM images i
+            JOIN chats c ON c.id = i.chat_id
+            WHERE ($1::BIGINT IS NULL OR i.chat_id = $1)
+                AND i.deleted_at IS NULL
+                AND to_tsvector('simple', COALESCE(i.caption, ''))
+                    @@ websearch_to_tsquery('simple', $2)
+            UNION
+            SELECT i.id, i.chat_id, c.title AS chat_title, i.message_id, i.caption, i.created_at
+            FROM chats c
+            JOIN images i ON i.chat_id = c.id
+            WHERE ($1::BIGINT IS NULL OR c.id = $1)
+                AND i.deleted_at IS NULL
+                AND to_tsvector('simple', c.title) @@ websearch_to_tsquery('simple', $2)
+        ) found,
+            websearch_to_tsquery('simple', $2) q
+        ORDER BY ts_rank(
+                to_tsvector('simple', COALESCE(found.caption, ''))
+                    || to_tsvector('simple', found.chat_title),
+                q
+            ) DESC,
+            found.created_at DESC
+        LIMIT $3
+        "#,
+    )
+    .bind(chat_id)
+    .bind(query)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+/// Takes the image out of the index. It's only marked as deleted until
+/// [`purge_deleted_images`] gets to it, so [`restore_image`] can bring it back. Returns its
+/// message, unless it was deleted already.
+pub async fn delete_image(
+    pool: &PgPool,
+    chat_id: i64,
+    id: Uuid,
+) -> sqlx::Result<Option<MessageRef>> {
+    let row: Option<(i64, i32)> = sqlx::query_as(
+        r#"
+        UPDATE images SET deleted_at = NOW(), deleted_by = 'forget'
+        WHERE id = $1 AND chat_id = $2 AND deleted_at IS NULL
+        RETURNING chat_id, message_id
+        "#,
+    )
+    .bind(id)
+    .bind(chat_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|(chat_id, message_id)| MessageRef {
+        chat_id,
+        message_id,
+    }))
+}
+
+/// Records that the image's message was still there.
+pub async fn mark_checked(pool: &PgPool, message: MessageRef) -> sqlx::Result<()> {
+    sqlx::query("UPDATE images SET checked_at = NOW() WHERE chat_id = $1 AND message_id = $2")
+        .bind(message.chat_id)
+        .bind(message.message_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Stops matching against an image whose message is gone, deleting it too if `purge` is set.
+pub async fn mark_stale(pool: &PgPool, message: MessageRef, purge: bool) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE images
+        SET stale_at = COALESCE(stale_at, NOW()),
+            deleted_at = CASE WHEN $3 THEN COALESCE(deleted_at, NOW()) ELSE deleted_at END,
+            deleted_by = CASE WHEN $3 AND deleted_at IS NULL THEN 'stale' ELSE deleted_by END
+        WHERE chat_id = $1 AND message_id = $2
+        "#,
+    )
+    .bind(message.chat_id)
+    .bind(message.message_id)
+    .bind(purge)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Sample of images still matched against, from the given chats or all if empty. Random
+/// unless `sweep` is set, which picks the least recently checked ones, see [`mark_checked`].
+pub async fn sample_live_images(
+    pool: &PgPool,
+    chats: &[i64],
+    limit: i64,
+    sweep: bool,
+) -> sqlx::Result<Vec<MessageRef>> {
+    let order = match sweep {
+        true => "checked_at ASC NULLS FIRST",
+        false => "random()",
+    };
+    let rows: Vec<(i64, i32)> = sqlx::query_as(&format!(
+        r#"
+        SELECT chat_id, message_id FROM images
+        WHERE deleted_at IS NULL AND stale_at IS NULL
+            AND (cardinality($1::BIGINT[]) = 0 OR chat_id = ANY($1))
+        ORDER BY {order}
+        LIMIT $2
+        "#
+    ))
+    .bind(chats)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(chat_id, message_id)| MessageRef {
+            chat_id,
+            message_id,
+        })
+        .collect())
+}
+
+/// Undoes [`delete_image`], unless the image was purged in the meantime. Returns its
+/// message if it was restored.
+pub async fn restore_image(
+    pool: &PgPool,
+    chat_id: i64,
+    id: Uuid,
+) -> sqlx::Result<Option<MessageRef>> {
+    let row: Option<(i64, i32)> = sqlx::query_as(
+        r#"
+        UPDATE images SET deleted_at = NULL, deleted_by = NULL
+        WHERE id = $1 AND chat_id = $2 AND deleted_at IS NOT NULL
+        RETURNING chat_id, message_id
+        "#,
+    )
+    .bind(id)
+    .bind(chat_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|(chat_id, message_id)| MessageRef {
+        chat_id,
+        message_id,
+    }))
+}
+
+/// Restores the image of the chat an admin forgot most recently, returning its message id.
+/// Images deleted for other reasons, like a pruned source, are left alone.
+pub async fn undo_last_delete(pool: &PgPool, chat_id: i64) -> sqlx::Result<Option<i32>> {
+    sqlx::query_scalar(
+        r#"
+        UPDATE images SET deleted_at = NULL, deleted_by = NULL
+        WHERE chat_id = $1 AND id = (
+            SELECT id FROM images
+            WHERE chat_id = $1 AND deleted_at IS NOT NULL AND deleted_by = 'forget'
+            ORDER BY deleted_at DESC
+            LIMIT 1
+        )
+        RETURNING message_id
+        "#,
+    )
+    .bind(chat_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Removes images deleted more than `max_age_secs` ago for good.
+pub async fn purge_deleted_images(pool: &PgPool, max_age_secs: i64) -> sqlx::Result<u64> {
+    let result =
+        sqlx::query("DELETE FROM images WHERE deleted_at < NOW() - make_interval(secs => $1)")
+            .bind(max_age_secs as f64)
+            .execute(pool)
+            .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Deletes the images of every chat beyond its newest or most recently matched
+/// `max_entries`, returning how many there were. Deleted images don't count towards the
+/// quota, they're purged anyway.
+pub async fn evict_over_quota(
+    pool: &PgPool,
+    max_entries: i64,
+    policy: EvictionPolicy,
+) -> sqlx::Result<u64> {
+    let result = sqlx::query(
+        r#"
+        WITH ranked AS (
+            SELECT
+                chat_id,
+                message_id,
+                ROW_NUMBER() OVER (
+                    PARTITION BY chat_id
+                    ORDER BY
+                        CASE WHEN $2 THEN COALESCE(last_matched_at, created_at) END DESC,
+                        created_at DESC,
+                        message_id DESC
+                ) AS rank
+            FROM images
+            WHERE deleted_at IS NULL
+        )
+        DELETE FROM images
+        USING ranked
+        WHERE images.chat_id = ranked.chat_id
+            AND images.message_id = ranked.message_id
+            AND ranked.rank > $1
+        "#,
+    )
+    .bind(max_entries)
+    .bind(policy == EvictionPolicy::LeastRecentlyMatched)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Number of images per source, most first.
+pub async fn image_sources(pool: &PgPool) -> sqlx::Result<Vec<(String, i64)>> {
+    sqlx::query_as(
+        r#"
+        SELECT source, COUNT(*) FROM images
+        WHERE deleted_at IS NULL
+        GROUP BY source
+        ORDER BY COUNT(*) DESC, source
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Deletes every image of the source, returning how many there were. They're removed for good
+/// by [`purge_deleted_images`] like any other deleted image, until then [`restore_source`]
+/// brings them back.
+pub async fn delete_source(pool: &PgPool, source: &str) -> sqlx::Result<u64> {
+    let result = sqlx::query(
+        r#"
+        UPDATE images SET deleted_at = NOW(), deleted_by = 'prune'
+        WHERE source = $1 AND deleted_at IS NULL
+        "#,
+    )
+    .bind(source)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Undoes [`delete_source`] for the images that weren't purged yet, returning how many
+/// there were. Images of the source deleted for other reasons stay deleted.
+pub async fn restore_source(pool: &PgPool, source: &str) -> sqlx::Result<u64> {
+    let result = sqlx::query(
+        r#"
+        UPDATE images SET deleted_at = NULL, deleted_by = NULL
+        WHERE source = $1 AND deleted_by = 'prune'
+        "#,
+    )
+    .bind(source)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// An indexed image and every later image that was detected as its duplicate.
+#[derive(Debug)]
+pub struct Cluster {
+    pub original_message_id: i32,
+    pub duplicates: Vec<Sighting>,
+}
+
+/// The chat's sightings grouped by the image they duplicate, biggest clusters first.
+/// False positives are left out.
+pub async fn duplicate_clusters(pool: &PgPool, chat_id: i64) -> sqlx::Result<Vec<Cluster>> {
+    let sightings: Vec<Sighting> = sqlx::query_as(
+        r#"
+        SELECT id, chat_id, message_id, original_message_id, distance, false_positive, media_key,
+            created_at
+        FROM sightings
+        WHERE chat_id = $1 AND NOT false_positive
+        ORDER BY original_message_id, message_id
+        "#,
+    )
+    .bind(chat_id)
+    .fetch_all(pool)
+    .await?;
+
+    let mut clusters: Vec<Cluster> = Vec::new();
+    for sighting in sightings {
+        match clusters.last_mut() {
+            Some(cluster) if cluster.original_message_id == sighting.original_message_id => {
+                cluster.duplicates.push(sighting)
+            }
+            _ => clusters.push(Cluster {
+                original_message_id: sighting.original_message_id,
+                duplicates: vec![sighting],
+            }),
+        }
+    }
+
+    clusters.sort_by_key(|x| std::cmp::Reverse(x.duplicates.len()));
+
+    Ok(clusters)
+}
+
+/// Reposts by the sender in the chat within the last `window_secs`, false positives aside.
+pub async fn recent_reposts(
+    pool: &PgPool,
+    chat_id: i64,
+    sender_id: i64,
+    window_secs: i64,
+) -> sqlx::Result<i64> {
+    sqlx::query_scalar(
+        r#"
+        SELECT COUNT(*) FROM sightings
+        WHERE chat_id = $1 AND sender_id = $2 AND NOT false_positive
+            AND created_at > NOW() - make_interval(secs => $3)
+        "#,
+    )
+    .bind(chat_id)
+    .bind(sender_id)
+    .bind(window_secs as f64)
+    .fetch_one(pool)
+    .await
+}
+
+/// An entry of the enforcement audit log.
+pub struct EnforcementRecord<'a> {
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub message_id: i32,
+    pub action: &'a str,
+    /// Only set for actions of the escalation policy.
+    pub reposts: Option<i64>,
+    pub restricted_until: Option<DateTime<Utc>>,
+    /// The admin behind a manual action.
+    pub actor_id: Option<i64>,
+    /// The match the repost was flagged for.
+    pub original: Option<(MessageRef, u8)>,
+}
+
+pub async fn save_enforcement(pool: &PgPool, record: &EnforcementRecord<'_>) -> sqlx::Result<Uuid> {
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO enforcement_actions (
+            chat_id, sender_id, message_id, action, reposts, restricted_until, actor_id,
+            original_chat_id, original_message_id, distance
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING id
+        "#,
+    )
+    .bind(record.chat_id)
+    .bind(record.sender_id)
+    .bind(record.message_id)
+    .bind(record.action)
+    .bind(record.reposts.map(|x| x as i32))
+    .bind(record.restricted_until)
+    .bind(record.actor_id)
+    .bind(record.original.map(|(x, _)| x.chat_id))
+    .bind(record.original.map(|(x, _)| x.message_id))
+    .bind(record.original.map(|(_, x)| x as i16))
+    .fetch_one(pool)
+    .await
+}
+
+/// An entry of the audit log, see [`crate::audit`].
+#[derive(Debug, sqlx::FromRow)]
+pub struct AuditEntry {
+    pub chat_id: Option<i64>,
+    pub actor_id: Option<i64>,
+    pub action: String,
+    pub payload: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn save_audit_entry(
+    pool: &PgPool,
+    chat_id: Option<i64>,
+    actor_id: Option<i64>,
+    action: &str,
+    payload: &serde_json::Value,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO audit_log (chat_id, actor_id, action, payload) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(chat_id)
+    .bind(actor_id)
+    .bind(action)
+    .bind(payload)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The latest audit log entries, of one chat or all of them.
+pub async fn audit_log(
+    pool: &PgPool,
+    chat_id: Option<i64>,
+    limit: i64,
+) -> sqlx::Result<Vec<AuditEntry>> {
+    sqlx::query_as(
+        r#"
+        SELECT chat_id, actor_id, action, payload, created_at
+        FROM audit_log
+        WHERE $1::BIGINT IS NULL OR chat_id = $1
+        ORDER BY created_at DESC
+        LIMIT $2
+        "#,
+    )
+    .bind(chat_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Appeal {
+    pub chat_id: i64,
+    pub message_id: i32,
+    pub action: String,
+    pub reposts: Option<i32>,
+    pub original_chat_id: Option<i64>,
+    pub original_message_id: Option<i32>,
+    pub distance: Option<i16>,
+    /// Enforcement actions against the sender in the chat so far, this one included.
+    pub enforcements: i64,
+}
+
+/// Marks the enforcement action as appealed by `sender_id`, who it was taken against.
+/// Returns `None` if it wasn't against them or was appealed already.
+pub async fn appeal(pool: &PgPool, id: Uuid, sender_id: i64) -> sqlx::Result<Option<Appeal>> {
+    sqlx::query_as(
+        r#"
+        UPDATE enforcement_actions a SET appealed_at = NOW()
+        WHERE id = $1 AND sender_id = $2 AND appealed_at IS NULL
+        RETURNING
+            chat_id, message_id, action, reposts, original_chat_id, original_message_id, distance,
+            (SELECT COUNT(*) FROM enforcement_actions e
+                WHERE e.chat_id = a.chat_id AND e.sender_id = a.sender_id
+                    AND e.actor_id IS NULL) AS enforcements
+        "#,
+    )
+    .bind(id)
+    .bind(sender_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Who [`whitelist_sender`] and friends are about.
+#[derive(Debug, Clone)]
+pub enum Whitelisted {
+    Sender(i64),
+    /// Lowercase, without the @.
+    Username(String),
+}
+
+pub async fn whitelist_sender(
+    pool: &PgPool,
+    chat_id: i64,
+    who: &Whitelisted,
+    added_by: Option<i64>,
+) -> sqlx::Result<()> {
+    let (sender_id, username) = match who {
+        Whitelisted::Sender(id) => (Some(*id), None),
+        Whitelisted::Username(name) => (None, Some(name.as_str())),
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO whitelisted_users (chat_id, sender_id, username, added_by)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(chat_id)
+    .bind(sender_id)
+    .bind(username)
+    .bind(added_by)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Returns `false` if they weren't whitelisted.
+pub async fn unwhitelist_sender(
+    pool: &PgPool,
+    chat_id: i64,
+    who: &Whitelisted,
+) -> sqlx::Result<bool> {
+    let (sender_id, username) = match who {
+        Whitelisted::Sender(id) => (Some(*id), None),
+        Whitelisted::Username(name) => (None, Some(name.as_str())),
+    };
+
+    let result = sqlx::query(
+        "DELETE FROM whitelisted_users WHERE chat_id = $1 AND (sender_id = $2 OR username = $3)",
+    )
+    .bind(chat_id)
+    .bind(sender_id)
+    .bind(username)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Whether a sender, known by id and possibly username, is whitelisted in the chat.
+pub async fn is_whitelisted(
+    pool: &PgPool,
+    chat_id: i64,
+    sender_id: i64,
+    username: Option<&str>,
+) -> sqlx::Result<bool> {
+    sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM whitelisted_users
+            WHERE chat_id = $1 AND (sender_id = $2 OR username = lower($3))
+        )
+        "#,
+    )
+    .bind(chat_id)
+    .bind(sender_id)
+    .bind(username)
+    .fetch_one(pool)
+    .await
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Reposted {
+    /// The chat itself, or its linked channel.
+    pub chat_id: i64,
+    pub message_id: i32,
+    pub reposts: i64,
+    pub media_key: Option<String>,
+    pub spoiler: bool,
+}
+
+/// The chat's most reposted images, not counting false positives.
+pub async fn most_reposted(pool: &PgPool, chat_id: i64, limit: i64) -> sqlx::Result<Vec<Reposted>> {
+    sqlx::query_as(
+        r#"
+        SELECT
+            r.chat_id,
+            r.message_id,
+            r.reposts,
+            i.media_key,
+            COALESCE(i.spoiler, FALSE) AS spoiler
+        FROM (
+            SELECT
+                COALESCE(original_chat_id, chat_id) AS chat_id,
+                original_message_id AS message_id,
+                COUNT(*) AS reposts
+            FROM sightings
+            WHERE chat_id = $1 AND NOT false_positive
+            GROUP BY 1, 2
+            ORDER BY reposts DESC, message_id
+            LIMIT $2
+        ) r
+        LEFT JOIN images i ON i.chat_id = r.chat_id AND i.message_id = r.message_id
+        ORDER BY r.reposts DESC, r.message_id
+        "#,
+    )
+    .bind(chat_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct HashMatch {
+    pub id: Uuid,
+    pub message_id: i32,
+    pub phash: i64,
+    pub distance: i32,
+}
+
+/// Indexed images of the chat within `max_distance` of the hash, closest first.
+pub async fn search_hash(
+    pool: &PgPool,
+    chat_id: i64,
+    hash: i64,
+    max_distance: u8,
+    limit: i64,
+) -> sqlx::Result<Vec<HashMatch>> {
+    sqlx::query_as(
+        r#"
+        SELECT id, message_id, phash, bit_count( (phash # $2)::bit(64) )::INT as distance
+        FROM images
+        WHERE chat_id = $1 AND deleted_at IS NULL AND bit_count( (phash # $2)::bit(64) ) <= $3
+        ORDER BY distance, message_id
+        LIMIT $4
+        "#,
+    )
+    .bind(chat_id)
+    .bind(hash)
+    .bind(max_distance as i32)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+/// Random sample of the chat's hashes.
+pub async fn sample_hashes(pool: &PgPool, chat_id: i64, limit: i64) -> sqlx::Result<Vec<i64>> {
+    sqlx::query_scalar(
+        "SELECT phash FROM images WHERE chat_id = $1 AND deleted_at IS NULL ORDER BY random() LIMIT $2",
+    )
+        .bind(chat_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+}
+
+/// Distances of the chat's sightings and whether each was marked as a false positive.
+pub async fn sighting_feedback(pool: &PgPool, chat_id: i64) -> sqlx::Result<Vec<(i16, bool)>> {
+    sqlx::query_as("SELECT distance, false_positive FROM sightings WHERE chat_id = $1")
+        .bind(chat_id)
+        .fetch_all(pool)
+        .await
+}
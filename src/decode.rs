@@ -0,0 +1,256 @@
+use image::codecs::png::PngDecoder;
+use image::io::Reader;
+use image::{AnimationDecoder, DynamicImage, RgbImage, RgbaImage};
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("image error")]
+    Image(#[from] image::ImageError),
+    #[cfg(feature = "heif")]
+    #[error("heif error")]
+    Heif(#[from] libheif_rs::HeifError),
+    #[cfg(feature = "jxl")]
+    #[error("jxl error")]
+    Jxl(#[from] jxl_oxide::Error),
+    #[error("corrupt or unreadable image ({0})")]
+    Corrupt(&'static str),
+    #[error("unsupported format ({0})")]
+    Unsupported(&'static str),
+    #[error("decoded image has an unexpected buffer size")]
+    BufferSize,
+}
+
+/// Formats we sniff ourselves because the `image` crate doesn't know about them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    WebP,
+    Heif,
+    Avif,
+    Jxl,
+}
+
+/// Decodes an image from memory, dispatching to the optional decoders for formats
+/// that the `image` crate can't handle.
+pub fn decode(data: &[u8]) -> Result<DynamicImage, Error> {
+    match sniff(data) {
+        Some(Format::Png) => decode_png(data),
+        Some(Format::WebP) => decode_webp(data),
+        Some(Format::Heif) => decode_heif(data),
+        Some(Format::Avif) => decode_avif(data),
+        Some(Format::Jxl) => decode_jxl(data),
+        None => Ok(Reader::new(Cursor::new(data))
+            .with_guessed_format()?
+            .decode()?),
+    }
+}
+
+/// The image's size as its header gives it, without decoding it, so huge images can be
+/// turned down before their pixels take up any memory. `None` if the header doesn't tell,
+/// or for formats only their decoders read.
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    match sniff(data) {
+        Some(Format::WebP) => {
+            let features = webp::BitstreamFeatures::new(data)?;
+            Some((features.width(), features.height()))
+        }
+        Some(Format::Heif | Format::Avif | Format::Jxl) => None,
+        Some(Format::Png) | None => Reader::new(Cursor::new(data))
+            .with_guessed_format()
+            .ok()?
+            .into_dimensions()
+            .ok(),
+    }
+}
+
+/// Reads and decodes an image file.
+pub fn open(path: &Path) -> Result<DynamicImage, Error> {
+    let data = fs::read(path)?;
+    decode(&data)
+}
+
+/// Tells the formats we decode ourselves apart by their first bytes, `None` leaving the rest
+/// to the `image` crate.
+pub fn sniff(data: &[u8]) -> Option<Format> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some(Format::Png);
+    }
+
+    if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some(Format::WebP);
+    }
+
+    // JPEG XL: either a bare codestream or an ISOBMFF container.
+    if data.starts_with(&[0xff, 0x0a])
+        || data.starts_with(&[0x00, 0x00, 0x00, 0x0c, b'J', b'X', b'L', b' '])
+    {
+        return Some(Format::Jxl);
+    }
+
+    // HEIF and AVIF are both ISOBMFF, the brands in the ftyp box tell them apart. AVIF
+    // files often have the generic HEIF brand mif1 as the major one and avif only among
+    // the compatible brands after the minor version, so all of them are looked at.
+    if data.len() < 12 || &data[4..8] != b"ftyp" {
+        return None;
+    }
+
+    let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let compatible = data.get(16..size.min(data.len())).unwrap_or_default();
+    let brands = || std::iter::once(&data[8..12]).chain(compatible.chunks_exact(4));
+
+    if brands().any(|x| matches!(x, b"avif" | b"avis")) {
+        return Some(Format::Avif);
+    }
+
+    brands()
+        .any(|x| {
+            matches!(
+                x,
+                b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" | b"mif1" | b"msf1"
+            )
+        })
+        .then_some(Format::Heif)
+}
+
+/// Decodes a PNG, taking the first animation frame for APNGs. The default image of an
+/// APNG isn't necessarily part of the animation (it can be a thumbnail), so don't rely on it.
+fn decode_png(data: &[u8]) -> Result<DynamicImage, Error> {
+    let decoder = PngDecoder::new(Cursor::new(data))?;
+    if !decoder.is_apng() {
+        return Ok(DynamicImage::from_decoder(decoder)?);
+    }
+
+    match decoder.apng().into_frames().next() {
+        Some(frame) => Ok(DynamicImage::ImageRgba8(frame?.into_buffer())),
+        // An animation without frames, retry as a plain PNG.
+        None => Ok(DynamicImage::from_decoder(PngDecoder::new(Cursor::new(
+            data,
+        ))?)?),
+    }
+}
+
+/// Decodes a WebP via libwebp, which unlike the `image` crate's decoder handles lossless,
+/// alpha and animated files. Animated files are hashed by their first frame.
+fn decode_webp(data: &[u8]) -> Result<DynamicImage, Error> {
+    let features = webp::BitstreamFeatures::new(data).ok_or(Error::Corrupt("webp"))?;
+
+    if features.has_animation() {
+        let animation = webp::AnimDecoder::new(data)
+            .decode()
+            .map_err(|_| Error::Corrupt("animated webp"))?;
+        let frame = animation
+            .get_frame(0)
+            .ok_or(Error::Corrupt("animated webp"))?;
+
+        return from_raw(
+            frame.width(),
+            frame.height(),
+            frame.get_layout().is_alpha(),
+            frame.get_image().to_vec(),
+        );
+    }
+
+    let image = webp::Decoder::new(data)
+        .decode()
+        .ok_or(Error::Corrupt("webp"))?;
+
+    from_raw(
+        image.width(),
+        image.height(),
+        image.is_alpha(),
+        image.to_vec(),
+    )
+}
+
+fn from_raw(width: u32, height: u32, alpha: bool, pixels: Vec<u8>) -> Result<DynamicImage, Error> {
+    let image = if alpha {
+        RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8)
+    } else {
+        RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+    };
+
+    image.ok_or(Error::BufferSize)
+}
+
+#[cfg(feature = "heif")]
+fn decode_heif(data: &[u8]) -> Result<DynamicImage, Error> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let lib_heif = LibHeif::new();
+    let ctx = HeifContext::read_from_bytes(data)?;
+    let handle = ctx.primary_image_handle()?;
+    let image = lib_heif.decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)?;
+
+    let planes = image.planes();
+    let plane = planes.interleaved.ok_or(Error::BufferSize)?;
+
+    // Rows may be padded, copy them out without the stride padding.
+    let row_len = plane.width as usize * 3;
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+
+    let image = RgbImage::from_raw(plane.width, plane.height, pixels).ok_or(Error::BufferSize)?;
+
+    Ok(DynamicImage::ImageRgb8(image))
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(_data: &[u8]) -> Result<DynamicImage, Error> {
+    Err(Error::Unsupported("heif"))
+}
+
+#[cfg(feature = "avif")]
+fn decode_avif(data: &[u8]) -> Result<DynamicImage, Error> {
+    Ok(Reader::with_format(Cursor::new(data), image::ImageFormat::Avif).decode()?)
+}
+
+// libheif can usually decode AVIF too, so fall back to it if it's the only one enabled.
+#[cfg(all(not(feature = "avif"), feature = "heif"))]
+fn decode_avif(data: &[u8]) -> Result<DynamicImage, Error> {
+    decode_heif(data)
+}
+
+#[cfg(all(not(feature = "avif"), not(feature = "heif")))]
+fn decode_avif(_data: &[u8]) -> Result<DynamicImage, Error> {
+    Err(Error::Unsupported("avif"))
+}
+
+#[cfg(feature = "jxl")]
+fn decode_jxl(data: &[u8]) -> Result<DynamicImage, Error> {
+    use image::{GrayAlphaImage, GrayImage};
+    use jxl_oxide::JxlImage;
+
+    let image = JxlImage::builder().read(Cursor::new(data))?;
+    let render = image.render_frame(0)?;
+    let frame = render.image_all_channels();
+
+    let (width, height) = (frame.width() as u32, frame.height() as u32);
+    let pixels = frame
+        .buf()
+        .iter()
+        .map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect::<Vec<_>>();
+
+    let image = match frame.channels() {
+        1 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        2 => GrayAlphaImage::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        3 => RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        4 => RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+        _ => None,
+    };
+
+    image.ok_or(Error::BufferSize)
+}
+
+#[cfg(not(feature = "jxl"))]
+fn decode_jxl(_data: &[u8]) -> Result<DynamicImage, Error> {
+    Err(Error::Unsupported("jxl"))
+}
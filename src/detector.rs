@@ -0,0 +1,1183 @@
+use crate::archive::Archive;
+use crate::audit;
+use crate::config::{RepostHandling, VerificationSettings};
+use crate::database::{self, Candidate, ClosestMatch, NewImage};
+use crate::decode;
+use crate::hashing::{self, Hasher};
+use crate::locale::Locale;
+use crate::matching::{Matcher, Outcome};
+use crate::messenger::{IncomingImage, MessageRef, Messenger};
+use crate::notices::Notices;
+use crate::outbox::Outbox;
+use crate::scripting::{Action, HookContext, Scripts};
+use crate::verify;
+use crate::webhook::{DuplicateEvent, Webhooks};
+use chrono::{TimeDelta, Utc};
+use image::{DynamicImage, ImageOutputFormat};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::{Instrument, debug, error, info, info_span};
+
+/// How recent an original without a link must be for duplicates to be replied to on it.
+const REPLY_TO_ORIGINAL_WITHIN: TimeDelta = TimeDelta::hours(48);
+
+/// Deleted originals skipped over for one duplicate before giving up on replying.
+const MAX_STALE_FALLBACKS: usize = 3;
+
+/// The same sender sending the same image again this soon is taken to be a client glitch.
+const DOUBLE_SEND_WITHIN: TimeDelta = TimeDelta::seconds(60);
+
+/// Further reposts of the same sender this soon after a notice are added to it, see
+/// [`Detector::with_notice_aggregation`].
+const AGGREGATE_WITHIN: Duration = Duration::from_secs(60);
+
+#[derive(Error, Debug)]
+pub enum Error<E> {
+    #[error("messenger error")]
+    Messenger(#[source] E),
+    #[error("couldnt hash image")]
+    Hashing(#[from] hashing::Error),
+    #[error("hashing task failed")]
+    Task(#[from] tokio::task::JoinError),
+    #[error("database error")]
+    Database(#[from] sqlx::Error),
+}
+
+/// What went into matching an image, see [`Detector::explain`].
+pub struct Explanation {
+    pub hash: i64,
+    pub alt_hash: Option<i64>,
+    /// The chat's threshold.
+    pub threshold: u8,
+    /// The closest indexed images, closest first.
+    pub candidates: Vec<Candidate>,
+}
+
+/// A duplicate notice further reposts of the same sender, or of the same burst, are added
+/// to.
+struct RecentNotice {
+    notice: MessageRef,
+    /// Until when reposts are added to it.
+    until: Instant,
+    /// Where the originals are, one line each.
+    originals: Vec<String>,
+    spoiler: bool,
+}
+
+/// Glues hashing, matching and a [`Messenger`] together into the actual bot behavior.
+#[derive(Clone)]
+pub struct Detector {
+    hasher: Arc<Hasher>,
+    matcher: Matcher,
+    webhooks: Webhooks,
+    scripts: Option<Arc<Scripts>>,
+    archive: Option<Archive>,
+    verification: Option<VerificationSettings>,
+    shadow_threshold: Option<u8>,
+    outbox: Option<Outbox>,
+    notices: Option<Notices>,
+    own_reposts: RepostHandling,
+    young_reposts: RepostHandling,
+    delete_double_sends: bool,
+    recent_notices: Arc<Mutex<RecentNotices>>,
+    aggregate_notices: bool,
+    bursts: Option<Arc<Bursts>>,
+}
+
+/// By chat and sender, or by chat alone for the notice of a burst.
+type RecentNotices = HashMap<(i64, Option<i64>), RecentNotice>;
+
+/// When the last images of each chat arrived, see [`Detector::with_burst_summaries`].
+struct Bursts {
+    images: usize,
+    within: Duration,
+    arrivals: Mutex<HashMap<i64, VecDeque<Instant>>>,
+}
+
+impl Bursts {
+    fn arrived(&self, chat_id: i64) {
+        let now = Instant::now();
+        let mut arrivals = self.arrivals.lock().unwrap();
+        // Chats that went quiet don't need remembering.
+        if arrivals.len() > 1000 {
+            arrivals.retain(|_, x| x.back().is_some_and(|x| now - *x < self.within));
+        }
+
+        let chat = arrivals.entry(chat_id).or_default();
+        chat.push_back(now);
+        if chat.len() > self.images {
+            chat.pop_front();
+        }
+    }
+
+    /// Whether the chat's last `images` images all arrived in the last `within`.
+    fn is_burst(&self, chat_id: i64) -> bool {
+        self.arrivals
+            .lock()
+            .unwrap()
+            .get(&chat_id)
+            .is_some_and(|x| {
+                x.len() >= self.images && x.front().is_some_and(|x| x.elapsed() < self.within)
+            })
+    }
+}
+
+impl Detector {
+    pub fn new(hasher: Arc<Hasher>, matcher: Matcher) -> Self {
+        Self {
+            hasher,
+            matcher,
+            webhooks: Webhooks::default(),
+            scripts: None,
+            archive: None,
+            verification: None,
+            shadow_threshold: None,
+            outbox: None,
+            notices: None,
+            own_reposts: RepostHandling::Flag,
+            young_reposts: RepostHandling::Flag,
+            delete_double_sends: false,
+            recent_notices: Default::default(),
+            aggregate_notices: false,
+            bursts: None,
+        }
+    }
+
+    /// How to treat someone reposting their own image, and reposts of images younger than
+    /// the chat's minimum age.
+    pub fn with_repost_handling(mut self, own: RepostHandling, young: RepostHandling) -> Self {
+        self.own_reposts = own;
+        self.young_reposts = young;
+        self
+    }
+
+    /// Quietly deletes the second copy of an image sent twice in a row by accident, see
+    /// [`Detector::is_double_send`].
+    pub fn with_double_send_cleanup(mut self) -> Self {
+        self.delete_double_sends = true;
+        self
+    }
+
+    /// Edits the notice about a sender's repost to list their further reposts for a minute,
+    /// instead of replying to each. Only applies to replies sent right away, not through
+    /// the outbox.
+    pub fn with_notice_aggregation(mut self) -> Self {
+        self.aggregate_notices = true;
+        self
+    }
+
+    /// Once `images` images arrive in a chat within `within`, as when someone dumps a folder
+    /// of photos, the duplicates among them share a single notice listing their originals.
+    /// Like [`Detector::with_notice_aggregation`], only applies to replies sent right away.
+    pub fn with_burst_summaries(mut self, images: usize, within: Duration) -> Self {
+        self.bursts = Some(Arc::new(Bursts {
+            images: images.max(1),
+            within,
+            arrivals: Default::default(),
+        }));
+        self
+    }
+
+    /// Whether the repost is the sender's own image from moments ago in the same chat, as
+    /// when a client sends a message twice.
+    pub fn is_double_send(
+        &self,
+        chat_id: i64,
+        closest_match: &ClosestMatch,
+        sender_id: Option<i64>,
+    ) -> bool {
+        closest_match.chat_id == chat_id
+            && closest_match.sent_by(sender_id)
+            && Utc::now() - closest_match.created_at < DOUBLE_SEND_WITHIN
+    }
+
+    /// How a repost in the chat is treated. Only [`RepostHandling::Flag`] gets replies and
+    /// escalation as usual, reposts that are both the sender's own and young get whichever
+    /// treatment is more lenient.
+    pub async fn handling(
+        &self,
+        chat_id: i64,
+        closest_match: &ClosestMatch,
+        sender_id: Option<i64>,
+    ) -> sqlx::Result<RepostHandling> {
+        // Deleted without a word.
+        if self.delete_double_sends && self.is_double_send(chat_id, closest_match, sender_id) {
+            return Ok(RepostHandling::Ignore);
+        }
+
+        let mut handling = RepostHandling::Flag;
+        if closest_match.sent_by(sender_id) {
+            handling = handling.max(self.own_reposts);
+        }
+
+        if self.young_reposts != RepostHandling::Flag
+            && let Some(min_age) = self.matcher.min_repost_age(chat_id).await?
+            && Utc::now() - closest_match.created_at < min_age
+        {
+            handling = handling.max(self.young_reposts);
+        }
+
+        Ok(handling)
+    }
+
+    pub fn with_webhooks(mut self, webhooks: Webhooks) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+
+    pub fn with_scripts(mut self, scripts: Scripts) -> Self {
+        self.scripts = Some(Arc::new(scripts));
+        self
+    }
+
+    pub fn with_archive(mut self, archive: Archive) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    pub fn with_verification(mut self, settings: VerificationSettings) -> Self {
+        self.verification = Some(settings);
+        self
+    }
+
+    /// Evaluates a second threshold alongside the live one, only logging where they disagree.
+    pub fn with_shadow_threshold(mut self, threshold: u8) -> Self {
+        self.shadow_threshold = Some(threshold);
+        self
+    }
+
+    /// Queues duplicate replies in the outbox instead of sending them right away.
+    pub fn with_outbox(mut self, outbox: Outbox) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    /// Keeps track of duplicate notices in chats that have them cleaned up.
+    pub fn with_notices(mut self, notices: Notices) -> Self {
+        self.notices = Some(notices);
+        self
+    }
+
+    pub fn hasher(&self) -> &Hasher {
+        &self.hasher
+    }
+
+    pub fn matcher(&self) -> &Matcher {
+        &self.matcher
+    }
+
+    /// Hashes the image again and looks up the `limit` closest indexed images, for showing
+    /// why it was or wasn't flagged. Nothing gets indexed or replied.
+    pub async fn explain<M: Messenger>(
+        &self,
+        messenger: &M,
+        image: &IncomingImage<M::Media>,
+        limit: i64,
+    ) -> Result<Explanation, Error<M::Error>> {
+        let (hash, _) = self.hash(messenger, image).await?;
+        let alt_hash = self.alt_hash(messenger, image).await;
+        let chat_id = image.message.chat_id;
+
+        Ok(Explanation {
+            hash,
+            alt_hash,
+            threshold: self.matcher.threshold(chat_id).await?,
+            candidates: self
+                .matcher
+                .candidates(
+                    chat_id,
+                    hash,
+                    alt_hash,
+                    Some(image.message.message_id),
+                    limit,
+                )
+                .await?,
+        })
+    }
+
+    pub fn archive(&self) -> Option<&Archive> {
+        self.archive.as_ref()
+    }
+
+    /// Checks a freshly posted image, replying to it if it's a duplicate and indexing it otherwise.
+    #[tracing::instrument(skip_all, fields(chat_id = image.message.chat_id, message_id = image.message.message_id))]
+    pub async fn handle<M: Messenger>(
+        &self,
+        messenger: &M,
+        image: IncomingImage<M::Media>,
+    ) -> Result<Outcome, Error<M::Error>> {
+        let started = Instant::now();
+        if let Some(bursts) = &self.bursts {
+            bursts.arrived(image.message.chat_id);
+        }
+
+        let (hash, data) = self.hash(messenger, &image).await?;
+        let alt_hash = self.alt_hash(messenger, &image).await;
+        let MessageRef {
+            chat_id,
+            message_id,
+        } = image.message;
+
+        let media_ref = image.media.to_string();
+        let new_image = NewImage {
+            chat_id,
+            chat_title: &image.chat_title,
+            message_id,
+            phash: hash,
+            alt_phash: alt_hash,
+            media_key: Some(&image.media_key),
+            media_ref: Some(&media_ref),
+            sender_id: image.sender_id,
+            forward: image.forward,
+            spoiler: image.spoiler,
+            low_entropy: self.hasher.is_low_entropy(hash),
+            source: database::LIVE_SOURCE,
+            caption: image.caption.as_deref(),
+        };
+
+        if new_image.low_entropy {
+            debug!("{message_id} in {chat_id} has a low-entropy hash {hash:016x}");
+        }
+
+        let (threshold, mut closest_match) = async {
+            let threshold = self.matcher.threshold(chat_id).await?;
+            let closest_match = self.matcher.find(&new_image, threshold).await?;
+
+            Ok::<_, sqlx::Error>((threshold, closest_match))
+        }
+        .instrument(info_span!("db_query"))
+        .await?;
+
+        // A match that failed verification mustn't turn up again when recording the image.
+        let mut recheck = Some(threshold);
+        if let (Some(settings), Some(closest)) = (&self.verification, &closest_match)
+            && closest.distance.saturating_add(settings.margin) >= threshold
+            && !self.verify(messenger, closest, data, settings).await?
+        {
+            debug!(
+                "match of {message_id} against {original} in {chat_id} failed verification",
+                original = closest.message_id
+            );
+            recheck = closest.distance.checked_sub(1);
+            closest_match = None;
+        }
+
+        if let Some(shadow_threshold) = self.shadow_threshold {
+            self.evaluate_shadow(&new_image, shadow_threshold, closest_match.is_some())
+                .await?;
+        }
+
+        let outcome = match closest_match {
+            Some(closest) => Outcome::Duplicate(closest),
+            None => Outcome::New,
+        };
+
+        let outcome = self
+            .matcher
+            .record(&new_image, outcome, recheck)
+            .instrument(info_span!("db_write"))
+            .await?;
+
+        let mut ctx = HookContext {
+            chat_id,
+            chat_title: image.chat_title.clone(),
+            message_id,
+            original_message_id: None,
+            distance: None,
+            original_link: None,
+        };
+
+        match &outcome {
+            Outcome::Duplicate(closest_match) => {
+                info!(
+                    chat_id,
+                    message_id,
+                    original_message_id = closest_match.message_id,
+                    distance = closest_match.distance,
+                    duration_ms = started.elapsed().as_millis() as u64,
+                    "duplicate image detected"
+                );
+
+                self.webhooks.notify(DuplicateEvent {
+                    event: "duplicate",
+                    chat_id,
+                    chat_title: image.chat_title.clone(),
+                    message_id,
+                    original_message_id: closest_match.message_id,
+                    distance: closest_match.distance,
+                    message_link: messenger.message_link(image.message),
+                    original_link: messenger.message_link(closest_match.message()),
+                });
+
+                ctx.original_message_id = Some(closest_match.message_id);
+                ctx.distance = Some(closest_match.distance);
+                ctx.original_link = messenger.message_link(closest_match.message());
+
+                let action = match &self.scripts {
+                    Some(scripts) => scripts.on_duplicate(&ctx),
+                    None => Action::Default,
+                };
+
+                // Don't let the reply reveal either image if it's behind a spoiler.
+                let spoiler = image.spoiler || closest_match.spoiler;
+                let handling = self
+                    .handling(chat_id, closest_match, image.sender_id)
+                    .await?;
+
+                if self.delete_double_sends
+                    && self.is_double_send(chat_id, closest_match, image.sender_id)
+                {
+                    debug!("{message_id} in {chat_id} was sent twice, deleting the copy");
+                    match messenger.delete(image.message).await {
+                        Ok(()) => {
+                            audit::record(
+                                self.matcher.pool(),
+                                Some(chat_id),
+                                None,
+                                audit::Action::Delete,
+                                json!({
+                                    "message_id": message_id,
+                                    "reason": "double send",
+                                    "original_message_id": closest_match.message_id,
+                                }),
+                            )
+                            .await
+                        }
+                        // Most likely the bot isn't an admin there.
+                        Err(e) => {
+                            debug!("Couldn't delete double send {message_id} in {chat_id}: {e}")
+                        }
+                    }
+                } else if handling == RepostHandling::Ignore {
+                    debug!("{message_id} in {chat_id} is an own or early repost, not replying");
+                } else if action == Action::Default && self.matcher.is_silent(chat_id).await? {
+                    debug!("{chat_id} is in silent mode, not replying to {message_id}");
+                } else if action == Action::Default {
+                    match &self.outbox {
+                        Some(outbox) => {
+                            let locale = self.matcher.locale(chat_id).await?;
+                            let (target, text) = self.duplicate_notice(
+                                messenger,
+                                &image,
+                                closest_match,
+                                handling,
+                                &locale,
+                            );
+                            outbox
+                                .enqueue(target, image.message, &text, spoiler)
+                                .await?
+                        }
+                        None => {
+                            self.reply_duplicate(
+                                messenger,
+                                &image,
+                                &new_image,
+                                threshold,
+                                closest_match.clone(),
+                                handling,
+                            )
+                            .instrument(info_span!("reply"))
+                            .await?
+                        }
+                    }
+                } else {
+                    self.apply_action(messenger, image.message, action, spoiler)
+                        .await?;
+                }
+            }
+            Outcome::New => {
+                debug!(
+                    chat_id,
+                    message_id,
+                    duration_ms = started.elapsed().as_millis() as u64,
+                    "new image sent to {title} ({chat_id}). added hash to memory",
+                    title = image.chat_title
+                );
+
+                if let Some(scripts) = &self.scripts {
+                    let action = scripts.on_new_image(&ctx);
+                    self.apply_action(messenger, image.message, action, image.spoiler)
+                        .await?;
+                }
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    /// Replies about a duplicate. Replies to an original that turns out to be deleted mark it
+    /// stale and move on to the next closest match, if there is one.
+    async fn reply_duplicate<M: Messenger>(
+        &self,
+        messenger: &M,
+        image: &IncomingImage<M::Media>,
+        new_image: &NewImage<'_>,
+        threshold: u8,
+        mut closest_match: ClosestMatch,
+        handling: RepostHandling,
+    ) -> Result<(), Error<M::Error>> {
+        let locale = self.matcher.locale(image.message.chat_id).await?;
+
+        if handling == RepostHandling::Flag
+            && self
+                .aggregate(messenger, image, &closest_match, &locale)
+                .await
+        {
+            return Ok(());
+        }
+
+        for _ in 0..MAX_STALE_FALLBACKS {
+            let (target, text) =
+                self.duplicate_notice(messenger, image, &closest_match, handling, &locale);
+            let spoiler = image.spoiler || closest_match.spoiler;
+
+            match self.reply(messenger, target, &text, spoiler).await {
+                Ok(notice) => {
+                    if let Some(notices) = &self.notices {
+                        notices.record(notice, image.message).await?;
+                    }
+                    if handling == RepostHandling::Flag && target == image.message {
+                        self.remember_notice(messenger, image, &closest_match, &locale, notice);
+                    }
+                    return Ok(());
+                }
+                Err(e) if target == closest_match.message() && M::is_missing_message(&e) => {
+                    info!(
+                        chat_id = target.chat_id,
+                        message_id = target.message_id,
+                        "original is gone, marking it stale"
+                    );
+                    self.matcher.mark_stale(target).await?;
+                }
+                Err(e) => return Err(Error::Messenger(e)),
+            }
+
+            match self.matcher.find(new_image, threshold).await? {
+                Some(next) => closest_match = next,
+                None => return Ok(()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Which notice the repost would be added to, if any, and for how long it takes
+    /// further ones after that. The chat's burst notice takes precedence over the sender's.
+    fn notice_key<T>(&self, image: &IncomingImage<T>) -> Option<((i64, Option<i64>), Duration)> {
+        let chat_id = image.message.chat_id;
+        if let Some(bursts) = &self.bursts
+            && bursts.is_burst(chat_id)
+        {
+            return Some(((chat_id, None), bursts.within));
+        }
+
+        match (self.aggregate_notices, image.sender_id) {
+            (true, Some(sender_id)) => Some(((chat_id, Some(sender_id)), AGGREGATE_WITHIN)),
+            _ => None,
+        }
+    }
+
+    /// Adds the repost to the notice about the sender's previous one if that was sent less
+    /// than [`AGGREGATE_WITHIN`] ago, or to the notice of the burst it's part of. Returns
+    /// whether it did, a new notice is needed if not.
+    async fn aggregate<M: Messenger>(
+        &self,
+        messenger: &M,
+        image: &IncomingImage<M::Media>,
+        closest_match: &ClosestMatch,
+        locale: &Locale,
+    ) -> bool {
+        let Some((key, within)) = self.notice_key(image) else {
+            return false;
+        };
+        let line = original_line(messenger, closest_match, locale);
+
+        // Not held across the edit, a chat's messages are handled one at a time anyway.
+        let (notice, text, spoiler) = {
+            let recent_notices = self.recent_notices.lock().unwrap();
+            let Some(recent) = recent_notices
+                .get(&key)
+                .filter(|x| x.until > Instant::now())
+            else {
+                return false;
+            };
+
+            let mut originals = recent.originals.clone();
+            originals.push(line.clone());
+            let text = match key.1 {
+                Some(_) => format!(
+                    "{n} reposts in the last minute, originals:\n{originals}",
+                    n = originals.len(),
+                    originals = originals.join("\n"),
+                ),
+                None => format!(
+                    "{n} duplicates among the images just sent, originals:\n{originals}",
+                    n = originals.len(),
+                    originals = originals.join("\n"),
+                ),
+            };
+            let spoiler = recent.spoiler || image.spoiler || closest_match.spoiler;
+
+            (recent.notice, text, spoiler)
+        };
+
+        if let Err(e) = messenger.edit(notice, &text, spoiler).await {
+            debug!(
+                "Couldn't edit notice {}, replying anew: {e}",
+                notice.message_id
+            );
+            return false;
+        }
+
+        audit::record(
+            self.matcher.pool(),
+            Some(notice.chat_id),
+            None,
+            audit::Action::Reply,
+            json!({ "edited": notice.message_id, "text": text }),
+        )
+        .await;
+
+        if let Some(recent) = self.recent_notices.lock().unwrap().get_mut(&key) {
+            recent.originals.push(line);
+            recent.spoiler = spoiler;
+            // A burst's notice takes duplicates until the burst is over.
+            if key.1.is_none() {
+                recent.until = Instant::now() + within;
+            }
+        }
+
+        true
+    }
+
+    /// Keeps the notice around for [`Detector::aggregate`].
+    fn remember_notice<M: Messenger>(
+        &self,
+        messenger: &M,
+        image: &IncomingImage<M::Media>,
+        closest_match: &ClosestMatch,
+        locale: &Locale,
+        notice: MessageRef,
+    ) {
+        let Some((key, within)) = self.notice_key(image) else {
+            return;
+        };
+
+        let mut recent_notices = self.recent_notices.lock().unwrap();
+        let now = Instant::now();
+        recent_notices.retain(|_, x| x.until > now);
+        recent_notices.insert(
+            key,
+            RecentNotice {
+                notice,
+                until: now + within,
+                originals: vec![original_line(messenger, closest_match, locale)],
+                spoiler: image.spoiler || closest_match.spoiler,
+            },
+        );
+    }
+
+    /// Where to reply about a duplicate and what to say. Without links to the original, a
+    /// recent one in the same chat gets the reply instead so it's a tap away.
+    fn duplicate_notice<M: Messenger>(
+        &self,
+        messenger: &M,
+        image: &IncomingImage<M::Media>,
+        closest_match: &ClosestMatch,
+        handling: RepostHandling,
+        locale: &Locale,
+    ) -> (MessageRef, String) {
+        let similarity = self.matcher.similarity(closest_match.distance);
+        if handling == RepostHandling::Soften {
+            let text = if closest_match.sent_by(image.sender_id) {
+                format!("you've posted this before ({similarity:.0}% similar).")
+            } else {
+                format!("looks like this was sent a moment ago already ({similarity:.0}% similar).")
+            };
+            return (image.message, text);
+        }
+
+        let original = closest_match.message();
+        if messenger.message_link(original).is_none()
+            && original.chat_id == image.message.chat_id
+            && Utc::now() - closest_match.created_at < REPLY_TO_ORIGINAL_WITHIN
+        {
+            // Being a reply to the original, the notice has to say which message the repost is.
+            let original_by = if closest_match.sent_by(image.sender_id) {
+                "by them".to_owned()
+            } else {
+                format!("by {}", sender(closest_match.sender_id))
+            };
+            let text = format!(
+                "this image was just reposted by {repost_by} in message {message_id} \
+                 ({similarity:.0}% similar), first sent {original_by} on {date}.",
+                repost_by = sender(image.sender_id),
+                message_id = image.message.message_id,
+                date = locale.format_date(closest_match.created_at),
+            );
+
+            return (original, text);
+        }
+
+        let text = format_match(
+            "duplicate image",
+            messenger,
+            &self.matcher,
+            closest_match,
+            image,
+            locale,
+        );
+
+        (image.message, text)
+    }
+
+    /// Answers an explicit "is this a duplicate?" question about `image`, replying to `question`
+    /// with the closest match at any distance. Nothing gets indexed.
+    pub async fn query<M: Messenger>(
+        &self,
+        messenger: &M,
+        question: MessageRef,
+        image: IncomingImage<M::Media>,
+    ) -> Result<Option<ClosestMatch>, Error<M::Error>> {
+        let (hash, _) = self.hash(messenger, &image).await?;
+        let alt_hash = self.alt_hash(messenger, &image).await;
+
+        let closest_match = self
+            .matcher
+            .closest(
+                image.message.chat_id,
+                hash,
+                alt_hash,
+                Some(image.message.message_id),
+            )
+            .await?;
+
+        if let Some(closest_match) = &closest_match {
+            let locale = self.matcher.locale(image.message.chat_id).await?;
+            let text = format_match(
+                "closest match",
+                messenger,
+                &self.matcher,
+                closest_match,
+                &image,
+                &locale,
+            );
+            self.reply(
+                messenger,
+                question,
+                &text,
+                image.spoiler || closest_match.spoiler,
+            )
+            .await
+            .map_err(Error::Messenger)?;
+        }
+
+        Ok(closest_match)
+    }
+
+    /// Answers "is this a repost?" about an image that was sent along with the question,
+    /// before it ever got indexed. Replies with the match within the chat's threshold, or
+    /// says there's none and indexes the image if `index_new` is set.
+    pub async fn ask<M: Messenger>(
+        &self,
+        messenger: &M,
+        image: IncomingImage<M::Media>,
+        index_new: bool,
+    ) -> Result<Outcome, Error<M::Error>> {
+        let (hash, _) = self.hash(messenger, &image).await?;
+        let alt_hash = self.alt_hash(messenger, &image).await;
+        let MessageRef {
+            chat_id,
+            message_id,
+        } = image.message;
+
+        let media_ref = image.media.to_string();
+        let new_image = NewImage {
+            chat_id,
+            chat_title: &image.chat_title,
+            message_id,
+            phash: hash,
+            alt_phash: alt_hash,
+            media_key: Some(&image.media_key),
+            media_ref: Some(&media_ref),
+            sender_id: image.sender_id,
+            forward: image.forward,
+            spoiler: image.spoiler,
+            low_entropy: self.hasher.is_low_entropy(hash),
+            source: database::LIVE_SOURCE,
+            caption: image.caption.as_deref(),
+        };
+
+        let threshold = self.matcher.threshold(chat_id).await?;
+        let text = match self.matcher.find(&new_image, threshold).await? {
+            Some(closest_match) => {
+                let locale = self.matcher.locale(chat_id).await?;
+                let text = format_match(
+                    "duplicate image",
+                    messenger,
+                    &self.matcher,
+                    &closest_match,
+                    &image,
+                    &locale,
+                );
+                self.reply(
+                    messenger,
+                    image.message,
+                    &text,
+                    image.spoiler || closest_match.spoiler,
+                )
+                .await
+                .map_err(Error::Messenger)?;
+
+                return Ok(Outcome::Duplicate(closest_match));
+            }
+            None if index_new => {
+                let outcome = self
+                    .matcher
+                    .record(&new_image, Outcome::New, Some(threshold))
+                    .await?;
+                let text = match &outcome {
+                    Outcome::New => "no match — indexing it now",
+                    Outcome::Duplicate(_) => "no match, but a copy was just indexed",
+                };
+
+                self.reply(messenger, image.message, text, image.spoiler)
+                    .await
+                    .map_err(Error::Messenger)?;
+
+                return Ok(outcome);
+            }
+            None => "no match",
+        };
+
+        self.reply(messenger, image.message, text, image.spoiler)
+            .await
+            .map_err(Error::Messenger)?;
+
+        Ok(Outcome::New)
+    }
+
+    /// Checks what the shadow threshold would have said and records it if it disagrees
+    /// with the live decision. Never affects what the bot actually does.
+    async fn evaluate_shadow(
+        &self,
+        image: &NewImage<'_>,
+        shadow_threshold: u8,
+        live_flagged: bool,
+    ) -> sqlx::Result<()> {
+        let shadow_match = self.matcher.find(image, shadow_threshold).await?;
+        if shadow_match.is_some() == live_flagged {
+            return Ok(());
+        }
+
+        info!(
+            "shadow threshold {shadow_threshold} would {verdict} message {message_id} in {chat_id}",
+            verdict = if live_flagged { "not flag" } else { "flag" },
+            message_id = image.message_id,
+            chat_id = image.chat_id,
+        );
+
+        database::save_shadow_sighting(
+            self.matcher.pool(),
+            image,
+            shadow_match.as_ref(),
+            shadow_threshold,
+            live_flagged,
+        )
+        .await
+    }
+
+    /// Downloads and hashes the image, returning the raw bytes too for later use.
+    async fn hash<M: Messenger>(
+        &self,
+        messenger: &M,
+        image: &IncomingImage<M::Media>,
+    ) -> Result<(i64, Arc<[u8]>), Error<M::Error>> {
+        let data: Arc<[u8]> = messenger
+            .download(image.message.chat_id, &image.media)
+            .instrument(info_span!("download"))
+            .await
+            .map_err(Error::Messenger)?
+            .into();
+
+        if let Some(archive) = &self.archive {
+            let archive = archive.clone();
+            let key = image.media_key.clone();
+            let data = data.to_vec();
+
+            tokio::spawn(async move {
+                if let Err(e) = archive.store(&key, data).await {
+                    error!("Error archiving {key}: {e}");
+                }
+            });
+        }
+
+        // Decoding and hashing is CPU heavy, keep it off the async workers.
+        let hasher = self.hasher.clone();
+        let hash = {
+            let data = data.clone();
+            // Spans don't follow into the blocking pool on their own.
+            let span = info_span!("hash");
+            tokio::task::spawn_blocking(move || span.in_scope(|| hasher.hash_bytes(&data)))
+                .await??
+        };
+
+        Ok((hash, data))
+    }
+
+    /// Hashes the alternate rendition if there is one. It only improves matching, so
+    /// failures are logged and otherwise ignored.
+    async fn alt_hash<M: Messenger>(
+        &self,
+        messenger: &M,
+        image: &IncomingImage<M::Media>,
+    ) -> Option<i64> {
+        let alternate = image.alternate.as_ref()?;
+
+        let data = match messenger
+            .download(image.message.chat_id, alternate)
+            .instrument(info_span!("download_alternate"))
+            .await
+        {
+            Ok(data) => data,
+            Err(e) => {
+                debug!("Couldn't download the alternate rendition: {e}");
+                return None;
+            }
+        };
+
+        let hasher = self.hasher.clone();
+        match tokio::task::spawn_blocking(move || hasher.hash_bytes(&data)).await {
+            Ok(Ok(hash)) => Some(hash),
+            Ok(Err(e)) => {
+                debug!("Couldn't hash the alternate rendition: {e}");
+                None
+            }
+            Err(e) => {
+                error!("Alternate hashing task failed: {e}");
+                None
+            }
+        }
+    }
+
+    /// Compares the pixels of the new image against the original. Returns `true` if the match
+    /// holds up, or if the original isn't available anymore and there's nothing to compare against.
+    async fn verify<M: Messenger>(
+        &self,
+        messenger: &M,
+        closest_match: &ClosestMatch,
+        data: Arc<[u8]>,
+        settings: &VerificationSettings,
+    ) -> Result<bool, Error<M::Error>> {
+        let Some(original) = self.original(messenger, closest_match.message()).await? else {
+            return Ok(true);
+        };
+
+        let similarity = tokio::task::spawn_blocking(move || -> Result<f64, decode::Error> {
+            let image = decode::decode(&data)?;
+            let original = decode::decode(&original)?;
+
+            Ok(verify::similarity(&image, &original))
+        })
+        .await?;
+
+        match similarity {
+            Ok(similarity) => {
+                debug!(
+                    "similarity of match against {original} in {chat_id}: {similarity:.3}",
+                    original = closest_match.message_id,
+                    chat_id = closest_match.chat_id
+                );
+                Ok(similarity >= settings.min_similarity)
+            }
+            Err(e) => {
+                error!("Error decoding images for verification: {e}");
+                Ok(true)
+            }
+        }
+    }
+
+    /// The indexed image of the message, from the archive if it's there and downloaded again
+    /// otherwise, or `None` if neither works.
+    async fn original<M: Messenger>(
+        &self,
+        messenger: &M,
+        message: MessageRef,
+    ) -> Result<Option<Vec<u8>>, Error<M::Error>> {
+        let Some(media) =
+            database::image_media(self.matcher.pool(), message.chat_id, message.message_id).await?
+        else {
+            return Ok(None);
+        };
+
+        if let (Some(archive), Some(key)) = (&self.archive, &media.media_key) {
+            match archive.load(key).await {
+                Ok(Some(data)) => return Ok(Some(data)),
+                Ok(None) => (),
+                Err(e) => error!("Error loading {key} from the archive: {e}"),
+            }
+        }
+
+        let Some(media_ref) = media.media_ref else {
+            return Ok(None);
+        };
+
+        match messenger
+            .download(message.chat_id, &M::Media::from(media_ref))
+            .await
+        {
+            Ok(data) => Ok(Some(data)),
+            Err(e) => {
+                debug!("Couldn't download the original of {message:?}: {e}");
+                Ok(None)
+            }
+        }
+    }
+
+    /// A PNG of where the image differs from the indexed one of `original`, see
+    /// [`verify::heatmap`], or `None` if either image can't be had.
+    pub async fn heatmap<M: Messenger>(
+        &self,
+        messenger: &M,
+        image: &IncomingImage<M::Media>,
+        original: MessageRef,
+    ) -> Result<Option<Vec<u8>>, Error<M::Error>> {
+        let Some(original) = self.original(messenger, original).await? else {
+            return Ok(None);
+        };
+        let data = messenger
+            .download(image.message.chat_id, &image.media)
+            .await
+            .map_err(Error::Messenger)?;
+
+        let png = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, decode::Error> {
+            let heatmap = verify::heatmap(&decode::decode(&original)?, &decode::decode(&data)?);
+
+            let mut png = Vec::new();
+            DynamicImage::ImageRgb8(heatmap).write_to(&mut png, ImageOutputFormat::Png)?;
+            Ok(png)
+        })
+        .await?;
+
+        match png {
+            Ok(png) => Ok(Some(png)),
+            Err(e) => {
+                error!("Error rendering a heatmap: {e}");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Replies and records the reply in the audit log.
+    async fn reply<M: Messenger>(
+        &self,
+        messenger: &M,
+        to: MessageRef,
+        text: &str,
+        spoiler: bool,
+    ) -> Result<MessageRef, M::Error> {
+        let reply = messenger.reply(to, text, spoiler).await?;
+
+        audit::record(
+            self.matcher.pool(),
+            Some(to.chat_id),
+            None,
+            audit::Action::Reply,
+            json!({ "reply_to": to.message_id, "text": text }),
+        )
+        .await;
+
+        Ok(reply)
+    }
+
+    async fn apply_action<M: Messenger>(
+        &self,
+        messenger: &M,
+        message: MessageRef,
+        action: Action,
+        spoiler: bool,
+    ) -> Result<(), Error<M::Error>> {
+        let Action::Custom { reply, delete } = action else {
+            return Ok(());
+        };
+
+        if let Some(reply) = reply {
+            self.reply(messenger, message, &reply, spoiler)
+                .await
+                .map_err(Error::Messenger)?;
+        }
+
+        if delete {
+            messenger.delete(message).await.map_err(Error::Messenger)?;
+            audit::record(
+                self.matcher.pool(),
+                Some(message.chat_id),
+                None,
+                audit::Action::Delete,
+                json!({ "message_id": message.message_id, "reason": "script" }),
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+}
+
+/// One original of an aggregated notice, its link or when it was sent.
+fn original_line<M: Messenger>(
+    messenger: &M,
+    closest_match: &ClosestMatch,
+    locale: &Locale,
+) -> String {
+    match messenger.message_link(closest_match.message()) {
+        Some(link) => format!("- {link}"),
+        None => format!(
+            "- first sent on {}",
+            locale.format_date(closest_match.created_at)
+        ),
+    }
+}
+
+/// Names the match with a link to it, or with when and by whom it was sent where there are
+/// no links. Originals from another chat of the network say which one.
+/// Who sent an image, for notices that can't link to it.
+fn sender(sender_id: Option<i64>) -> String {
+    match sender_id {
+        Some(id) if id > 0 => format!("user {id}"),
+        Some(id) => format!("chat {id}"),
+        None => "someone".to_owned(),
+    }
+}
+
+fn format_match<M: Messenger>(
+    prefix: &str,
+    messenger: &M,
+    matcher: &Matcher,
+    closest_match: &ClosestMatch,
+    image: &IncomingImage<M::Media>,
+    locale: &Locale,
+) -> String {
+    let similarity = matcher.similarity(closest_match.distance);
+    let chat = match &closest_match.chat_title {
+        Some(title) if closest_match.chat_id != image.message.chat_id => format!(" in {title}"),
+        _ => String::new(),
+    };
+
+    if let Some(link) = messenger.message_link(closest_match.message()) {
+        return format!("{prefix} ({similarity:.0}% similar){chat}.\n{link}");
+    }
+
+    let by = if closest_match.sent_by(image.sender_id) {
+        " by the same sender"
+    } else {
+        ""
+    };
+
+    format!(
+        "{prefix} ({similarity:.0}% similar), first sent{by}{chat} on {date}.",
+        date = locale.format_date(closest_match.created_at),
+    )
+}
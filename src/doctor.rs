@@ -0,0 +1,170 @@
+use crate::bot;
+use dupfinder_tg::config::{ArchiveSettings, Config};
+use dupfinder_tg::database;
+use sqlx::types::chrono::{DateTime, Utc};
+use std::time::Duration;
+use teloxide::prelude::*;
+
+/// Clocks further apart than this break Telegram's and the database's timestamps.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(30);
+
+/// Archives with less free space than this are reported.
+const MIN_FREE_SPACE: u64 = 1024 * 1024 * 1024;
+
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn check(&mut self, name: &str, result: Result<String, String>) {
+        match result {
+            Ok(detail) => println!("✅ {name}: {detail}"),
+            Err(detail) => {
+                println!("❌ {name}: {detail}");
+                self.failures += 1;
+            }
+        }
+    }
+}
+
+/// Checks everything a deployment depends on and prints a pass/fail report. Returns
+/// `false` if anything failed.
+pub async fn run(config: &Config) -> bool {
+    let mut report = Report { failures: 0 };
+
+    match database::init_pool(&config.database.url).await {
+        Ok(pool) => {
+            report.check("Database", Ok("connected".to_owned()));
+
+            let schema = match database::pending_migrations(&pool).await {
+                Ok(pending) if pending.is_empty() => Ok("up to date".to_owned()),
+                Ok(pending) => Err(format!(
+                    "{} migrations pending ({pending:?}), they're applied on the next start",
+                    pending.len()
+                )),
+                Err(e) => Err(e.to_string()),
+            };
+            report.check("Database schema", schema);
+
+            let clock = match database::now(&pool).await {
+                Ok(now) => skew("database", now),
+                Err(e) => Err(e.to_string()),
+            };
+            report.check("Database clock", clock);
+        }
+        Err(e) => report.check("Database", Err(format!("{e:#}"))),
+    }
+
+    if let Some(url) = &config.database.read_url {
+        let replica = match database::init_pool(url).await {
+            Ok(pool) => match database::now(&pool).await {
+                Ok(_) => Ok("connected".to_owned()),
+                Err(e) => Err(e.to_string()),
+            },
+            Err(e) => Err(format!("{e:#}")),
+        };
+        report.check("Database replica", replica);
+    }
+
+    if config.bots().next().is_none() {
+        report.check("Telegram", Err("no bots configured".to_owned()));
+    }
+
+    for settings in config.bots() {
+        let name = settings.name.as_deref().unwrap_or("default");
+        let bot = match bot::bot(settings) {
+            Ok(bot) => bot,
+            Err(e) => {
+                report.check(&format!("Telegram ({name})"), Err(format!("{e:#}")));
+                continue;
+            }
+        };
+
+        match bot.get_me().await {
+            Ok(me) => {
+                report.check(
+                    &format!("Telegram ({name})"),
+                    Ok(format!("logged in as @{}", me.username())),
+                );
+
+                let privacy = if me.can_read_all_group_messages {
+                    Ok("sees all group messages".to_owned())
+                } else {
+                    Err("privacy mode is on, the bot won't see images in groups".to_owned())
+                };
+                report.check(&format!("Privacy mode ({name})"), privacy);
+            }
+            Err(e) => report.check(&format!("Telegram ({name})"), Err(e.to_string())),
+        }
+
+        // The bot uses long polling, which Telegram refuses while a webhook is set.
+        let webhook = match bot.get_webhook_info().await {
+            Ok(info) => match info.url {
+                None => Ok("none set, polling works".to_owned()),
+                Some(url) => Err(format!(
+                    "set to {url}, polling won't receive updates until it's deleted"
+                )),
+            },
+            Err(e) => Err(e.to_string()),
+        };
+        report.check(&format!("Webhook ({name})"), webhook);
+    }
+
+    report.check("Telegram clock", telegram_clock().await);
+
+    if let Some(ArchiveSettings::Disk { path }) = &config.archive {
+        let space = match fs4::available_space(path) {
+            Ok(free) if free >= MIN_FREE_SPACE => Ok(format!(
+                "{} MB free in {}",
+                free / 1024 / 1024,
+                path.display()
+            )),
+            Ok(free) => Err(format!(
+                "only {} MB free in {}",
+                free / 1024 / 1024,
+                path.display()
+            )),
+            Err(e) => Err(format!("{}: {e}", path.display())),
+        };
+        report.check("Archive disk space", space);
+    }
+
+    println!();
+    if report.failures == 0 {
+        println!("All checks passed.");
+    } else {
+        println!("{} checks failed.", report.failures);
+    }
+
+    report.failures == 0
+}
+
+/// Compares the local clock against the Date header of the Bot API.
+async fn telegram_clock() -> Result<String, String> {
+    let response = reqwest::Client::new()
+        .head("https://api.telegram.org")
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let date = response
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| DateTime::parse_from_rfc2822(x).ok())
+        .ok_or("no usable Date header in the response")?;
+
+    skew("Telegram", date.with_timezone(&Utc))
+}
+
+fn skew(other: &str, other_now: DateTime<Utc>) -> Result<String, String> {
+    let skew = (Utc::now() - other_now).abs().to_std().unwrap_or_default();
+    let message = format!("{}s off from {other}", skew.as_secs());
+
+    if skew > MAX_CLOCK_SKEW {
+        Err(message)
+    } else {
+        Ok(message)
+    }
+}
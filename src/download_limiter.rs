@@ -0,0 +1,62 @@
+use crate::config::DownloadSettings;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Bounds concurrent downloads overall and per chat, so a flood of albums doesn't open
+/// hundreds of them, and a single busy chat can't take every slot from the others.
+#[derive(Clone)]
+pub struct DownloadLimiter {
+    overall: Arc<Semaphore>,
+    chats: Arc<Mutex<HashMap<i64, Arc<Semaphore>>>>,
+    per_chat: usize,
+}
+
+/// Held for as long as a download runs.
+pub struct DownloadPermit {
+    _chat: OwnedSemaphorePermit,
+    _overall: OwnedSemaphorePermit,
+}
+
+impl DownloadLimiter {
+    pub fn new(settings: &DownloadSettings) -> Self {
+        Self {
+            overall: Arc::new(Semaphore::new(settings.max_concurrent.max(1))),
+            chats: Default::default(),
+            per_chat: settings.max_per_chat.max(1),
+        }
+    }
+
+    /// Waits for a free slot for a download from the chat. The chat's slot is taken first,
+    /// so downloads waiting on their chat don't hold up other chats.
+    pub async fn acquire(&self, chat_id: i64) -> DownloadPermit {
+        let chat = {
+            let mut chats = self.chats.lock().unwrap();
+            // Chats with nothing downloading or waiting don't need remembering.
+            if chats.len() > 1000 {
+                chats.retain(|_, x| Arc::strong_count(x) > 1);
+            }
+
+            chats
+                .entry(chat_id)
+                .or_insert_with(|| Arc::new(Semaphore::new(self.per_chat)))
+                .clone()
+        };
+
+        let chat = chat
+            .acquire_owned()
+            .await
+            .expect("chat download semaphore is never closed");
+        let overall = self
+            .overall
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("download semaphore is never closed");
+
+        DownloadPermit {
+            _chat: chat,
+            _overall: overall,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::config::{HashAlgorithm, HashingSettings, ResizeFilter};
+use crate::decode;
+use crate::preprocess;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use img_hash::{HashAlg, HasherConfig};
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+use tracing::info_span;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("couldnt decode image")]
+    Decode(#[from] decode::Error),
+    #[error("file is too large ({size} bytes, limit is {limit})")]
+    FileTooLarge { size: u64, limit: u64 },
+    #[error("image is too large ({width}x{height}, limit is {limit} pixels per side)")]
+    ImageTooLarge { width: u32, height: u32, limit: u32 },
+    #[error("hash is {0} bytes, but at most 8 fit into the database")]
+    HashTooWide(usize),
+}
+
+/// The one place images get turned into hashes, shared by the bot and the importer so
+/// both always produce comparable values.
+pub struct Hasher {
+    settings: HashingSettings,
+    bits: u32,
+}
+
+impl Hasher {
+    pub fn new(settings: &HashingSettings) -> Result<Self, Error> {
+        // The output width depends on the algorithm in non-obvious ways (e.g. double gradient),
+        // so just hash a dummy image to find out whether it fits into a BIGINT.
+        let width = inner(settings)
+            .hash_image(&DynamicImage::new_luma8(1, 1))
+            .as_bytes()
+            .len();
+        if width > 8 {
+            return Err(Error::HashTooWide(width));
+        }
+
+        Ok(Self {
+            settings: settings.clone(),
+            bits: width as u32 * 8,
+        })
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.settings.algorithm
+    }
+
+    /// Number of meaningful bits in the hashes this hasher produces.
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Whether the hash is (nearly) all zeros or all ones, as with solid or washed out images.
+    /// Those are all alike, so matching them only turns up false positives.
+    pub fn is_low_entropy(&self, hash: i64) -> bool {
+        let margin = self.bits / 32;
+        let ones = hash.count_ones();
+
+        ones <= margin || ones >= self.bits - margin
+    }
+
+    pub fn hash_bytes(&self, data: &[u8]) -> Result<i64, Error> {
+        self.check_file_size(data.len() as u64)?;
+        // A small file can still decode into gigabytes of pixels, so whatever the header
+        // tells is checked first.
+        if let Some((width, height)) = decode::dimensions(data) {
+            self.check_dimensions(width, height)?;
+        }
+
+        let image = info_span!("decode").in_scope(|| decode::decode(data))?;
+        self.hash_image(&image)
+    }
+
+    pub fn hash_file(&self, path: &Path) -> Result<i64, Error> {
+        self.check_file_size(fs::metadata(path)?.len())?;
+        self.hash_bytes(&fs::read(path)?)
+    }
+
+    pub fn hash_image(&self, image: &DynamicImage) -> Result<i64, Error> {
+        self.check_dimensions(image.width(), image.height())?;
+
+        let preprocessed;
+        let image = if self.settings.preprocess.is_enabled() {
+            preprocessed = preprocess::apply(image, &self.settings.preprocess);
+            &preprocessed
+        } else {
+            image
+        };
+
+        let inner = inner(&self.settings);
+        let hash = if self.settings.normalize {
+            inner.hash_image(&normalize(image, self.settings.normalize_size))
+        } else {
+            inner.hash_image(image)
+        };
+
+        Ok(pack(hash.as_bytes()))
+    }
+
+    fn check_file_size(&self, size: u64) -> Result<(), Error> {
+        let limit = self.settings.max_file_size;
+        if size > limit {
+            return Err(Error::FileTooLarge { size, limit });
+        }
+
+        Ok(())
+    }
+
+    fn check_dimensions(&self, width: u32, height: u32) -> Result<(), Error> {
+        let limit = self.settings.max_dimension;
+        if width > limit || height > limit {
+            return Err(Error::ImageTooLarge {
+                width,
+                height,
+                limit,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// The `img_hash` hasher for the settings. It can't be shared between threads (its DCT
+/// isn't `Send`), so one is made for every image, which is cheap next to decoding it.
+fn inner(settings: &HashingSettings) -> img_hash::Hasher {
+    let mut config = HasherConfig::new()
+        .hash_alg(settings.algorithm.into())
+        .hash_size(settings.hash_width, settings.hash_height)
+        .resize_filter(settings.resize_filter.into());
+
+    if settings.preproc_dct {
+        config = config.preproc_dct();
+    }
+
+    config.to_hasher()
+}
+
+/// Brings every rendition of an image to the same footing: a fixed size, averaged down with
+/// a filter that smooths out compression artifacts, and without color, whose subsampling
+/// differs the most between encoders.
+fn normalize(image: &DynamicImage, size: u32) -> DynamicImage {
+    let image = if image.width() > size || image.height() > size {
+        image.resize(size, size, FilterType::Triangle)
+    } else {
+        image.clone()
+    };
+
+    image.grayscale()
+}
+
+/// Packs the hash bytes into an i64, left-aligned so that narrower hashes just have
+/// zeroed low bits that never contribute to the distance.
+fn pack(bytes: &[u8]) -> i64 {
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+
+    i64::from_be_bytes(buf)
+}
+
+impl From<HashAlgorithm> for HashAlg {
+    fn from(value: HashAlgorithm) -> Self {
+        match value {
+            HashAlgorithm::Mean => HashAlg::Mean,
+            HashAlgorithm::Gradient => HashAlg::Gradient,
+            HashAlgorithm::VertGradient => HashAlg::VertGradient,
+            HashAlgorithm::DoubleGradient => HashAlg::DoubleGradient,
+            HashAlgorithm::Blockhash => HashAlg::Blockhash,
+        }
+    }
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(value: ResizeFilter) -> Self {
+        match value {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
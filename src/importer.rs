@@ -1,18 +1,46 @@
 // src/importer.rs
-use crate::database;
-use anyhow::Result;
-use img_hash::HasherConfig;
+use crate::database::{self, ClosestMatch, ImportCounts, NewImage};
+use crate::hashing::Hasher;
 use indicatif::{ProgressBar, ProgressStyle};
 use serde::Deserialize;
+use serde_json::Value;
 use sqlx::PgPool;
+use sqlx::types::chrono::Utc;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
+use std::io::BufReader;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tracing::{debug, info, warn};
+
+/// How often a watched directory is checked for new files.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
 
 // --- Structs to model the Telegram JSON export ---
+/// A single chat's export, or a full account export, which lists every chat.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ExportFile {
+    Account {
+        chats: ChatList,
+        left_chats: Option<ChatList>,
+    },
+    Chat(Chat),
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatList {
+    list: Vec<Chat>,
+}
+
 #[derive(Deserialize, Debug)]
-struct Export {
-    name: String,
+pub struct Chat {
+    /// Missing for Saved Messages and deleted accounts.
+    name: Option<String>,
+    #[serde(rename = "type")]
+    chat_type: Option<String>,
+    id: Option<i64>,
     messages: Vec<Message>,
 }
 
@@ -22,6 +50,26 @@ struct Message {
     #[serde(rename = "type")]
     message_type: String,
     photo: Option<PathBuf>,
+    /// `user123` or `channel123`, the latter for anonymous admins and channel posts.
+    from_id: Option<String>,
+    /// Plain text, or a list of parts that are either plain text or formatted `{"text": ..}`.
+    text: Option<Value>,
+}
+
+impl Message {
+    /// The text sent along with the photo, with formatting dropped.
+    fn caption(&self) -> Option<String> {
+        let text: String = match self.text.as_ref()? {
+            Value::String(text) => text.clone(),
+            Value::Array(parts) => parts
+                .iter()
+                .filter_map(|x| x.as_str().or_else(|| x.get("text")?.as_str()))
+                .collect(),
+            _ => return None,
+        };
+
+        (!text.is_empty()).then_some(text)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -34,71 +82,437 @@ pub enum Error {
     },
     #[error("couldnt parse json")]
     Json(#[from] serde_json::Error),
+    #[error("export has no chats")]
+    NoChats,
     #[error("database error")]
     Database(#[from] sqlx::Error),
 }
 
+/// Spaces out the importer's writes to at most a number per second, so a backfill leaves the
+/// database room for the live bot's match queries.
+pub struct Throttle {
+    interval: Option<Duration>,
+    next: Instant,
+}
+
+impl Throttle {
+    /// Without `max_rate`, writes go through as fast as images are hashed.
+    pub fn new(max_rate: Option<f64>) -> Self {
+        Self {
+            interval: max_rate
+                .filter(|x| *x > 0.0)
+                .map(|x| Duration::from_secs_f64(1.0 / x)),
+            next: Instant::now(),
+        }
+    }
+
+    /// Waits until the next write is due.
+    async fn wait(&mut self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+
+        tokio::time::sleep_until(self.next.into()).await;
+        self.next = self.next.max(Instant::now()) + interval;
+    }
+}
+
+/// How [`run`] imports a chat.
+pub struct ImportOptions {
+    /// Images within this distance of one imported before them are recorded as sightings of
+    /// it instead of being indexed themselves.
+    pub dedup: Option<u8>,
+    /// Messages already indexed or recorded as sightings aren't hashed again, so importing an
+    /// updated export only takes the new ones.
+    pub skip_existing: bool,
+    pub throttle: Throttle,
+}
+
+/// An image indexed earlier in the same import.
+struct Imported {
+    hash: i64,
+    message_id: i32,
+    sender_id: Option<i64>,
+}
+
+/// A parsed export, with the chats to pick from.
+pub struct Export {
+    path: PathBuf,
+    chats: Vec<Chat>,
+    /// A full account export rather than a single chat's.
+    account: bool,
+}
+
+impl Export {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        println!("▶️ Reading export: {}", path.display());
+
+        let file = File::open(path).map_err(|e| Error::Io {
+            path: path.to_owned(),
+            source: e,
+        })?;
+
+        let (chats, account) = match serde_json::from_reader(BufReader::new(file))? {
+            ExportFile::Account { chats, left_chats } => {
+                let left = left_chats.map(|x| x.list).unwrap_or_default();
+                (chats.list.into_iter().chain(left).collect::<Vec<_>>(), true)
+            }
+            ExportFile::Chat(chat) => (vec![chat], false),
+        };
+
+        if chats.is_empty() {
+            return Err(Error::NoChats);
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            chats,
+            account,
+        })
+    }
+
+    pub fn chats(&self) -> &[Chat] {
+        &self.chats
+    }
+
+    /// What import runs of the chat and their images are recorded as coming from.
+    fn source(&self, chat: &Chat) -> String {
+        match self.account {
+            true => format!("{} ({})", self.path.display(), chat.name()),
+            false => self.path.display().to_string(),
+        }
+    }
+}
+
+impl Chat {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("Unnamed chat")
+    }
+
+    /// The id the bot knows the chat by, if the export says enough to tell.
+    pub fn bot_chat_id(&self) -> Option<i64> {
+        let id = self.id?;
+        match self.chat_type.as_deref()? {
+            "public_supergroup" | "private_supergroup" | "public_channel" | "private_channel" => {
+                Some(channel_id(id))
+            }
+            "private_group" => Some(-id),
+            "personal_chat" | "bot_chat" | "saved_messages" => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Number of messages with an image, the ones the import spends its time on.
+    pub fn media_count(&self) -> usize {
+        self.messages.iter().filter(|x| x.has_media()).count()
+    }
+}
+
+impl Message {
+    fn has_media(&self) -> bool {
+        self.message_type == "message" && self.photo.is_some()
+    }
+}
+
 // The main function for the importer
-pub async fn run(pool: &PgPool, path: &Path, chat_id: i64) -> Result<(), Error> {
-    println!("▶️ Starting import from: {}", path.display());
-
-    // --- 1. Parse the JSON file ---
-    let file = File::open(path).map_err(|e| Error::Io {
-        path: path.to_owned(),
-        source: e,
-    })?;
-    let data: Export = serde_json::from_reader(file)?;
-    let base_path = path.parent().unwrap();
-
-    let chat_title = data.name;
+/// Imports one of the export's chats into `chat_id`.
+pub async fn run(
+    pool: &PgPool,
+    hasher: &Hasher,
+    export: &Export,
+    chat: &Chat,
+    chat_id: i64,
+    options: &mut ImportOptions,
+) -> Result<(), Error> {
+    println!("▶️ Importing '{}' into chat {chat_id}", chat.name());
+
+    // Recorded even if the import fails halfway, with what it got done until then.
+    let source = export.source(chat);
+    let run_id = database::start_import_run(pool, &source, chat_id).await?;
+    let started = Instant::now();
+    let mut counts = ImportCounts::default();
+
+    let result = import(pool, hasher, export, chat, chat_id, options, &mut counts).await;
+    let error = result.as_ref().err().map(|e| e.to_string());
+    database::finish_import_run(pool, run_id, &counts, error.as_deref()).await?;
+
+    let elapsed = started.elapsed();
+    println!(
+        "Hashed {} images, skipped {} other messages and failed on {} images in {:.1?} ({:.1} images/s)",
+        counts.hashed,
+        counts.skipped,
+        counts.failed,
+        elapsed,
+        (counts.hashed + counts.failed) as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+    );
+
+    result
+}
+
+async fn import(
+    pool: &PgPool,
+    hasher: &Hasher,
+    export: &Export,
+    chat: &Chat,
+    chat_id: i64,
+    options: &mut ImportOptions,
+    counts: &mut ImportCounts,
+) -> Result<(), Error> {
+    // --- 1. Pick the messages with images ---
+    let base_path = export.path.parent().unwrap();
+    let source = database::import_source(&export.source(chat));
+    let chat_title = chat.name();
+    let total = chat.messages.len();
+
+    let existing = match options.skip_existing {
+        true => database::known_message_ids(pool, chat_id).await?,
+        false => HashSet::new(),
+    };
+
+    // Most messages of a typical chat are text, so the progress is measured in images, the
+    // only thing that takes any time.
+    let media = chat
+        .messages
+        .iter()
+        .filter(|msg| msg.has_media())
+        .collect::<Vec<_>>();
+    let total_media = media.len();
+    let media = media
+        .into_iter()
+        .filter(|msg| !existing.contains(&msg.id))
+        .collect::<Vec<_>>();
+    counts.skipped = (total - media.len()) as i32;
+
     println!(
-        "Chat: '{}' with {} messages.",
-        chat_title,
-        data.messages.len()
+        "Chat: '{}' with {} messages, {} of them images.",
+        chat_title, total, total_media
     );
+    if options.skip_existing {
+        println!(
+            "{} of the images are indexed already and skipped.",
+            total_media - media.len()
+        );
+    }
 
-    // --- 2. Setup Hasher and Progress Bar ---
-    let hasher = HasherConfig::new().to_hasher();
-    let pb = ProgressBar::new(data.messages.len() as u64);
+    // --- 2. Setup Progress Bar ---
+    let pb = ProgressBar::new(media.len() as u64);
     pb.set_style(
         ProgressStyle::default_bar()
             .template(
-                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})", // LLM slop
+                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} images, {per_sec} ({eta})",
             )
             .unwrap()
             .progress_chars("#>-"),
     );
 
+    // Exports are in chronological order, so the first of a cluster is the original.
+    let mut imported = Vec::new();
+    let mut duplicates = 0;
+
     // --- 3. Loop through messages and process images ---
-    for msg in data.messages {
+    for msg in media {
         pb.inc(1);
-        if msg.message_type != "message" {
+        let Some(photo) = &msg.photo else {
             continue;
-        }
-
-        let image_path = match msg.photo {
-            Some(p) => base_path.join(p),
-            None => continue,
         };
+        let image_path = base_path.join(photo);
+        let caption = msg.caption();
 
         // --- 4. Hash and Save ---
-        let image = match image::open(&image_path) {
-            Ok(img) => img,
+        let hash = match hasher.hash_file(&image_path) {
+            Ok(hash) => hash,
             Err(_) => {
                 // Silently skip files that can't be opened (e.g., deleted thumbnails)
+                counts.failed += 1;
                 continue;
             }
         };
 
-        let hash = hasher.hash_image(&image);
-        let Ok(hash): Result<[u8; 8], _> = hash.as_bytes().try_into() else {
-            panic!("Hash was not exactly 8 bytes!");
+        let image = NewImage {
+            chat_id,
+            chat_title,
+            message_id: msg.id,
+            phash: hash,
+            alt_phash: None,
+            media_key: None,
+            media_ref: None,
+            sender_id: msg.from_id.as_deref().and_then(sender_id),
+            forward: None,
+            spoiler: false,
+            low_entropy: hasher.is_low_entropy(hash),
+            source: &source,
+            caption: caption.as_deref(),
         };
-        let hash = i64::from_be_bytes(hash);
+        counts.hashed += 1;
+        options.throttle.wait().await;
+
+        if let Some(threshold) = options.dedup
+            && !image.low_entropy
+        {
+            if let Some(original) = closest(&imported, hash, threshold) {
+                let closest = ClosestMatch {
+                    chat_id,
+                    chat_title: None,
+                    message_id: original.message_id,
+                    distance: (original.hash ^ hash).count_ones() as u8,
+                    spoiler: false,
+                    created_at: Utc::now(),
+                    sender_id: original.sender_id,
+                };
+                database::save_sighting(pool, &image, &closest).await?;
+                duplicates += 1;
+                continue;
+            }
 
-        database::save_image(pool, chat_id, &chat_title, msg.id, hash).await?;
+            imported.push(Imported {
+                hash,
+                message_id: msg.id,
+                sender_id: image.sender_id,
+            });
+        }
+
+        database::save_image(&mut *pool.acquire().await?, &image).await?;
     }
 
     pb.finish_with_message("✅ Import complete!");
+    if options.dedup.is_some() {
+        println!("{duplicates} images were duplicates of earlier ones and recorded as sightings.");
+    }
+
     Ok(())
 }
+
+/// The closest image within `threshold` of `hash`.
+fn closest(imported: &[Imported], hash: i64, threshold: u8) -> Option<&Imported> {
+    imported
+        .iter()
+        .map(|x| (x, (x.hash ^ hash).count_ones()))
+        .filter(|(_, distance)| *distance <= threshold as u32)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(x, _)| x)
+}
+
+/// Keeps indexing image files that appear in `dir` into `chat_id` until interrupted, for
+/// setups where something else, like an archiving userbot, saves a chat's media. The message
+/// id is taken from the last number in the file name, e.g. `1234.jpg` or `chat_5_1234.png`.
+/// Files already there are indexed on the first pass.
+pub async fn watch(
+    pool: &PgPool,
+    hasher: &Hasher,
+    dir: &Path,
+    chat_id: i64,
+    throttle: &mut Throttle,
+) -> Result<(), Error> {
+    // Only used if the chat isn't known yet.
+    let title = dir.display().to_string();
+    let source = database::import_source(&dir.display().to_string());
+
+    // Files are only picked up once their size stopped changing, so ones still being
+    // written aren't read halfway.
+    let mut sizes = HashMap::<PathBuf, u64>::new();
+    let mut done = HashSet::<PathBuf>::new();
+
+    info!("Watching {} for images of chat {chat_id}", dir.display());
+
+    loop {
+        let entries = std::fs::read_dir(dir).map_err(|e| Error::Io {
+            path: dir.to_owned(),
+            source: e,
+        })?;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if done.contains(&path) || is_partial(&path) {
+                continue;
+            }
+
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !metadata.is_file()
+                || sizes.insert(path.clone(), metadata.len()) != Some(metadata.len())
+            {
+                continue;
+            }
+
+            sizes.remove(&path);
+            done.insert(path.clone());
+
+            let Some(message_id) = message_id(&path) else {
+                warn!(
+                    "No message id in the name of {}, skipping it",
+                    path.display()
+                );
+                continue;
+            };
+
+            let hash = match hasher.hash_file(&path) {
+                Ok(hash) => hash,
+                Err(e) => {
+                    warn!("Error hashing {}: {e}", path.display());
+                    continue;
+                }
+            };
+
+            let image = NewImage {
+                chat_id,
+                chat_title: &title,
+                message_id,
+                phash: hash,
+                alt_phash: None,
+                media_key: None,
+                media_ref: None,
+                sender_id: None,
+                forward: None,
+                spoiler: false,
+                low_entropy: hasher.is_low_entropy(hash),
+                source: &source,
+                caption: None,
+            };
+            throttle.wait().await;
+            database::save_image(&mut *pool.acquire().await?, &image).await?;
+            debug!("Indexed {} as message {message_id}", path.display());
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(WATCH_INTERVAL) => (),
+            _ = tokio::signal::ctrl_c() => {
+                info!("Stopped watching {}", dir.display());
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Files that are still being downloaded, by the names common tools give them.
+fn is_partial(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .unwrap_or_default();
+    name.starts_with('.') || name.ends_with(".part") || name.ends_with(".tmp")
+}
+
+/// The last number in the file's name.
+fn message_id(path: &Path) -> Option<i32> {
+    let stem = path.file_stem()?.to_str()?;
+    stem.split(|x: char| !x.is_ascii_digit())
+        .rfind(|x| !x.is_empty())?
+        .parse()
+        .ok()
+}
+
+/// Converts an export's `from_id` into the ids the bot sees.
+fn sender_id(from_id: &str) -> Option<i64> {
+    if let Some(id) = from_id.strip_prefix("user") {
+        return id.parse().ok();
+    }
+
+    let id = from_id.strip_prefix("channel")?.parse::<i64>().ok()?;
+    Some(channel_id(id))
+}
+
+/// Channels and supergroups get the -100 prefix in the Bot API.
+fn channel_id(id: i64) -> i64 {
+    -1_000_000_000_000 - id
+}
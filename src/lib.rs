@@ -0,0 +1,33 @@
+//! Duplicate image detection core: decoding, perceptual hashing and the Postgres-backed
+//! index. The Telegram bot in `main.rs` is just one frontend on top of this.
+
+pub mod archive;
+pub mod audit;
+pub mod backup;
+pub mod bench;
+pub mod config;
+pub mod dashboard;
+pub mod database;
+pub mod decode;
+pub mod detector;
+pub mod download_limiter;
+pub mod hashing;
+pub mod importer;
+pub mod locale;
+pub mod matching;
+pub mod messenger;
+pub mod notices;
+pub mod outbox;
+pub mod prefilter;
+pub mod preprocess;
+pub mod report;
+pub mod scan;
+pub mod scheduler;
+pub mod scripting;
+pub mod send_limiter;
+pub mod stats;
+pub mod tui;
+pub mod tune;
+pub mod verify;
+pub mod webhook;
+pub mod writer;
@@ -0,0 +1,93 @@
+//! Per-chat timezone and date formatting.
+
+use chrono_tz::Tz;
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// How a chat wants dates shown. Defaults to UTC and ISO dates.
+#[derive(Debug, Clone, Copy)]
+pub struct Locale {
+    timezone: Tz,
+    date_format: &'static str,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self {
+            timezone: Tz::UTC,
+            date_format: ISO_FORMAT,
+        }
+    }
+}
+
+const ISO_FORMAT: &str = "%Y-%m-%d %H:%M %Z";
+
+/// Date formats by language, or language and region. Anything not listed gets ISO dates.
+const DATE_FORMATS: &[(&str, &str)] = &[
+    ("en-us", "%m/%d/%Y %I:%M %p %Z"),
+    ("en", "%d/%m/%Y %H:%M %Z"),
+    ("fr", "%d/%m/%Y %H:%M %Z"),
+    ("es", "%d/%m/%Y %H:%M %Z"),
+    ("it", "%d/%m/%Y %H:%M %Z"),
+    ("pt", "%d/%m/%Y %H:%M %Z"),
+    ("nl", "%d-%m-%Y %H:%M %Z"),
+    ("de", "%d.%m.%Y %H:%M %Z"),
+    ("cs", "%d.%m.%Y %H:%M %Z"),
+    ("sk", "%d.%m.%Y %H:%M %Z"),
+    ("pl", "%d.%m.%Y %H:%M %Z"),
+    ("ru", "%d.%m.%Y %H:%M %Z"),
+    ("uk", "%d.%m.%Y %H:%M %Z"),
+    ("fi", "%d.%m.%Y %H:%M %Z"),
+    ("nb", "%d.%m.%Y %H:%M %Z"),
+    ("da", "%d.%m.%Y %H:%M %Z"),
+    ("tr", "%d.%m.%Y %H:%M %Z"),
+    ("ja", "%Y/%m/%d %H:%M %Z"),
+    ("zh", "%Y/%m/%d %H:%M %Z"),
+];
+
+impl Locale {
+    /// Unknown timezones and locales fall back to the defaults.
+    pub fn new(timezone: Option<&str>, locale: Option<&str>) -> Self {
+        Self {
+            timezone: timezone.and_then(parse_timezone).unwrap_or(Tz::UTC),
+            date_format: locale.map(date_format).unwrap_or(ISO_FORMAT),
+        }
+    }
+
+    pub fn format_date(&self, at: DateTime<Utc>) -> String {
+        at.with_timezone(&self.timezone)
+            .format(self.date_format)
+            .to_string()
+    }
+}
+
+/// An IANA timezone name like `Europe/Prague`.
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+    name.trim().parse().ok()
+}
+
+/// Normalizes a locale like `en_US` to `en-us`, if it looks like one at all.
+pub fn parse_locale(locale: &str) -> Option<String> {
+    let locale = locale.trim().to_ascii_lowercase().replace('_', "-");
+    let (language, region) = match locale.split_once('-') {
+        Some((language, region)) => (language, Some(region)),
+        None => (locale.as_str(), None),
+    };
+
+    let valid = (2..=3).contains(&language.len())
+        && language.chars().all(|x| x.is_ascii_alphabetic())
+        && region.is_none_or(|x| {
+            (2..=3).contains(&x.len()) && x.chars().all(|x| x.is_ascii_alphanumeric())
+        });
+
+    valid.then_some(locale)
+}
+
+/// The format for the most specific match of the locale, trying `en-us` before `en`.
+fn date_format(locale: &str) -> &'static str {
+    let language = locale.split('-').next().unwrap_or(locale);
+
+    [locale, language]
+        .into_iter()
+        .find_map(|key| DATE_FORMATS.iter().find(|(x, _)| *x == key))
+        .map_or(ISO_FORMAT, |(_, format)| format)
+}
@@ -0,0 +1,127 @@
+use anyhow::Result;
+use dupfinder_tg::config::{LogFormat, LoggingSettings};
+use tracing::level_filters::LevelFilter;
+use tracing::subscriber;
+use tracing_log::LogTracer;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{EnvFilter, fmt};
+
+/// Flushes exported spans when dropped, keep it alive until the end of main.
+pub struct Guard {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+    #[cfg(feature = "sentry")]
+    _sentry: Option<sentry::ClientInitGuard>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take()
+            && let Err(e) = provider.shutdown()
+        {
+            eprintln!("Error shutting down the OTLP exporter: {e}");
+        }
+    }
+}
+
+pub fn init(settings: &LoggingSettings) -> Result<Guard> {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+
+    let json = settings.format == LogFormat::Json;
+
+    // Captures panics by itself, error events come in through the tracing layer below.
+    #[cfg(feature = "sentry")]
+    let sentry = settings.sentry.as_ref().map(|sentry| {
+        sentry::init((
+            sentry.dsn.as_str(),
+            sentry::ClientOptions {
+                release: sentry::release_name!(),
+                environment: sentry.environment.clone().map(Into::into),
+                ..Default::default()
+            },
+        ))
+    });
+
+    #[cfg(feature = "otel")]
+    let (otel, provider) = match &settings.otlp {
+        Some(otlp) => {
+            let (layer, provider) = otel::layer(otlp)?;
+            (Some(layer), Some(provider))
+        }
+        None => (None, None),
+    };
+
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with((!json).then(|| fmt::layer().without_time().with_target(false)))
+        // Log aggregators want timestamps and the structured fields as-is.
+        .with(json.then(|| fmt::layer().json().flatten_event(true)));
+
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otel);
+
+    #[cfg(feature = "sentry")]
+    let registry = registry.with(sentry.is_some().then(sentry::integrations::tracing::layer));
+
+    subscriber::set_global_default(registry).unwrap();
+
+    // Log tracing adapter for teloxide.
+    LogTracer::init().unwrap();
+
+    #[cfg(not(feature = "otel"))]
+    if settings.otlp.is_some() {
+        tracing::warn!("OTLP export is configured, but this build doesn't have the `otel` feature");
+    }
+
+    #[cfg(not(feature = "sentry"))]
+    if settings.sentry.is_some() {
+        tracing::warn!("Sentry is configured, but this build doesn't have the `sentry` feature");
+    }
+
+    Ok(Guard {
+        #[cfg(feature = "otel")]
+        provider,
+        #[cfg(feature = "sentry")]
+        _sentry: sentry,
+    })
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use anyhow::Result;
+    use dupfinder_tg::config::OtlpSettings;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::registry::LookupSpan;
+
+    pub fn layer<S>(
+        settings: &OtlpSettings,
+    ) -> Result<(OpenTelemetryLayer<S, SdkTracer>, SdkTracerProvider)>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(&settings.endpoint)
+            .build()?;
+
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(settings.service_name.clone())
+                    .build(),
+            )
+            .build();
+
+        let tracer = provider.tracer("dupfinder-tg");
+
+        Ok((tracing_opentelemetry::layer().with_tracer(tracer), provider))
+    }
+}
@@ -1,18 +1,19 @@
 mod bot;
-mod config;
-mod database;
-mod importer;
+mod doctor;
+mod init;
+mod logging;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use clap::{Parser, Subcommand};
-use config::Config;
+use dupfinder_tg::archive::Archive;
+use dupfinder_tg::audit;
+use dupfinder_tg::config::Config;
+use dupfinder_tg::hashing::Hasher;
+use dupfinder_tg::{backup, bench, dashboard, database, importer, report, stats, tui, tune};
+use serde_json::json;
 use std::path::PathBuf;
 use tokio::fs;
-use tracing::level_filters::LevelFilter;
-use tracing::{info, subscriber};
-use tracing_log::LogTracer;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::{EnvFilter, fmt};
+use tracing::{error, info};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -30,58 +31,536 @@ pub struct Cli {
 pub enum Command {
     /// Run the Telegram bot and listen for new messages
     Run,
-    /// Import data from a Telegram JSON chat export
+    /// Interactively write a config file, checking the token and database on the way
+    Init,
+    /// Check the database, Telegram, the archive and clocks, and print what's wrong
+    Doctor,
+    /// Import data from a Telegram JSON export of a chat or a whole account
     Import {
-        /// Path to the chat export's result.json file
+        /// Path to the export's result.json file, or the directory to watch
         #[arg(required = true)]
         path: PathBuf,
-        /// the BOT-FACING chat id (might be different from the one in the file)
+        /// Keep watching the directory at `path` and index image files as they appear in it,
+        /// named after the message they're from
+        #[arg(long, requires = "chat_id", conflicts_with_all = ["chat_name", "dedup", "skip_existing"])]
+        watch: bool,
+        /// the BOT-FACING chat id (might be different from the one in the file), worked out
+        /// from the export if left out
+        #[arg(allow_negative_numbers = true)]
+        chat_id: Option<i64>,
+        /// Chats of an account export to import, asked for interactively if there are
+        /// several and none are given
+        #[arg(long)]
+        chat_name: Vec<String>,
+        /// Only index the earliest of images in the export that are duplicates of each
+        /// other, and record the rest as sightings of it
+        #[arg(long)]
+        dedup: bool,
+        /// Don't hash images of messages that are already indexed, for importing a newer
+        /// export of the same chat
+        #[arg(long)]
+        skip_existing: bool,
+        /// Write at most this many images a second, so importing while the bot runs doesn't
+        /// slow down its duplicate checks
+        #[arg(long)]
+        max_rate: Option<f64>,
+    },
+    /// List past imports, with what each of them did
+    ImportRuns {
+        /// Only show imports into this chat
+        #[arg(long, allow_negative_numbers = true)]
+        chat_id: Option<i64>,
+        #[arg(long, default_value_t = 20)]
+        limit: i64,
+    },
+    /// List what the bot and admins did, newest first
+    AuditLog {
+        /// Only show actions in this chat
+        #[arg(long, allow_negative_numbers = true)]
+        chat_id: Option<i64>,
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Find indexed images by their caption or their chat's title, best matches first
+    Search {
+        /// Words to look for, "quotes" for a phrase, `or` between alternatives and a leading
+        /// `-` to leave a word out
+        #[arg(required = true)]
+        query: String,
+        /// Only search this chat
+        #[arg(long, allow_negative_numbers = true)]
+        chat_id: Option<i64>,
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Print the size and health of the database, how many images and duplicates each chat
+    /// has, the distances duplicates were detected at and how the index grew month by month
+    Stats {
+        /// Only show this chat
+        #[arg(long, allow_negative_numbers = true)]
+        chat_id: Option<i64>,
+    },
+    /// List where indexed images came from, live or which import
+    Sources,
+    /// Delete every image of one source, they're removed for good with the next purge
+    PruneSource {
+        /// As listed by `sources`, e.g. "import:result.json"
+        #[arg(required = true)]
+        source: String,
+    },
+    /// Bring back the images of a pruned source that weren't purged yet
+    RestoreSource {
+        /// The source given to `prune-source`
+        #[arg(required = true)]
+        source: String,
+    },
+    /// Delete everything stored about a chat, after showing how much that is
+    DeleteChat {
+        /// the BOT-FACING chat id
         #[arg(required = true, allow_negative_numbers = true)]
         chat_id: i64,
+        /// Don't ask before deleting
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Move everything imported into one chat id to another, e.g. after importing with the
+    /// wrong one
+    RemapChat {
+        /// The chat id the images are stored under now
+        #[arg(long, required = true, allow_negative_numbers = true)]
+        from: i64,
+        /// The BOT-FACING chat id they belong to
+        #[arg(long, required = true, allow_negative_numbers = true)]
+        to: i64,
+    },
+    /// Serve only the admin dashboard, without the bot
+    Dashboard,
+    /// Print a histogram of distances between a chat's images and suggest a threshold
+    Tune {
+        /// the BOT-FACING chat id
+        #[arg(required = true, allow_negative_numbers = true)]
+        chat_id: i64,
+        /// How many images to sample, the work grows quadratically with this
+        #[arg(long, default_value_t = 2000)]
+        sample: i64,
+    },
+    /// Render a chat's duplicate clusters into a standalone HTML file
+    Report {
+        /// the BOT-FACING chat id
+        #[arg(long, required = true, allow_negative_numbers = true)]
+        chat_id: i64,
+        #[arg(long, default_value = "report.html")]
+        out: PathBuf,
+    },
+    /// Browse chats, images and duplicate clusters in the terminal
+    Tui,
+    /// Dump the database, and optionally the image archive, into a single file
+    Backup {
+        #[arg(long, default_value = "dupfinder-backup.tar.zst")]
+        out: PathBuf,
+        /// Include the archived images, needs [archive] in the config
+        #[arg(long)]
+        with_images: bool,
+    },
+    /// Load a backup into an empty database, e.g. on a new server
+    Restore {
+        #[arg(required = true)]
+        path: PathBuf,
+        /// Put the backed up images into the archive configured here
+        #[arg(long)]
+        with_images: bool,
+    },
+    /// Probe a sample of indexed messages once and mark the deleted ones stale, needs
+    /// [stale-check] and a bot
+    CheckStale {
+        /// Only check this chat
+        #[arg(long, allow_negative_numbers = true)]
+        chat_id: Option<i64>,
+    },
+    /// Compare hash algorithms and sizes on a labeled dataset of image pairs
+    BenchHash {
+        /// Directory with `same/` and `different/` subdirectories of image pairs
+        #[arg(long, required = true)]
+        dataset: PathBuf,
+    },
+    /// Time scanning hashes in memory for the nearest one
+    BenchScan {
+        #[arg(long, default_value_t = 1_000_000)]
+        entries: usize,
     },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let filter = EnvFilter::builder()
-        .with_default_directive(LevelFilter::INFO.into())
-        .from_env_lossy();
-
-    let registry = tracing_subscriber::registry()
-        .with(filter)
-        .with(fmt::layer().without_time().with_target(false));
-
-    subscriber::set_global_default(registry).unwrap();
-
-    // Log tracing adapter for teloxide.
-    LogTracer::init().unwrap();
-
     let cli = Cli::parse();
 
+    // There's no config to read yet.
+    if let Command::Init = cli.command {
+        return init::run(&cli.config).await;
+    }
+
     let config = fs::read_to_string(&cli.config)
         .await
         .context("error reading config")?;
 
     let config = toml::from_str::<Config>(&config).context("error parsing config")?;
 
+    let _logging = logging::init(&config.logging)?;
+
+    let hasher = Hasher::new(&config.hashing).context("invalid hashing settings")?;
+
+    // Reports database problems instead of failing on them.
+    if let Command::Doctor = cli.command {
+        if !doctor::run(&config).await {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Doesn't need the database.
+    if let Command::BenchHash { dataset } = &cli.command {
+        let dataset = dataset.clone();
+        let settings = config.hashing.clone();
+        return tokio::task::spawn_blocking(move || bench::run(&dataset, &settings))
+            .await?
+            .context("benchmark failed");
+    }
+
+    if let Command::BenchScan { entries } = cli.command {
+        bench::scan(entries);
+        return Ok(());
+    }
+
     info!("Configuration loaded. Connecting to database...");
 
     let pool = database::init_pool(&config.database.url).await?;
 
-    sqlx::migrate!("./migrations").run(&pool).await?;
+    database::migrate(&pool).await?;
 
     info!("Database connected.");
 
     match cli.command {
         Command::Run => {
+            if let Some(settings) = config.dashboard.clone() {
+                let pool = pool.clone();
+                let bits = hasher.bits();
+                tokio::spawn(async move {
+                    if let Err(e) = dashboard::run(settings, pool, bits).await {
+                        error!("Dashboard stopped: {e}");
+                    }
+                });
+            }
+
             info!("Starting bot...");
-            bot::run(config, pool).await?;
+            bot::run(config, pool, hasher).await?;
         }
-        Command::Import { path, chat_id } => {
+        Command::Import {
+            path,
+            chat_id,
+            watch,
+            chat_name,
+            dedup,
+            skip_existing,
+            max_rate,
+        } => {
+            let mut options = importer::ImportOptions {
+                dedup: None,
+                skip_existing,
+                throttle: importer::Throttle::new(max_rate),
+            };
+
+            if watch && let Some(chat_id) = chat_id {
+                importer::watch(&pool, &hasher, &path, chat_id, &mut options.throttle).await?;
+                return Ok(());
+            }
+
+            let export = importer::Export::open(&path)?;
+
             info!("Running importer...");
-            importer::run(&pool, &path, chat_id).await?;
+            for (chat, chat_id) in import_targets(&export, chat_id, &chat_name)? {
+                options.dedup = match dedup {
+                    true => Some(
+                        database::chat_threshold(&pool, chat_id)
+                            .await?
+                            .unwrap_or_else(|| config.global_threshold(hasher.bits())),
+                    ),
+                    false => None,
+                };
+
+                importer::run(&pool, &hasher, &export, chat, chat_id, &mut options).await?;
+            }
+        }
+        Command::ImportRuns { chat_id, limit } => {
+            for run in database::import_runs(&pool, chat_id, limit).await? {
+                let duration = run
+                    .finished_at
+                    .map(|x| format!("{}s", (x - run.started_at).num_seconds()))
+                    .unwrap_or_else(|| "unfinished".to_owned());
+
+                println!(
+                    "{started}  chat {chat_id}  {hashed} hashed  {skipped} skipped  {failed} failed  \
+                     {duration}  {source}{error}",
+                    started = run.started_at.format("%Y-%m-%d %H:%M:%S"),
+                    chat_id = run.chat_id,
+                    hashed = run.hashed,
+                    skipped = run.skipped,
+                    failed = run.failed,
+                    source = run.source,
+                    error = run
+                        .error
+                        .map(|x| format!("  error: {x}"))
+                        .unwrap_or_default(),
+                );
+            }
+        }
+        Command::AuditLog { chat_id, limit } => {
+            for entry in database::audit_log(&pool, chat_id, limit).await? {
+                println!(
+                    "{created}  {chat_id:>16}  {actor:>12}  {action:<10}  {payload}",
+                    created = entry.created_at.format("%Y-%m-%d %H:%M:%S"),
+                    chat_id = entry.chat_id.map(|x| x.to_string()).unwrap_or_default(),
+                    actor = entry
+                        .actor_id
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| "bot".to_owned()),
+                    action = entry.action,
+                    payload = entry.payload,
+                );
+            }
+        }
+        Command::Search {
+            query,
+            chat_id,
+            limit,
+        } => {
+            for image in database::search_archive(&pool, chat_id, &query, limit).await? {
+                println!(
+                    "{created}  {chat_id:>16}  {message_id:>8}  {title}  {caption}",
+                    created = image.created_at.format("%Y-%m-%d %H:%M:%S"),
+                    chat_id = image.chat_id,
+                    message_id = image.message_id,
+                    title = image.chat_title,
+                    caption = image.caption.unwrap_or_default(),
+                );
+            }
+        }
+        Command::Stats { chat_id } => {
+            stats::run(&pool, chat_id).await?;
+        }
+        Command::Sources => {
+            for (source, images) in database::image_sources(&pool).await? {
+                println!("{images:>10}  {source}");
+            }
+        }
+        Command::PruneSource { source } => {
+            if source == database::LIVE_SOURCE {
+                bail!("refusing to prune the images the bot collected itself");
+            }
+
+            let deleted = database::delete_source(&pool, &source).await?;
+            audit::record(
+                &pool,
+                None,
+                None,
+                audit::Action::Forget,
+                json!({ "source": source, "images": deleted }),
+            )
+            .await;
+            println!("Deleted {deleted} images from {source}, `restore-source` brings them back.");
+        }
+        Command::RestoreSource { source } => {
+            let restored = database::restore_source(&pool, &source).await?;
+            audit::record(
+                &pool,
+                None,
+                None,
+                audit::Action::Restore,
+                json!({ "source": source, "images": restored }),
+            )
+            .await;
+            println!("Restored {restored} images of {source}.");
+        }
+        Command::DeleteChat { chat_id, yes } => {
+            let chat = database::chat_stats(&pool)
+                .await?
+                .into_iter()
+                .find(|x| x.id == chat_id)
+                .with_context(|| format!("nothing is stored about chat {chat_id}"))?;
+
+            println!(
+                "{title} ({chat_id}) has {images} images and {sightings} duplicates recorded.",
+                title = chat.title,
+                images = chat.images,
+                sightings = chat.sightings,
+            );
+            if !yes
+                && !init::prompt("Delete all of it? (y/n)", Some("n"))?.eq_ignore_ascii_case("y")
+            {
+                println!("Nothing was deleted.");
+                return Ok(());
+            }
+
+            let deleted = database::delete_chat(&pool, chat_id).await?;
+            audit::record(
+                &pool,
+                Some(chat_id),
+                None,
+                audit::Action::Forget,
+                json!({ "reason": "delete-chat", "images": deleted }),
+            )
+            .await;
+            println!("Deleted chat {chat_id} and its {deleted} images.");
+        }
+        Command::RemapChat { from, to } => {
+            if from == to {
+                bail!("the chat ids are the same");
+            }
+
+            let moved = database::remap_chat(&pool, from, to).await?;
+            println!("Moved {moved} images from chat {from} to chat {to}.");
+        }
+        Command::Dashboard => {
+            let settings = config
+                .dashboard
+                .context("no [dashboard] section in the config")?;
+            dashboard::run(settings, pool, hasher.bits()).await?;
+        }
+        Command::Tune { chat_id, sample } => {
+            tune::run(&pool, chat_id, sample, hasher.bits()).await?;
+        }
+        Command::Report { chat_id, out } => {
+            let archive = config.archive.as_ref().map(Archive::new).transpose()?;
+            report::run(&pool, archive.as_ref(), chat_id, &out, bot::message_link).await?;
+        }
+        Command::Tui => {
+            tui::run(pool).await?;
+        }
+        Command::Backup { out, with_images } => {
+            let archive = archive(&config, with_images)?;
+            backup::backup(&pool, archive.as_ref(), &out).await?;
+        }
+        Command::Restore { path, with_images } => {
+            let archive = archive(&config, with_images)?;
+            backup::restore(&pool, archive.as_ref(), &path).await?;
+        }
+        Command::CheckStale { chat_id } => {
+            let settings = config
+                .stale_check
+                .as_ref()
+                .context("no [stale-check] section in the config")?;
+            let bot_settings = config.bots().next().context("no bots configured")?;
+            let chats = match chat_id {
+                Some(chat_id) => vec![chat_id],
+                None => bot_settings.allowed_chats.clone(),
+            };
+
+            let bot = bot::bot(bot_settings)?;
+            let result =
+                bot::stale_check::check(&bot, &pool, settings, config.purge_stale, &chats).await?;
+            println!(
+                "Checked {} indexed messages, {} of them were gone",
+                result.checked, result.stale
+            );
+        }
+        Command::Init => unreachable!("handled before reading the config"),
+        Command::Doctor => unreachable!("handled before connecting to the database"),
+        Command::BenchHash { .. } | Command::BenchScan { .. } => {
+            unreachable!("handled before connecting to the database")
         }
     }
 
     Ok(())
 }
+
+/// The chats of the export to import and the bot-facing ids to import them into.
+fn import_targets<'a>(
+    export: &'a importer::Export,
+    chat_id: Option<i64>,
+    names: &[String],
+) -> Result<Vec<(&'a importer::Chat, i64)>> {
+    let chats = match (export.chats(), names) {
+        ([chat], []) => vec![chat],
+        (chats, []) => return pick_chats(chats, chat_id),
+        (chats, names) => names
+            .iter()
+            .map(|name| {
+                chats
+                    .iter()
+                    .find(|x| x.name() == name)
+                    .with_context(|| format!("the export has no chat named {name:?}"))
+            })
+            .collect::<Result<_>>()?,
+    };
+
+    if chat_id.is_some() && chats.len() > 1 {
+        bail!("a chat id can only be given when importing a single chat");
+    }
+
+    chats
+        .into_iter()
+        .map(|chat| {
+            let chat_id = chat_id.or(chat.bot_chat_id()).with_context(|| {
+                format!("can't tell the id of {:?}, pass it explicitly", chat.name())
+            })?;
+            anyhow::Ok((chat, chat_id))
+        })
+        .collect()
+}
+
+/// Lists the chats and asks which to import, and into which bot-facing ids.
+fn pick_chats(
+    chats: &[importer::Chat],
+    chat_id: Option<i64>,
+) -> Result<Vec<(&importer::Chat, i64)>> {
+    println!("The export has {} chats:", chats.len());
+    for (i, chat) in chats.iter().enumerate() {
+        println!(
+            "{:>4}. {} ({} images)",
+            i + 1,
+            chat.name(),
+            chat.media_count()
+        );
+    }
+
+    let answer = init::prompt("Chats to import, as numbers separated by commas", None)?;
+    let picked = answer
+        .split(',')
+        .map(|x| {
+            x.trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|x| chats.get(x.checked_sub(1)?))
+                .with_context(|| format!("{:?} isn't one of the listed numbers", x.trim()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if chat_id.is_some() && picked.len() > 1 {
+        bail!("a chat id can only be given when importing a single chat");
+    }
+
+    picked
+        .into_iter()
+        .map(|chat| {
+            let default = chat_id.or(chat.bot_chat_id()).map(|x| x.to_string());
+            let question = format!("Bot-facing chat id for {:?}", chat.name());
+            let chat_id = init::prompt(&question, default.as_deref())?
+                .parse()
+                .context("chat ids are numbers")?;
+            anyhow::Ok((chat, chat_id))
+        })
+        .collect()
+}
+
+/// The configured archive if `wanted`, complaining if there's none.
+fn archive(config: &Config, wanted: bool) -> Result<Option<Archive>> {
+    if !wanted {
+        return Ok(None);
+    }
+
+    let settings = config
+        .archive
+        .as_ref()
+        .context("no [archive] section in the config")?;
+
+    Ok(Some(Archive::new(settings)?))
+}
@@ -0,0 +1,302 @@
+use crate::database::{self, Candidate, ClosestMatch, NewImage};
+use crate::locale::Locale;
+use crate::messenger::MessageRef;
+use crate::prefilter::Prefilter;
+use crate::writer::Writer;
+use chrono::TimeDelta;
+use sqlx::PgPool;
+
+/// Result of checking an incoming image against a chat's index.
+pub enum Outcome {
+    /// A close enough image was already indexed, nothing was stored.
+    Duplicate(ClosestMatch),
+    /// No match, the image was added to the index.
+    New,
+}
+
+/// Frontend-agnostic duplicate detection on top of the image index.
+#[derive(Clone)]
+pub struct Matcher {
+    pool: PgPool,
+    /// Where match queries go, the same as `pool` unless there's a read replica.
+    read_pool: PgPool,
+    threshold: u8,
+    bits: u8,
+    writer: Option<Writer>,
+    prefilter: Option<Prefilter>,
+    purge_stale: bool,
+    match_low_entropy: bool,
+}
+
+impl Matcher {
+    /// `bits` is the hash width, which is the largest distance two hashes can have.
+    pub fn new(pool: PgPool, threshold: u8, bits: u32) -> Self {
+        Self {
+            read_pool: pool.clone(),
+            pool,
+            threshold,
+            bits: bits as u8,
+            writer: None,
+            prefilter: None,
+            purge_stale: false,
+            match_low_entropy: false,
+        }
+    }
+
+    /// Hands new images to the batch writer instead of inserting them right away.
+    pub fn with_writer(mut self, writer: Writer) -> Self {
+        self.writer = Some(writer);
+        self
+    }
+
+    /// Sends match queries to a read replica. Images indexed within the replication lag
+    /// aren't matched against.
+    pub fn with_read_pool(mut self, pool: PgPool) -> Self {
+        self.read_pool = pool;
+        self
+    }
+
+    /// Skips the database for images the prefilter rules out.
+    pub fn with_prefilter(mut self, prefilter: Prefilter) -> Self {
+        self.prefilter = Some(prefilter);
+        self
+    }
+
+    /// Deletes images [`Matcher::mark_stale`] is told about instead of only skipping them.
+    pub fn with_purge_stale(mut self) -> Self {
+        self.purge_stale = true;
+        self
+    }
+
+    /// Matches low-entropy images like any other.
+    pub fn with_low_entropy_matching(mut self) -> Self {
+        self.match_low_entropy = true;
+        self
+    }
+
+    /// Stops matching against an image whose message turned out to be deleted.
+    pub async fn mark_stale(&self, message: MessageRef) -> sqlx::Result<()> {
+        database::mark_stale(&self.pool, message, self.purge_stale).await
+    }
+
+    /// Whether low-entropy images are matched, see [`Matcher::with_low_entropy_matching`].
+    pub fn matches_low_entropy(&self) -> bool {
+        self.match_low_entropy
+    }
+
+    /// How alike two images `distance` apart are, in percent.
+    pub fn similarity(&self, distance: u8) -> f64 {
+        similarity(distance.into(), self.bits.into())
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Checks the image against the chat's index and indexes it if it's new.
+    pub async fn process(&self, image: &NewImage<'_>) -> sqlx::Result<Outcome> {
+        let threshold = self.threshold(image.chat_id).await?;
+        let outcome = match self.find(image, threshold).await? {
+            Some(closest) => Outcome::Duplicate(closest),
+            None => Outcome::New,
+        };
+
+        self.record(image, outcome, Some(threshold)).await
+    }
+
+    /// The chat's own threshold if it has one, the global one otherwise.
+    pub async fn threshold(&self, chat_id: i64) -> sqlx::Result<u8> {
+        Ok(database::chat_threshold(&self.pool, chat_id)
+            .await?
+            .unwrap_or(self.threshold))
+    }
+
+    /// How the chat wants dates shown.
+    pub async fn locale(&self, chat_id: i64) -> sqlx::Result<Locale> {
+        let (timezone, locale) = database::chat_locale(&self.pool, chat_id).await?;
+        Ok(Locale::new(timezone.as_deref(), locale.as_deref()))
+    }
+
+    /// Whether duplicates in the chat go without a reply, see [`database::set_chat_silent`].
+    pub async fn is_silent(&self, chat_id: i64) -> sqlx::Result<bool> {
+        Ok(database::chat_settings(&self.pool, chat_id)
+            .await?
+            .is_some_and(|x| x.silent))
+    }
+
+    /// Reposts of images younger than this aren't flagged in the chat.
+    pub async fn min_repost_age(&self, chat_id: i64) -> sqlx::Result<Option<TimeDelta>> {
+        let secs = database::chat_min_repost_age(&self.pool, chat_id).await?;
+        Ok(secs.map(|x| TimeDelta::seconds(x.into())))
+    }
+
+    /// Finds the closest indexed image within `threshold`, without recording anything.
+    pub async fn find(
+        &self,
+        image: &NewImage<'_>,
+        threshold: u8,
+    ) -> sqlx::Result<Option<ClosestMatch>> {
+        if image.low_entropy && !self.match_low_entropy {
+            return Ok(None);
+        }
+
+        if let Some(prefilter) = &self.prefilter {
+            let hashes = hashes(image);
+            if !prefilter
+                .may_match(&self.pool, image.chat_id, &hashes, threshold)
+                .await?
+            {
+                return Ok(None);
+            }
+        }
+
+        database::find_closest_match(
+            &mut *self.read_pool.acquire().await?,
+            image.chat_id,
+            image.phash,
+            image.alt_phash,
+            threshold,
+            None,
+            self.match_low_entropy,
+        )
+        .await
+    }
+
+    /// Stores the outcome of [`Matcher::find`]: a sighting for duplicates, the image itself
+    /// otherwise, and returns what was stored.
+    ///
+    /// A new image is first checked again within `recheck` under a lock on the chat's index,
+    /// since a copy sent at the same time may have been indexed in the meantime by another bot
+    /// or replica. The check reads from the primary, but can't see images still queued in the
+    /// batch writer.
+    pub async fn record(
+        &self,
+        image: &NewImage<'_>,
+        outcome: Outcome,
+        recheck: Option<u8>,
+    ) -> sqlx::Result<Outcome> {
+        if let Outcome::Duplicate(closest) = &outcome {
+            database::save_sighting(&self.pool, image, closest).await?;
+            return Ok(outcome);
+        }
+
+        let mut tx = self.pool.begin().await?;
+        database::lock_chat_index(&mut tx, image.chat_id).await?;
+
+        if let Some(threshold) = recheck
+            && (!image.low_entropy || self.match_low_entropy)
+            && let Some(closest) = database::find_closest_match(
+                &mut tx,
+                image.chat_id,
+                image.phash,
+                image.alt_phash,
+                threshold,
+                None,
+                self.match_low_entropy,
+            )
+            .await?
+        {
+            database::save_sighting(&mut *tx, image, &closest).await?;
+            tx.commit().await?;
+
+            return Ok(Outcome::Duplicate(closest));
+        }
+
+        if let Some(prefilter) = &self.prefilter {
+            prefilter.insert(image.chat_id, &hashes(image));
+        }
+
+        match &self.writer {
+            Some(writer) => writer.save(image).await,
+            None => database::save_image(&mut tx, image).await?,
+        }
+
+        tx.commit().await?;
+
+        Ok(Outcome::New)
+    }
+
+    /// Restores the chat's most recently deleted image, see [`database::undo_last_delete`].
+    pub async fn undo_last_delete(&self, chat_id: i64) -> sqlx::Result<Option<i32>> {
+        let restored = database::undo_last_delete(&self.pool, chat_id).await?;
+
+        // The filters don't know about the image anymore.
+        if restored.is_some()
+            && let Some(prefilter) = &self.prefilter
+        {
+            prefilter.forget(chat_id);
+        }
+
+        Ok(restored)
+    }
+
+    /// Links a discussion group to its channel, see [`database::link_chats`].
+    pub async fn link_chats(&self, group_id: i64, channel_id: i64) -> sqlx::Result<()> {
+        let linked = database::link_chats(&self.pool, group_id, channel_id).await?;
+
+        // The group's filters don't have the channel's images yet.
+        if linked && let Some(prefilter) = &self.prefilter {
+            prefilter.forget(group_id);
+        }
+
+        Ok(())
+    }
+
+    /// The `limit` indexed images closest to the hash, see [`database::nearest_images`].
+    pub async fn candidates(
+        &self,
+        chat_id: i64,
+        hash: i64,
+        alt_hash: Option<i64>,
+        exclude_message_id: Option<i32>,
+        limit: i64,
+    ) -> sqlx::Result<Vec<Candidate>> {
+        database::nearest_images(
+            &mut *self.read_pool.acquire().await?,
+            chat_id,
+            hash,
+            alt_hash,
+            exclude_message_id,
+            limit,
+        )
+        .await
+    }
+
+    /// Finds the closest indexed image regardless of the threshold, not counting
+    /// `exclude_message_id` (usually the image being asked about).
+    pub async fn closest(
+        &self,
+        chat_id: i64,
+        hash: i64,
+        alt_hash: Option<i64>,
+        exclude_message_id: Option<i32>,
+    ) -> sqlx::Result<Option<ClosestMatch>> {
+        database::find_closest_match(
+            &mut *self.read_pool.acquire().await?,
+            chat_id,
+            hash,
+            alt_hash,
+            self.bits,
+            exclude_message_id,
+            self.match_low_entropy,
+        )
+        .await
+    }
+}
+
+/// The largest distance between `bits` bit hashes that's still `percent` similar.
+pub fn max_distance(percent: u8, bits: u32) -> u8 {
+    let percent = percent.min(100) as u32;
+    (bits * (100 - percent) / 100) as u8
+}
+
+/// Share of the `bits` bits two hashes `distance` apart have in common, in percent.
+pub fn similarity(distance: u32, bits: u32) -> f64 {
+    (bits.saturating_sub(distance)) as f64 / bits as f64 * 100.0
+}
+
+fn hashes(image: &NewImage<'_>) -> Vec<i64> {
+    std::iter::once(image.phash)
+        .chain(image.alt_phash)
+        .collect()
+}
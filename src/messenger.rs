@@ -0,0 +1,85 @@
+use std::future::Future;
+
+/// Points at a single message in a chat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageRef {
+    pub chat_id: i64,
+    pub message_id: i32,
+}
+
+/// An image that arrived in a chat, not downloaded yet.
+#[derive(Debug, Clone)]
+pub struct IncomingImage<M> {
+    pub message: MessageRef,
+    pub chat_title: String,
+    pub media: M,
+    /// A smaller rendition of the same image at a fixed size, if the platform has one.
+    /// Platforms serving several sizes don't always hash the same across them, this one is
+    /// hashed too and compared against the same rendition of earlier images.
+    pub alternate: Option<M>,
+    /// Stays the same for the same file across messages (Telegram's file_unique_id),
+    /// unlike `media` which may be tied to the bot or expire.
+    pub media_key: String,
+    /// Who posted it. Positive for users, negative chat ids for messages sent as a chat
+    /// (Telegram's anonymous admins and posts on behalf of a channel), which share one
+    /// id space this way.
+    pub sender_id: Option<i64>,
+    pub forward: Option<ForwardOrigin>,
+    /// Sent behind a spoiler.
+    pub spoiler: bool,
+    /// The text sent along with it.
+    pub caption: Option<String>,
+}
+
+/// Where a forwarded message originally came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForwardOrigin {
+    /// The original chat or user, in the same id space as [`IncomingImage::sender_id`].
+    /// Unknown if the user hides forwards.
+    pub from_id: Option<i64>,
+    /// The original message, only known for channel posts.
+    pub message_id: Option<i32>,
+}
+
+/// Everything the detection core needs from a chat platform. Implement this to put a
+/// different frontend (Discord, Matrix, ...) on top of the same index.
+pub trait Messenger: Sync {
+    /// Platform handle to downloadable media, e.g. a Telegram file id. It's stored as a string
+    /// so originals can be downloaded again later.
+    type Media: Send + Sync + ToString + From<String>;
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// `chat_id` is the chat the media was posted in, downloads may be limited per chat.
+    fn download(
+        &self,
+        chat_id: i64,
+        media: &Self::Media,
+    ) -> impl Future<Output = Result<Vec<u8>, Self::Error>> + Send;
+
+    /// With `spoiler` set, the reply is about a spoilered image and must not reveal it,
+    /// e.g. through link previews. Returns the reply.
+    fn reply(
+        &self,
+        to: MessageRef,
+        text: &str,
+        spoiler: bool,
+    ) -> impl Future<Output = Result<MessageRef, Self::Error>> + Send;
+
+    /// Replaces the text of one of the bot's own messages, `spoiler` as in [`Messenger::reply`].
+    fn edit(
+        &self,
+        message: MessageRef,
+        text: &str,
+        spoiler: bool,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    fn delete(&self, message: MessageRef) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// A link users can click to jump to the message, if the platform has such a thing.
+    fn message_link(&self, message: MessageRef) -> Option<String>;
+
+    /// Whether the error says the message acted on doesn't exist (anymore).
+    fn is_missing_message(_error: &Self::Error) -> bool {
+        false
+    }
+}
@@ -0,0 +1,139 @@
+use crate::messenger::{MessageRef, Messenger};
+use sqlx::PgPool;
+use std::time::Duration;
+use tracing::{debug, error};
+
+/// Telegram doesn't let bots delete messages older than this, so notices are forgotten then.
+const MAX_NOTICE_AGE_SECS: f64 = 48.0 * 60.0 * 60.0;
+
+/// The bot's duplicate notices, deleted after the chat's notice lifetime or once the repost
+/// they're about is gone where the chat has them cleaned up, and a worker that deletes them.
+#[derive(Clone)]
+pub struct Notices {
+    pool: PgPool,
+    bot_id: i64,
+}
+
+impl Notices {
+    /// `bot_id` tells the workers of several bots sharing the database apart.
+    pub fn new(pool: PgPool, bot_id: i64) -> Self {
+        Self { pool, bot_id }
+    }
+
+    /// Keeps track of a notice about `flagged`, for cleaning it up if its chat wants that and
+    /// for /why.
+    pub async fn record(&self, notice: MessageRef, flagged: MessageRef) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO notices (chat_id, message_id, bot_id, flagged_message_id)
+            SELECT id, $2, $3, $4 FROM chats
+            WHERE id = $1
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(notice.chat_id)
+        .bind(notice.message_id)
+        .bind(self.bot_id)
+        .bind(flagged.message_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deletes due notices until the process exits.
+    pub async fn run<M: Messenger>(self, messenger: M) {
+        let mut interval = tokio::time::interval(Duration::from_secs(30));
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.delete_due(&messenger).await {
+                error!("Error deleting notices: {e}");
+            }
+        }
+    }
+
+    async fn delete_due<M: Messenger>(&self, messenger: &M) -> sqlx::Result<()> {
+        sqlx::query("DELETE FROM notices WHERE created_at < NOW() - make_interval(secs => $1)")
+            .bind(MAX_NOTICE_AGE_SECS)
+            .execute(&self.pool)
+            .await?;
+
+        let due: Vec<(i64, i32)> = sqlx::query_as(
+            r#"
+            SELECT n.chat_id, n.message_id
+            FROM notices n
+            JOIN chats c ON c.id = n.chat_id
+            WHERE n.bot_id = $1
+                AND (n.created_at + make_interval(secs => c.notice_lifetime_secs) <= NOW()
+                    OR (c.delete_notices_with_repost AND n.repost_gone_at IS NOT NULL))
+            "#,
+        )
+        .bind(self.bot_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        for (chat_id, message_id) in due {
+            let notice = MessageRef {
+                chat_id,
+                message_id,
+            };
+
+            // Admins may have deleted it already, either way it's not tried again.
+            if let Err(e) = messenger.delete(notice).await {
+                debug!("Couldn't delete notice {message_id} in {chat_id}: {e}");
+            }
+
+            sqlx::query("DELETE FROM notices WHERE chat_id = $1 AND message_id = $2")
+                .bind(chat_id)
+                .bind(message_id)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The repost a notice is about, if it's recent enough to be known.
+#[derive(Debug, sqlx::FromRow)]
+pub struct Flagged {
+    pub message_id: i32,
+    pub sender_id: Option<i64>,
+    pub media_key: Option<String>,
+    pub media_ref: Option<String>,
+}
+
+/// Looks up the repost `notice` is about. Notices don't always reply to it, those sent where
+/// the original can't be linked to reply to the original instead.
+pub async fn flagged(pool: &PgPool, notice: MessageRef) -> sqlx::Result<Option<Flagged>> {
+    sqlx::query_as(
+        r#"
+        SELECT s.message_id, s.sender_id, s.media_key, s.media_ref
+        FROM notices n
+        JOIN sightings s ON s.chat_id = n.chat_id AND s.message_id = n.flagged_message_id
+        WHERE n.chat_id = $1 AND n.message_id = $2
+        "#,
+    )
+    .bind(notice.chat_id)
+    .bind(notice.message_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Marks the notices about a repost that was deleted, so they go too where the chat wants
+/// that.
+pub async fn repost_gone(pool: &PgPool, repost: MessageRef) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE notices SET repost_gone_at = NOW()
+        WHERE chat_id = $1 AND flagged_message_id = $2 AND repost_gone_at IS NULL
+        "#,
+    )
+    .bind(repost.chat_id)
+    .bind(repost.message_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use crate::audit;
+use crate::messenger::{MessageRef, Messenger};
+use crate::notices::Notices;
+use serde_json::json;
+use sqlx::PgPool;
+use sqlx::types::Uuid;
+use std::time::Duration;
+use tracing::{debug, error, warn};
+
+/// Replies are given up on after this many failed attempts.
+const MAX_ATTEMPTS: i32 = 10;
+
+/// Replies persisted in the database before they're sent, and a worker that delivers them
+/// with retries. A crash or a Telegram outage between detection and the reply doesn't lose it.
+#[derive(Clone)]
+pub struct Outbox {
+    pool: PgPool,
+    bot_id: i64,
+    notices: Notices,
+}
+
+#[derive(sqlx::FromRow)]
+struct Pending {
+    id: Uuid,
+    chat_id: i64,
+    reply_to: i32,
+    /// The repost the reply is about, unknown for replies queued before this was stored.
+    flagged_message_id: Option<i32>,
+    text: String,
+    spoiler: bool,
+    attempts: i32,
+}
+
+impl Outbox {
+    /// `bot_id` tells the workers of several bots sharing the database apart.
+    pub fn new(pool: PgPool, bot_id: i64) -> Self {
+        let notices = Notices::new(pool.clone(), bot_id);
+        Self {
+            pool,
+            bot_id,
+            notices,
+        }
+    }
+
+    /// Queues a reply to `to` about the repost `flagged`.
+    pub async fn enqueue(
+        &self,
+        to: MessageRef,
+        flagged: MessageRef,
+        text: &str,
+        spoiler: bool,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO outbox (bot_id, chat_id, reply_to, flagged_message_id, text, spoiler)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(self.bot_id)
+        .bind(to.chat_id)
+        .bind(to.message_id)
+        .bind(flagged.message_id)
+        .bind(text)
+        .bind(spoiler)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sends due replies until the process exits.
+    pub async fn run<M: Messenger>(self, messenger: M) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.flush(&messenger).await {
+                error!("Error flushing the outbox: {e}");
+            }
+        }
+    }
+
+    async fn flush<M: Messenger>(&self, messenger: &M) -> sqlx::Result<()> {
+        loop {
+            let mut tx = self.pool.begin().await?;
+
+            // SKIP LOCKED lets replicas share the outbox without sending anything twice.
+            let pending: Option<Pending> = sqlx::query_as(
+                r#"
+                SELECT id, chat_id, reply_to, flagged_message_id, text, spoiler, attempts
+                FROM outbox
+                WHERE bot_id = $1 AND next_attempt_at <= NOW()
+                ORDER BY next_attempt_at
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+                "#,
+            )
+            .bind(self.bot_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let Some(pending) = pending else {
+                return Ok(());
+            };
+
+            let to = MessageRef {
+                chat_id: pending.chat_id,
+                message_id: pending.reply_to,
+            };
+
+            match messenger.reply(to, &pending.text, pending.spoiler).await {
+                Ok(notice) => {
+                    debug!("Delivered reply to {} in {}", to.message_id, to.chat_id);
+                    if let Some(flagged_message_id) = pending.flagged_message_id {
+                        let flagged = MessageRef {
+                            chat_id: to.chat_id,
+                            message_id: flagged_message_id,
+                        };
+                        self.notices.record(notice, flagged).await?;
+                    }
+                    sqlx::query("DELETE FROM outbox WHERE id = $1")
+                        .bind(pending.id)
+                        .execute(&mut *tx)
+                        .await?;
+                    audit::record(
+                        &self.pool,
+                        Some(to.chat_id),
+                        None,
+                        audit::Action::Reply,
+                        json!({ "reply_to": to.message_id, "text": pending.text }),
+                    )
+                    .await;
+                }
+                Err(e) if pending.attempts + 1 >= MAX_ATTEMPTS => {
+                    error!(
+                        "Giving up on reply to {} in {} after {MAX_ATTEMPTS} attempts: {e}",
+                        to.message_id, to.chat_id
+                    );
+                    sqlx::query("DELETE FROM outbox WHERE id = $1")
+                        .bind(pending.id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                Err(e) => {
+                    // Exponential backoff, capped at about an hour.
+                    let delay = 2f64.powi(pending.attempts.min(12));
+                    warn!(
+                        "Error sending reply to {} in {}, retrying in {delay}s: {e}",
+                        to.message_id, to.chat_id
+                    );
+                    sqlx::query(
+                        r#"
+                        UPDATE outbox
+                        SET attempts = attempts + 1,
+                            next_attempt_at = NOW() + make_interval(secs => $2)
+                        WHERE id = $1
+                        "#,
+                    )
+                    .bind(pending.id)
+                    .bind(delay)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            }
+
+            tx.commit().await?;
+        }
+    }
+}
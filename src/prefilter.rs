@@ -0,0 +1,165 @@
+use crate::database;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Filter bits per stored hash, about a 1% false positive rate per band lookup.
+const BITS_PER_ENTRY: usize = 10;
+const HASH_FUNCTIONS: u64 = 7;
+
+/// Room left for new images when a filter is built, it's rebuilt once that's used up.
+const MIN_CAPACITY: usize = 1024;
+
+/// In-memory Bloom filters that rule out a match without asking the database.
+///
+/// Splitting the 64-bit hashes into `threshold + 1` bands, two hashes within `threshold`
+/// bits of each other must agree on at least one band. Every chat's filter holds the bands
+/// of its indexed hashes, and an image none of whose bands are in it can't have a match.
+/// Wide bands are needed for this to skip anything, so it only pays off with low thresholds.
+///
+/// Images indexed by anything else than this process (replicas, the importer) aren't seen,
+/// so it must not be used alongside those.
+#[derive(Clone, Default)]
+pub struct Prefilter {
+    filters: Arc<Mutex<HashMap<(i64, u8), ChatFilter>>>,
+}
+
+struct ChatFilter {
+    /// Chats whose images are in the filter, the chat and its linked channel.
+    sources: Vec<i64>,
+    bloom: Bloom,
+}
+
+impl Prefilter {
+    /// Returns `false` if none of the hashes can be within `threshold` of an indexed image.
+    pub async fn may_match(
+        &self,
+        pool: &PgPool,
+        chat_id: i64,
+        hashes: &[i64],
+        threshold: u8,
+    ) -> sqlx::Result<bool> {
+        // Every band would be empty.
+        if threshold >= 64 {
+            return Ok(true);
+        }
+
+        let bands = threshold + 1;
+        let key = (chat_id, bands);
+
+        if let Some(filter) = self.filters.lock().unwrap().get(&key) {
+            return Ok(hashes.iter().any(|x| filter.bloom.contains_any(*x, bands)));
+        }
+
+        let (sources, stored) = database::chat_hashes(pool, chat_id).await?;
+
+        let mut bloom = Bloom::new((stored.len() * 2).max(stored.len() + MIN_CAPACITY), bands);
+        for hash in stored {
+            bloom.insert(hash, bands);
+        }
+
+        let may_match = hashes.iter().any(|x| bloom.contains_any(*x, bands));
+        self.filters
+            .lock()
+            .unwrap()
+            .insert(key, ChatFilter { sources, bloom });
+
+        Ok(may_match)
+    }
+
+    /// Adds a newly indexed image's hashes to every filter covering its chat.
+    pub fn insert(&self, chat_id: i64, hashes: &[i64]) {
+        self.filters.lock().unwrap().retain(|(_, bands), filter| {
+            if !filter.sources.contains(&chat_id) {
+                return true;
+            }
+
+            for hash in hashes {
+                filter.bloom.insert(*hash, *bands);
+            }
+
+            // Full filters get too many false positives, rebuild them on next use.
+            !filter.bloom.is_full()
+        });
+    }
+
+    /// Drops every filter covering the chat, e.g. after it got linked to a channel.
+    pub fn forget(&self, chat_id: i64) {
+        self.filters
+            .lock()
+            .unwrap()
+            .retain(|_, filter| !filter.sources.contains(&chat_id));
+    }
+}
+
+struct Bloom {
+    bits: Vec<u64>,
+    entries: usize,
+    capacity: usize,
+}
+
+impl Bloom {
+    /// Sized for `capacity` hashes split into `bands` bands each.
+    fn new(capacity: usize, bands: u8) -> Self {
+        let words = (capacity * bands as usize * BITS_PER_ENTRY).div_ceil(64);
+
+        Self {
+            bits: vec![0; words.max(1)],
+            entries: 0,
+            capacity,
+        }
+    }
+
+    fn insert(&mut self, hash: i64, bands: u8) {
+        for band in 0..bands {
+            for bit in self.positions(band_key(hash, band, bands)) {
+                self.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+
+        self.entries += 1;
+    }
+
+    fn contains_any(&self, hash: i64, bands: u8) -> bool {
+        (0..bands).any(|band| {
+            self.positions(band_key(hash, band, bands))
+                .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+        })
+    }
+
+    fn is_full(&self) -> bool {
+        self.entries >= self.capacity
+    }
+
+    /// Bit positions of a key, by double hashing. They don't borrow the filter, so they can
+    /// be set while iterating.
+    fn positions(&self, key: u64) -> impl Iterator<Item = usize> + use<> {
+        let len = self.bits.len() as u64 * 64;
+        let h1 = mix(key);
+        let h2 = mix(h1) | 1;
+
+        (0..HASH_FUNCTIONS).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+/// The band's bits of the hash, tagged with the band so equal bits in different bands differ.
+fn band_key(hash: i64, band: u8, bands: u8) -> u64 {
+    let (band, bands) = (band as u32, bands as u32);
+    let start = 64 * band / bands;
+    let end = 64 * (band + 1) / bands;
+
+    let mask = if end - start == 64 {
+        u64::MAX
+    } else {
+        ((1u64 << (end - start)) - 1) << start
+    };
+
+    mix(hash as u64 & mask) ^ band as u64
+}
+
+/// splitmix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
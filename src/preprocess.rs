@@ -0,0 +1,222 @@
+//! Optional image cleanup before hashing, so that the same picture with added borders or
+//! different levels still hashes alike.
+
+use crate::config::PreprocessSettings;
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgb, RgbImage};
+
+/// Luminance at or below which letterbox bars count as black.
+const LETTERBOX_MAX_LUMA: u8 = 24;
+
+/// Share of a row or column's pixels that must be the border's color for it to count as
+/// border, leaving room for compression noise.
+const BORDER_SHARE: f32 = 0.98;
+
+/// A watermark bar's rows can have a logo or handle in them, as long as most of the row is
+/// the bar's color.
+const WATERMARK_SHARE: f32 = 0.8;
+
+/// Watermark bars are assumed to take up at most this share of the height, anything taller
+/// is more likely part of the picture.
+const MAX_WATERMARK_BAR: f32 = 0.2;
+
+/// Bars thinner than this share of the height are left alone, they're more likely an edge of
+/// the picture than a watermark.
+const MIN_WATERMARK_BAR: f32 = 0.02;
+
+/// Borders are only cropped if at least this share of each side is left, otherwise the image
+/// is probably (nearly) solid and the "border" is all there is.
+const MIN_CONTENT: f32 = 0.25;
+
+/// Runs the enabled steps: watermark bar and border cropping, letterbox removal, grayscale,
+/// gamma and resizing, in that order.
+pub fn apply(image: &DynamicImage, settings: &PreprocessSettings) -> DynamicImage {
+    let mut image = image.clone();
+
+    if settings.strip_watermark_bar {
+        image = strip_watermark_bar(&image, settings.border_tolerance);
+    }
+
+    if settings.crop_borders {
+        image = crop_borders(&image, settings.border_tolerance);
+    }
+
+    if settings.remove_letterbox {
+        let rgb = image.to_rgb8();
+        let black = |_: Side, x: &Rgb<u8>| luma(x) <= LETTERBOX_MAX_LUMA;
+        if let Some((x, y, width, height)) = content_bounds(&rgb, black) {
+            image = image.crop_imm(x, y, width, height);
+        }
+    }
+
+    if settings.grayscale {
+        image = image.grayscale();
+    }
+
+    if settings.normalize_gamma {
+        image = normalize_gamma(&image);
+    }
+
+    if let Some(resize) = &settings.resize {
+        image = image.resize_exact(resize.size, resize.size, FilterType::from(resize.filter));
+    }
+
+    image
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+/// Crops away uniformly colored rows and columns at the edges. Each side's border color is
+/// taken from its corner, so differently colored borders around one image are fine too.
+fn crop_borders(image: &DynamicImage, tolerance: u8) -> DynamicImage {
+    let rgb = image.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    if width == 0 || height == 0 {
+        return image.clone();
+    }
+
+    let top = *rgb.get_pixel(0, 0);
+    let bottom = *rgb.get_pixel(0, height - 1);
+    let right = *rgb.get_pixel(width - 1, 0);
+    let is_border = |side, x: &Rgb<u8>| {
+        let color = match side {
+            Side::Top | Side::Left => &top,
+            Side::Bottom => &bottom,
+            Side::Right => &right,
+        };
+        close(x, color, tolerance)
+    };
+
+    match content_bounds(&rgb, is_border) {
+        Some((x, y, width, height)) => image.crop_imm(x, y, width, height),
+        None => image.clone(),
+    }
+}
+
+/// Crops a bar at the bottom that's mostly one color with a logo or handle in it, as added by
+/// video and meme apps.
+fn strip_watermark_bar(image: &DynamicImage, tolerance: u8) -> DynamicImage {
+    let rgb = image.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    if width == 0 || height == 0 {
+        return image.clone();
+    }
+
+    // Taken from the corner, the middle of the bar is where logos usually are.
+    let color = *rgb.get_pixel(0, height - 1);
+    let max_bar = (height as f32 * MAX_WATERMARK_BAR) as u32;
+
+    let bar = (0..max_bar)
+        .take_while(|i| {
+            let y = height - 1 - i;
+            let row = (0..width).map(|x| rgb.get_pixel(x, y));
+            share(row, |x| close(x, &color, tolerance)) >= WATERMARK_SHARE
+        })
+        .count() as u32;
+
+    if bar == 0 || (bar as f32) < height as f32 * MIN_WATERMARK_BAR {
+        return image.clone();
+    }
+
+    image.crop_imm(0, 0, width, height - bar)
+}
+
+/// The rectangle left after stripping edge rows and columns that are almost entirely border
+/// pixels, or `None` if there's nothing to strip or too little would be left.
+fn content_bounds(
+    image: &RgbImage,
+    is_border: impl Fn(Side, &Rgb<u8>) -> bool,
+) -> Option<(u32, u32, u32, u32)> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let row_is_border = |side, y: u32| {
+        let row = (0..width).map(|x| image.get_pixel(x, y));
+        share(row, |x| is_border(side, x)) >= BORDER_SHARE
+    };
+    let column_is_border = |side, x: u32, top: u32, bottom: u32| {
+        let column = (top..bottom).map(|y| image.get_pixel(x, y));
+        share(column, |x| is_border(side, x)) >= BORDER_SHARE
+    };
+
+    let top = (0..height).find(|y| !row_is_border(Side::Top, *y))?;
+    let bottom = (top..height)
+        .rev()
+        .find(|y| !row_is_border(Side::Bottom, *y))?
+        + 1;
+    let left = (0..width).find(|x| !column_is_border(Side::Left, *x, top, bottom))?;
+    let right = (left..width)
+        .rev()
+        .find(|x| !column_is_border(Side::Right, *x, top, bottom))?
+        + 1;
+
+    let (content_width, content_height) = (right - left, bottom - top);
+    if (content_width, content_height) == (width, height)
+        || (content_width as f32) < width as f32 * MIN_CONTENT
+        || (content_height as f32) < height as f32 * MIN_CONTENT
+    {
+        return None;
+    }
+
+    Some((left, top, content_width, content_height))
+}
+
+/// Share of the pixels that pass the test.
+fn share<'a>(pixels: impl Iterator<Item = &'a Rgb<u8>>, test: impl Fn(&Rgb<u8>) -> bool) -> f32 {
+    let (mut passed, mut total) = (0, 0);
+    for pixel in pixels {
+        passed += test(pixel) as u32;
+        total += 1;
+    }
+
+    passed as f32 / total.max(1) as f32
+}
+
+/// Applies the gamma that brings the mean brightness to the middle, evening out images that
+/// were brightened or darkened as a whole.
+fn normalize_gamma(image: &DynamicImage) -> DynamicImage {
+    let mut rgb = image.to_rgb8();
+    let pixels = rgb.pixels().len().max(1) as f64;
+    let mean = rgb.pixels().map(|x| luma(x) as f64).sum::<f64>() / pixels / 255.0;
+
+    // Solid black or white images have no gamma that would help.
+    if mean <= 0.01 || mean >= 0.99 {
+        return image.clone();
+    }
+
+    let gamma = 0.5f64.ln() / mean.ln();
+    let table: Vec<u8> = (0..=255)
+        .map(|x| ((x as f64 / 255.0).powf(gamma) * 255.0).round() as u8)
+        .collect();
+
+    for pixel in rgb.pixels_mut() {
+        for channel in &mut pixel.0 {
+            *channel = table[*channel as usize];
+        }
+    }
+
+    let rgb = DynamicImage::ImageRgb8(rgb);
+    if image.color().has_color() {
+        rgb
+    } else {
+        rgb.grayscale()
+    }
+}
+
+fn close(a: &Rgb<u8>, b: &Rgb<u8>, tolerance: u8) -> bool {
+    a.0.iter().zip(b.0).all(|(a, b)| a.abs_diff(b) <= tolerance)
+}
+
+/// Rec. 601 luma.
+fn luma(pixel: &Rgb<u8>) -> u8 {
+    let [r, g, b] = pixel.0;
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
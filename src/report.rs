@@ -0,0 +1,191 @@
+use crate::archive::{self, Archive};
+use crate::dashboard::escape;
+use crate::database::{self, Cluster};
+use crate::decode;
+use crate::messenger::MessageRef;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use image::{DynamicImage, ImageOutputFormat};
+use sqlx::PgPool;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+use tracing::debug;
+
+/// Longest side of the embedded thumbnails.
+const THUMBNAIL_SIZE: u32 = 160;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("database error")]
+    Database(#[from] sqlx::Error),
+    #[error("archive error")]
+    Archive(#[from] archive::Error),
+    #[error("thumbnail task failed")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+/// Renders the chat's duplicate clusters into a standalone HTML file, with thumbnails
+/// embedded from the archive when it has the images. `link` turns messages into links.
+pub async fn run(
+    pool: &PgPool,
+    archive: Option<&Archive>,
+    chat_id: i64,
+    out: &Path,
+    link: impl Fn(MessageRef) -> Option<String>,
+) -> Result<(), Error> {
+    let title = database::chat_stats(pool)
+        .await?
+        .into_iter()
+        .find(|x| x.id == chat_id)
+        .map(|x| x.title)
+        .unwrap_or_else(|| chat_id.to_string());
+
+    let clusters = database::duplicate_clusters(pool, chat_id).await?;
+    let reposts = clusters.iter().map(|x| x.duplicates.len()).sum::<usize>();
+
+    let mut body = format!(
+        "<h1>Duplicates in {title}</h1><p>{clusters} images reposted {reposts} times.</p>",
+        title = escape(&title),
+        clusters = clusters.len(),
+    );
+
+    for cluster in &clusters {
+        render_cluster(&mut body, pool, archive, chat_id, cluster, &link).await?;
+    }
+
+    let html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Duplicates in {title}</title>\
+         <style>body{{font-family:sans-serif}}.cluster{{border-top:1px solid #ccc;padding:8px 0}}\
+         .image{{display:inline-block;vertical-align:top;margin:4px;text-align:center}}\
+         .image img{{display:block;max-width:{THUMBNAIL_SIZE}px;max-height:{THUMBNAIL_SIZE}px}}\
+         .original{{font-weight:bold}}</style></head><body>{body}</body></html>",
+        title = escape(&title),
+    );
+
+    fs::write(out, html)?;
+    println!(
+        "Wrote {} clusters with {reposts} reposts to {}",
+        clusters.len(),
+        out.display()
+    );
+
+    Ok(())
+}
+
+async fn render_cluster(
+    body: &mut String,
+    pool: &PgPool,
+    archive: Option<&Archive>,
+    chat_id: i64,
+    cluster: &Cluster,
+    link: &impl Fn(MessageRef) -> Option<String>,
+) -> Result<(), Error> {
+    let _ = write!(
+        body,
+        "<div class=\"cluster\"><h2>Message {} ({} reposts)</h2>",
+        cluster.original_message_id,
+        cluster.duplicates.len()
+    );
+
+    let original_key = database::image_media(pool, chat_id, cluster.original_message_id)
+        .await?
+        .and_then(|x| x.media_key);
+    let original_thumb = thumbnail(archive, original_key.as_deref()).await?;
+    render_image(
+        body,
+        "original",
+        cluster.original_message_id,
+        None,
+        original_thumb.as_deref(),
+        link(MessageRef {
+            chat_id,
+            message_id: cluster.original_message_id,
+        }),
+    );
+
+    for sighting in &cluster.duplicates {
+        let thumb = thumbnail(archive, sighting.media_key.as_deref()).await?;
+        render_image(
+            body,
+            "duplicate",
+            sighting.message_id,
+            Some(sighting.distance),
+            thumb.as_deref(),
+            link(MessageRef {
+                chat_id,
+                message_id: sighting.message_id,
+            }),
+        );
+    }
+
+    body.push_str("</div>");
+
+    Ok(())
+}
+
+fn render_image(
+    body: &mut String,
+    class: &str,
+    message_id: i32,
+    distance: Option<i16>,
+    thumbnail: Option<&str>,
+    link: Option<String>,
+) {
+    let _ = write!(body, "<div class=\"image {class}\">");
+
+    match thumbnail {
+        Some(thumbnail) => {
+            let _ = write!(body, "<img src=\"data:image/jpeg;base64,{thumbnail}\">");
+        }
+        None => body.push_str("<p>(not archived)</p>"),
+    }
+
+    let label = match distance {
+        Some(distance) => format!("{message_id} (dst {distance})"),
+        None => format!("{message_id} (original)"),
+    };
+
+    match link {
+        Some(link) => {
+            let _ = write!(body, "<a href=\"{}\">{label}</a>", escape(&link));
+        }
+        None => body.push_str(&label),
+    }
+
+    body.push_str("</div>");
+}
+
+/// Base64 JPEG thumbnail of the archived image, if the archive has it.
+async fn thumbnail(archive: Option<&Archive>, key: Option<&str>) -> Result<Option<String>, Error> {
+    let (Some(archive), Some(key)) = (archive, key) else {
+        return Ok(None);
+    };
+
+    let Some(data) = archive.load(key).await? else {
+        return Ok(None);
+    };
+
+    let thumbnail = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, decode::Error> {
+        let image = decode::decode(&data)?.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(80))
+            .map_err(decode::Error::Image)?;
+
+        Ok(jpeg)
+    })
+    .await?;
+
+    match thumbnail {
+        Ok(jpeg) => Ok(Some(BASE64.encode(jpeg))),
+        Err(e) => {
+            debug!("Couldn't make a thumbnail of {key}: {e}");
+            Ok(None)
+        }
+    }
+}
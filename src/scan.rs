@@ -0,0 +1,89 @@
+//! Brute-force nearest neighbor search over hashes held in memory, for scanning a whole chat
+//! without the database or a tree index.
+
+/// Index and distance of the hash closest to `query`, if any is within `max_distance`.
+/// Ties go to the lowest index.
+pub fn nearest(hashes: &[u64], query: u64, max_distance: u32) -> Option<(usize, u32)> {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 support was just checked.
+        return unsafe { avx2::nearest(hashes, query, max_distance) };
+    }
+
+    nearest_portable(hashes, query, max_distance)
+}
+
+/// [`nearest`] without explicit SIMD, which the compiler still vectorizes somewhat.
+pub fn nearest_portable(hashes: &[u64], query: u64, max_distance: u32) -> Option<(usize, u32)> {
+    let mut best = None;
+    let mut best_distance = max_distance.saturating_add(1);
+
+    for (i, hash) in hashes.iter().enumerate() {
+        let distance = (hash ^ query).count_ones();
+        if distance < best_distance {
+            best = Some((i, distance));
+            best_distance = distance;
+        }
+    }
+
+    best
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    /// Popcounts four hashes at a time with nibble lookups (Muła et al.), since AVX2 has no
+    /// vector popcount of its own.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn nearest(hashes: &[u64], query: u64, max_distance: u32) -> Option<(usize, u32)> {
+        let lookup = _mm256_setr_epi8(
+            0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4, 0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2,
+            3, 3, 4,
+        );
+        let low_nibbles = _mm256_set1_epi8(0x0f);
+        let query_vec = _mm256_set1_epi64x(query as i64);
+
+        let mut best = None;
+        let mut best_distance = max_distance.saturating_add(1);
+
+        let chunks = hashes.chunks_exact(4);
+        let remainder = chunks.remainder();
+
+        for (chunk_index, chunk) in chunks.enumerate() {
+            // SAFETY: the chunk is exactly four u64s, and the load is unaligned.
+            let x = unsafe { _mm256_loadu_si256(chunk.as_ptr().cast()) };
+            let x = _mm256_xor_si256(x, query_vec);
+
+            let low = _mm256_and_si256(x, low_nibbles);
+            let high = _mm256_and_si256(_mm256_srli_epi16(x, 4), low_nibbles);
+            let bytes = _mm256_add_epi8(
+                _mm256_shuffle_epi8(lookup, low),
+                _mm256_shuffle_epi8(lookup, high),
+            );
+            // Sums the byte counts of every 64-bit lane.
+            let counts = _mm256_sad_epu8(bytes, _mm256_setzero_si256());
+
+            let mut distances = [0u64; 4];
+            // SAFETY: the array has room for exactly one vector.
+            unsafe { _mm256_storeu_si256(distances.as_mut_ptr().cast(), counts) };
+
+            for (lane, distance) in distances.into_iter().enumerate() {
+                let distance = distance as u32;
+                if distance < best_distance {
+                    best = Some((chunk_index * 4 + lane, distance));
+                    best_distance = distance;
+                }
+            }
+        }
+
+        let offset = hashes.len() - remainder.len();
+        if let Some((i, distance)) = super::nearest_portable(remainder, query, max_distance)
+            && distance < best_distance
+        {
+            best = Some((offset + i, distance));
+        }
+
+        best
+    }
+}
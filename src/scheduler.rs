@@ -0,0 +1,288 @@
+//! Runs periodic jobs on cron schedules, remembering in the database when each last ran so
+//! restarts don't skip or repeat runs, and several replicas run each one only once.
+
+use crate::database;
+use chrono::{DateTime, Datelike, DurationRound, TimeDelta, Timelike, Utc};
+use futures_util::future::BoxFuture;
+use sqlx::PgPool;
+use std::future::Future;
+use std::str::FromStr;
+use thiserror::Error;
+use tracing::{debug, error, info};
+
+#[derive(Error, Debug)]
+#[error("invalid cron expression {expression:?}: {reason}")]
+pub struct ParseError {
+    expression: String,
+    reason: String,
+}
+
+/// A standard five field cron expression (minute, hour, day of month, month, day of week) in
+/// UTC. Fields take `*`, numbers, ranges, lists and `/` steps, days of the week go from 0
+/// (Sunday) to 6, and 7 is Sunday too.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// As in cron, a day matches if either day field does when both are restricted.
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl FromStr for Schedule {
+    type Err = ParseError;
+
+    fn from_str(expression: &str) -> Result<Self, ParseError> {
+        let error = |reason: String| ParseError {
+            expression: expression.to_owned(),
+            reason,
+        };
+
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(error(format!("expected 5 fields, got {}", fields.len())));
+        };
+
+        let mut weekday_bits = parse_field(weekdays, 0, 7).map_err(error)?;
+        // 7 is another name for Sunday.
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits = (weekday_bits | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59).map_err(error)?,
+            hours: parse_field(hours, 0, 23).map_err(error)?,
+            days: parse_field(days, 1, 31).map_err(error)?,
+            months: parse_field(months, 1, 12).map_err(error)?,
+            weekdays: weekday_bits,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+        })
+    }
+}
+
+/// Bit set of the values the field allows.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|x| *x > 0)
+                    .ok_or_else(|| format!("invalid step in {part:?}"))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let parse = |x: &str| {
+            x.parse::<u32>()
+                .ok()
+                .filter(|x| (min..=max).contains(x))
+                .ok_or_else(|| format!("{x:?} isn't a number from {min} to {max}"))
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (parse(start)?, parse(end)?),
+                // `5/15` means from 5 on.
+                None if step > 1 => (parse(range)?, max),
+                None => {
+                    let value = parse(range)?;
+                    (value, value)
+                }
+            },
+        };
+
+        if start > end {
+            return Err(format!("empty range in {part:?}"));
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+impl Schedule {
+    /// The first minute strictly after `after` that the schedule matches.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut at = after.duration_trunc(TimeDelta::minutes(1)).ok()? + TimeDelta::minutes(1);
+        // Impossible schedules like February 31st would loop forever.
+        let give_up = at + TimeDelta::days(366 * 5);
+
+        while at < give_up {
+            if !has(self.months, at.month()) {
+                at = start_of_day(at.with_day(1)?) + months_later(at);
+                continue;
+            }
+
+            if !self.day_matches(at) {
+                at = start_of_day(at) + TimeDelta::days(1);
+                continue;
+            }
+
+            if !has(self.hours, at.hour()) {
+                at = at.with_minute(0)? + TimeDelta::hours(1);
+                continue;
+            }
+
+            if !has(self.minutes, at.minute()) {
+                at += TimeDelta::minutes(1);
+                continue;
+            }
+
+            return Some(at);
+        }
+
+        None
+    }
+
+    fn day_matches(&self, at: DateTime<Utc>) -> bool {
+        let day = has(self.days, at.day());
+        let weekday = has(self.weekdays, at.weekday().num_days_from_sunday());
+
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+fn start_of_day(at: DateTime<Utc>) -> DateTime<Utc> {
+    at.duration_trunc(TimeDelta::days(1)).unwrap_or(at)
+}
+
+/// From the first of `at`'s month to the first of the next one.
+fn months_later(at: DateTime<Utc>) -> TimeDelta {
+    let days = match at.month() {
+        4 | 6 | 9 | 11 => 30,
+        2 if at.year() % 4 == 0 && (at.year() % 100 != 0 || at.year() % 400 == 0) => 29,
+        2 => 28,
+        _ => 31,
+    };
+
+    TimeDelta::days(days)
+}
+
+type JobFn = Box<dyn Fn() -> BoxFuture<'static, anyhow::Result<()>> + Send + Sync>;
+
+struct Job {
+    name: String,
+    schedule: Schedule,
+    run: JobFn,
+}
+
+/// Collects jobs and then runs them from a single task, one at a time.
+pub struct Scheduler {
+    pool: PgPool,
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            jobs: Vec::new(),
+        }
+    }
+
+    /// `name` identifies the job's state in the database, so it has to stay the same across
+    /// restarts and be unique.
+    pub fn add<F, Fut>(&mut self, name: impl Into<String>, schedule: Schedule, job: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.jobs.push(Job {
+            name: name.into(),
+            schedule,
+            run: Box::new(move || Box::pin(job())),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Runs the jobs forever. A job whose time passed while nothing was running, e.g. during
+    /// a restart, runs right away.
+    pub async fn run(self) {
+        let last_runs = match database::job_runs(&self.pool).await {
+            Ok(last_runs) => last_runs,
+            Err(e) => {
+                error!("Error loading the last job runs, starting from scratch: {e}");
+                Default::default()
+            }
+        };
+
+        let now = Utc::now();
+        let mut due = self
+            .jobs
+            .iter()
+            .map(|job| {
+                let after = last_runs.get(&job.name).copied().unwrap_or(now);
+                job.schedule.next_after(after)
+            })
+            .collect::<Vec<_>>();
+
+        loop {
+            let Some((index, at)) = due
+                .iter()
+                .enumerate()
+                .filter_map(|(i, x)| x.map(|x| (i, x)))
+                .min_by_key(|(_, x)| *x)
+            else {
+                info!("No scheduled jobs left to run");
+                return;
+            };
+
+            if let Ok(wait) = (at - Utc::now()).to_std() {
+                tokio::time::sleep(wait).await;
+            }
+
+            let job = &self.jobs[index];
+            self.run_job(job, at).await;
+            due[index] = job.schedule.next_after(Utc::now().max(at));
+        }
+    }
+
+    async fn run_job(&self, job: &Job, due: DateTime<Utc>) {
+        // Another replica may have run it already.
+        match database::claim_job_run(&self.pool, &job.name, due).await {
+            Ok(true) => (),
+            Ok(false) => {
+                debug!("Job {} already ran elsewhere", job.name);
+                return;
+            }
+            Err(e) => {
+                error!("Error claiming job {}: {e}", job.name);
+                return;
+            }
+        }
+
+        debug!("Running job {}", job.name);
+        let result = (job.run)().await;
+        if let Err(e) = &result {
+            error!("Job {} failed: {e:#}", job.name);
+        }
+
+        let error = result.err().map(|x| format!("{x:#}"));
+        if let Err(e) = database::finish_job_run(&self.pool, &job.name, error.as_deref()).await {
+            error!("Error recording the run of job {}: {e}", job.name);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use rhai::{AST, Dynamic, Engine, EvalAltResult, Map, Scope};
+use std::path::Path;
+use thiserror::Error;
+use tracing::{debug, error, info};
+
+#[derive(Error, Debug)]
+#[error("couldnt load script: {0}")]
+pub struct Error(String);
+
+/// What a hook wants the bot to do instead of (or in addition to) its default behavior.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Hook isn't defined, returned nothing or failed; do what the bot would do anyway.
+    Default,
+    /// Hook returned `false`, stay quiet.
+    Suppress,
+    /// Hook returned a string or a `#{ reply: "...", delete: true }` map.
+    Custom { reply: Option<String>, delete: bool },
+}
+
+/// Values passed to the hooks as the `ctx` map.
+#[derive(Debug, Clone)]
+pub struct HookContext {
+    pub chat_id: i64,
+    pub chat_title: String,
+    pub message_id: i32,
+    pub original_message_id: Option<i32>,
+    pub distance: Option<u8>,
+    pub original_link: Option<String>,
+}
+
+/// Operator-provided Rhai script with `on_duplicate(ctx)` and `on_new_image(ctx)` hooks.
+pub struct Scripts {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Scripts {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let mut engine = Engine::new();
+
+        // Hooks run inline in the message handler, don't let a runaway loop hang it.
+        engine.set_max_operations(100_000);
+        engine.on_print(|text| info!("[script] {text}"));
+        engine.on_debug(|text, _, pos| debug!("[script] {pos}: {text}"));
+
+        let ast = engine
+            .compile_file(path.to_owned())
+            .map_err(|e| Error(e.to_string()))?;
+
+        Ok(Self { engine, ast })
+    }
+
+    pub fn on_duplicate(&self, ctx: &HookContext) -> Action {
+        self.call("on_duplicate", ctx)
+    }
+
+    pub fn on_new_image(&self, ctx: &HookContext) -> Action {
+        self.call("on_new_image", ctx)
+    }
+
+    fn call(&self, hook: &str, ctx: &HookContext) -> Action {
+        let result =
+            self.engine
+                .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, hook, (ctx.to_map(),));
+
+        match result {
+            Ok(value) => parse_action(value),
+            Err(e) => {
+                if !matches!(*e, EvalAltResult::ErrorFunctionNotFound(ref name, _) if name.starts_with(hook))
+                {
+                    error!("Script hook {hook} failed: {e}");
+                }
+
+                Action::Default
+            }
+        }
+    }
+}
+
+impl HookContext {
+    fn to_map(&self) -> Map {
+        let mut map = Map::new();
+        map.insert("chat_id".into(), self.chat_id.into());
+        map.insert("chat_title".into(), self.chat_title.clone().into());
+        map.insert("message_id".into(), (self.message_id as i64).into());
+        map.insert(
+            "original_message_id".into(),
+            optional(self.original_message_id.map(i64::from)),
+        );
+        map.insert("distance".into(), optional(self.distance.map(i64::from)));
+        map.insert("original_link".into(), optional(self.original_link.clone()));
+
+        map
+    }
+}
+
+fn optional<T: Into<Dynamic>>(value: Option<T>) -> Dynamic {
+    value.map(Into::into).unwrap_or(Dynamic::UNIT)
+}
+
+fn parse_action(value: Dynamic) -> Action {
+    if value.is_unit() {
+        return Action::Default;
+    }
+
+    if let Ok(keep) = value.as_bool() {
+        return if keep {
+            Action::Default
+        } else {
+            Action::Suppress
+        };
+    }
+
+    if value.is_string() {
+        return Action::Custom {
+            reply: value.into_string().ok(),
+            delete: false,
+        };
+    }
+
+    if let Some(map) = value.try_cast::<Map>() {
+        return Action::Custom {
+            reply: map.get("reply").and_then(|x| x.clone().into_string().ok()),
+            delete: map
+                .get("delete")
+                .and_then(|x| x.as_bool().ok())
+                .unwrap_or(false),
+        };
+    }
+
+    error!("Script hook returned an unsupported value, ignoring it");
+    Action::Default
+}
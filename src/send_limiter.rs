@@ -0,0 +1,75 @@
+use crate::config::SendLimitSettings;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Telegram lets bots send about one message a second to a private chat.
+const PRIVATE_CHAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Spaces out what a bot sends to stay within Telegram's limits, overall and per chat, so a
+/// burst of duplicates doesn't get it flood-limited halfway through. Sends take turns in the
+/// order they asked for one.
+#[derive(Clone)]
+pub struct SendLimiter {
+    slots: Arc<Mutex<Slots>>,
+    interval: Duration,
+    group_interval: Duration,
+}
+
+/// When the next send may go out, overall and to each chat that had one recently.
+struct Slots {
+    next: Instant,
+    chats: HashMap<i64, Instant>,
+}
+
+impl SendLimiter {
+    pub fn new(settings: &SendLimitSettings) -> Self {
+        Self {
+            slots: Arc::new(Mutex::new(Slots {
+                next: Instant::now(),
+                chats: HashMap::new(),
+            })),
+            interval: Duration::from_secs(1) / settings.per_second.max(1),
+            group_interval: Duration::from_secs(60) / settings.per_group_per_minute.max(1),
+        }
+    }
+
+    /// Waits for the turn of a send to the chat.
+    pub async fn wait(&self, chat_id: i64) {
+        let slot = {
+            let mut slots = self.slots.lock().unwrap();
+            let now = Instant::now();
+            // Chats whose turn has come don't need remembering.
+            if slots.chats.len() > 1000 {
+                slots.chats.retain(|_, x| *x > now);
+            }
+
+            let slot = now
+                .max(slots.next)
+                .max(slots.chats.get(&chat_id).copied().unwrap_or(now));
+            slots.next = slot + self.interval;
+            slots
+                .chats
+                .insert(chat_id, slot + self.chat_interval(chat_id));
+            slot
+        };
+
+        tokio::time::sleep_until(slot.into()).await;
+    }
+
+    /// Holds off sends to the chat for as long as Telegram asked to after flood-limiting one.
+    pub fn pause(&self, chat_id: i64, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut slots = self.slots.lock().unwrap();
+        let next = slots.chats.entry(chat_id).or_insert(until);
+        *next = (*next).max(until);
+    }
+
+    /// Group and channel ids are negative, users' positive.
+    fn chat_interval(&self, chat_id: i64) -> Duration {
+        match chat_id < 0 {
+            true => self.group_interval,
+            false => PRIVATE_CHAT_INTERVAL,
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::database;
+use sqlx::PgPool;
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// Width of the histogram bars in characters.
+const BAR_WIDTH: u64 = 50;
+
+/// Prints the size and health of the database, what each chat has indexed and, for one chat
+/// or all of them, the distances duplicates were detected at and how the index grew.
+pub async fn run(pool: &PgPool, chat_id: Option<i64>) -> sqlx::Result<()> {
+    if chat_id.is_none() {
+        println!(
+            "{:<24}{:>12}{:>12}{:>12}{:>12}",
+            "table", "MB", "index MB", "rows", "dead rows"
+        );
+        for table in database::table_stats(pool).await? {
+            println!(
+                "{:<24}{:>12.1}{:>12.1}{:>12}{:>12}",
+                table.name,
+                table.total_bytes as f64 / 1e6,
+                table.index_bytes as f64 / 1e6,
+                table.live_rows,
+                table.dead_rows,
+            );
+        }
+
+        println!();
+    }
+
+    let chats = database::chat_stats(pool)
+        .await?
+        .into_iter()
+        .filter(|x| chat_id.is_none_or(|id| x.id == id))
+        .collect::<Vec<_>>();
+
+    if let Some(chat_id) = chat_id
+        && chats.is_empty()
+    {
+        println!("Nothing indexed in {chat_id}.");
+        return Ok(());
+    }
+
+    for chat in &chats {
+        println!(
+            "{id:>16}  {images:>10} images  {duplicates:>8} duplicates  {oldest} to {newest}  {title}",
+            id = chat.id,
+            images = chat.images,
+            duplicates = chat.sightings - chat.false_positives,
+            oldest = date(chat.oldest),
+            newest = date(chat.newest),
+            title = chat.title,
+        );
+    }
+
+    let distances = database::sighting_distances(pool, chat_id).await?;
+    if !distances.is_empty() {
+        println!();
+        println!("Distances duplicates were detected at:");
+
+        let max = distances
+            .iter()
+            .map(|x| x.1 as u64)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        for (distance, count) in distances {
+            let bar = "#".repeat((count as u64 * BAR_WIDTH).div_ceil(max) as usize);
+            println!("{distance:>3} {count:>9} {bar}");
+        }
+    }
+
+    let growth = database::monthly_growth(pool, chat_id).await?;
+    if !growth.is_empty() {
+        println!();
+        println!(
+            "{:<10}{:>12}{:>12}{:>12}",
+            "month", "images", "duplicates", "total"
+        );
+
+        let mut total = 0;
+        for month in growth {
+            total += month.images;
+            println!(
+                "{:<10}{:>12}{:>12}{:>12}",
+                month.month.format("%Y-%m"),
+                month.images,
+                month.sightings,
+                total,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn date(x: Option<DateTime<Utc>>) -> String {
+    x.map(|x| x.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "-".to_owned())
+}
@@ -0,0 +1,363 @@
+use crate::database::{self, ChatStats, Cluster, HashMatch, Image};
+use ratatui::DefaultTerminal;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use sqlx::PgPool;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("terminal error")]
+    Io(#[from] std::io::Error),
+    #[error("database error")]
+    Database(#[from] sqlx::Error),
+}
+
+const PAGE_SIZE: i64 = 200;
+
+/// Largest distance hash searches go out to.
+const SEARCH_DISTANCE: u8 = 16;
+
+enum View {
+    Chats,
+    Images { chat: usize, page: i64 },
+    Clusters { chat: usize },
+    Search { chat: usize, query: String },
+}
+
+struct App {
+    pool: PgPool,
+    view: View,
+    chats: Vec<ChatStats>,
+    images: Vec<Image>,
+    clusters: Vec<Cluster>,
+    matches: Vec<HashMatch>,
+    list: ListState,
+    /// Image waiting for the delete to be confirmed.
+    confirm_delete: Option<usize>,
+    status: String,
+}
+
+/// Browses the index in the terminal until the user quits.
+pub async fn run(pool: PgPool) -> Result<(), Error> {
+    let mut app = App {
+        pool,
+        view: View::Chats,
+        chats: Vec::new(),
+        images: Vec::new(),
+        clusters: Vec::new(),
+        matches: Vec::new(),
+        list: ListState::default(),
+        confirm_delete: None,
+        status: String::new(),
+    };
+    app.load().await?;
+
+    let mut terminal = ratatui::init();
+    let result = app.event_loop(&mut terminal).await;
+    ratatui::restore();
+
+    result
+}
+
+impl App {
+    async fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Error> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            // Nothing else runs while the TUI is up, blocking the worker is fine.
+            let event = tokio::task::block_in_place(event::read)?;
+            let Event::Key(key) = event else {
+                continue;
+            };
+
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            if !self.on_key(key).await? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns `false` when the user wants to quit.
+    async fn on_key(&mut self, key: KeyEvent) -> Result<bool, Error> {
+        if let View::Search { query, .. } = &mut self.view {
+            match key.code {
+                KeyCode::Char(c) if c.is_ascii_hexdigit() && query.len() < 16 => {
+                    query.push(c);
+                    return Ok(true);
+                }
+                KeyCode::Backspace if !query.is_empty() => {
+                    query.pop();
+                    return Ok(true);
+                }
+                KeyCode::Enter => {
+                    self.search().await?;
+                    return Ok(true);
+                }
+                _ => (),
+            }
+        }
+
+        if let Some(selected) = self.confirm_delete.take() {
+            if key.code == KeyCode::Char('y') {
+                self.delete(selected).await?;
+            } else {
+                self.status = "Delete cancelled".to_owned();
+            }
+
+            return Ok(true);
+        }
+
+        match key.code {
+            KeyCode::Char('q') => return Ok(false),
+            KeyCode::Down | KeyCode::Char('j') => self.list.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.list.select_previous(),
+            KeyCode::Enter => self.open().await?,
+            KeyCode::Esc | KeyCode::Backspace => self.back().await?,
+            KeyCode::Tab => self.switch().await?,
+            KeyCode::Char('/') => {
+                if let Some(chat) = self.chat() {
+                    self.view = View::Search {
+                        chat,
+                        query: String::new(),
+                    };
+                    self.matches.clear();
+                    self.list.select(None);
+                }
+            }
+            KeyCode::Char('n') => {
+                if let View::Images { page, .. } = &mut self.view {
+                    *page += 1;
+                    self.load().await?;
+                }
+            }
+            KeyCode::Char('p') => {
+                if let View::Images { page, .. } = &mut self.view
+                    && *page > 0
+                {
+                    *page -= 1;
+                    self.load().await?;
+                }
+            }
+            KeyCode::Char('u') => {
+                if let View::Images { chat, .. } = self.view {
+                    self.undo(chat).await?;
+                }
+            }
+            KeyCode::Char('d') => {
+                if let (View::Images { .. }, Some(selected)) = (&self.view, self.list.selected())
+                    && selected < self.images.len()
+                {
+                    self.confirm_delete = Some(selected);
+                    self.status = format!(
+                        "Delete image of message {}? (y/n)",
+                        self.images[selected].message_id
+                    );
+                }
+            }
+            _ => (),
+        }
+
+        Ok(true)
+    }
+
+    /// Index of the chat the current view is about, or the selected one in the chat list.
+    fn chat(&self) -> Option<usize> {
+        match &self.view {
+            View::Chats => self.list.selected().filter(|x| *x < self.chats.len()),
+            View::Images { chat, .. } | View::Clusters { chat } | View::Search { chat, .. } => {
+                Some(*chat)
+            }
+        }
+    }
+
+    async fn open(&mut self) -> sqlx::Result<()> {
+        if let View::Chats = self.view
+            && let Some(chat) = self.chat()
+        {
+            self.view = View::Images { chat, page: 0 };
+            self.load().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn back(&mut self) -> sqlx::Result<()> {
+        if !matches!(self.view, View::Chats) {
+            self.view = View::Chats;
+            self.load().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Flips between a chat's images and its duplicate clusters.
+    async fn switch(&mut self) -> sqlx::Result<()> {
+        self.view = match self.view {
+            View::Images { chat, .. } => View::Clusters { chat },
+            View::Clusters { chat } | View::Search { chat, .. } => View::Images { chat, page: 0 },
+            View::Chats => return Ok(()),
+        };
+
+        self.load().await
+    }
+
+    async fn load(&mut self) -> sqlx::Result<()> {
+        match self.view {
+            View::Chats => self.chats = database::chat_stats(&self.pool).await?,
+            View::Images { chat, page } => {
+                self.images = database::list_images(
+                    &self.pool,
+                    self.chats[chat].id,
+                    PAGE_SIZE,
+                    page * PAGE_SIZE,
+                )
+                .await?
+            }
+            View::Clusters { chat } => {
+                self.clusters =
+                    database::duplicate_clusters(&self.pool, self.chats[chat].id).await?
+            }
+            View::Search { .. } => (),
+        }
+
+        self.list.select(Some(0));
+        Ok(())
+    }
+
+    async fn search(&mut self) -> sqlx::Result<()> {
+        let View::Search { chat, query } = &self.view else {
+            return Ok(());
+        };
+
+        let Ok(hash) = u64::from_str_radix(query, 16) else {
+            self.status = "Enter a hash as up to 16 hex digits".to_owned();
+            return Ok(());
+        };
+
+        self.matches = database::search_hash(
+            &self.pool,
+            self.chats[*chat].id,
+            hash as i64,
+            SEARCH_DISTANCE,
+            PAGE_SIZE,
+        )
+        .await?;
+        self.status = format!(
+            "{} images within {SEARCH_DISTANCE} bits",
+            self.matches.len()
+        );
+        self.list.select(Some(0));
+
+        Ok(())
+    }
+
+    async fn delete(&mut self, selected: usize) -> sqlx::Result<()> {
+        let image = &self.images[selected];
+        database::delete_image(&self.pool, image.chat_id, image.id).await?;
+
+        self.status = format!("Deleted image of message {}", image.message_id);
+        self.images.remove(selected);
+
+        Ok(())
+    }
+
+    async fn undo(&mut self, chat: usize) -> sqlx::Result<()> {
+        match database::undo_last_delete(&self.pool, self.chats[chat].id).await? {
+            Some(message_id) => {
+                self.load().await?;
+                self.status = format!("Restored image of message {message_id}");
+            }
+            None => self.status = "Nothing to undo".to_owned(),
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut ratatui::Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+
+        let (title, items, help) = match &self.view {
+            View::Chats => (
+                "Chats".to_owned(),
+                self.chats
+                    .iter()
+                    .map(|x| {
+                        format!(
+                            "{} ({}): {} images, {} duplicates, {} false positives",
+                            x.title, x.id, x.images, x.sightings, x.false_positives
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+                "enter: images  /: search  q: quit",
+            ),
+            View::Images { chat, page } => (
+                format!("{} - images, page {}", self.chats[*chat].title, page + 1),
+                self.images
+                    .iter()
+                    .map(|x| {
+                        format!(
+                            "message {}  hash {:016x}  {}",
+                            x.message_id,
+                            x.phash,
+                            x.created_at.format("%Y-%m-%d %H:%M")
+                        )
+                    })
+                    .collect(),
+                "tab: clusters  n/p: page  d: delete  u: undo  /: search  esc: back",
+            ),
+            View::Clusters { chat } => (
+                format!("{} - duplicate clusters", self.chats[*chat].title),
+                self.clusters
+                    .iter()
+                    .map(|x| {
+                        let duplicates = x
+                            .duplicates
+                            .iter()
+                            .map(|x| format!("{} (dst {})", x.message_id, x.distance))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        format!(
+                            "message {} reposted {} times: {duplicates}",
+                            x.original_message_id,
+                            x.duplicates.len()
+                        )
+                    })
+                    .collect(),
+                "tab: images  esc: back",
+            ),
+            View::Search { chat, query } => (
+                format!("{} - search hash: {query}", self.chats[*chat].title),
+                self.matches
+                    .iter()
+                    .map(|x| {
+                        format!(
+                            "message {}  hash {:016x}  dst {}",
+                            x.message_id, x.phash, x.distance
+                        )
+                    })
+                    .collect(),
+                "type hex digits, enter: search  tab: images  esc: back",
+            ),
+        };
+
+        let list = List::new(items.into_iter().map(ListItem::new))
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, main, &mut self.list);
+
+        let status_line = if self.status.is_empty() {
+            help.to_owned()
+        } else {
+            format!("{}  |  {help}", self.status)
+        };
+        frame.render_widget(Paragraph::new(Line::from(status_line)), status);
+    }
+}
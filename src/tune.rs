@@ -0,0 +1,91 @@
+use crate::database;
+use sqlx::PgPool;
+
+/// Width of the histogram bars in characters.
+const BAR_WIDTH: u64 = 50;
+
+/// Samples up to `sample` hashes from the chat, prints the distribution of their pairwise
+/// distances and suggests a threshold.
+pub async fn run(pool: &PgPool, chat_id: i64, sample: i64, bits: u32) -> sqlx::Result<()> {
+    let hashes = database::sample_hashes(pool, chat_id, sample).await?;
+    if hashes.len() < 2 {
+        println!("Not enough images indexed in {chat_id} to say anything.");
+        return Ok(());
+    }
+
+    println!(
+        "Pairwise distances between {} sampled images:",
+        hashes.len()
+    );
+
+    let histogram = histogram(&hashes, bits);
+    let max = histogram.iter().copied().max().unwrap_or(0).max(1);
+
+    for (distance, count) in histogram.iter().enumerate() {
+        let bar = "#".repeat((count * BAR_WIDTH).div_ceil(max) as usize);
+        println!("{distance:>3} {count:>9} {bar}");
+    }
+
+    let Some(threshold) = suggest_threshold(&histogram) else {
+        println!(
+            "No clear valley between duplicates and unrelated images, can't suggest a threshold."
+        );
+        return Ok(());
+    };
+
+    println!("Suggested threshold: {threshold}");
+
+    // Check the suggestion against what moderators marked as false positives.
+    let sightings = database::sighting_feedback(pool, chat_id).await?;
+    if sightings.is_empty() {
+        return Ok(());
+    }
+
+    let within = |d: &&(i16, bool)| d.0 <= threshold as i16;
+    let kept = sightings.iter().filter(within).count();
+    let false_positives = sightings.iter().filter(within).filter(|x| x.1).count();
+    let total_false_positives = sightings.iter().filter(|x| x.1).count();
+
+    println!(
+        "Of {total} recorded detections, {kept} would still be flagged, {false_positives} of them \
+         marked as false positives ({total_false_positives} false positives overall).",
+        total = sightings.len(),
+    );
+
+    Ok(())
+}
+
+/// Counts of pairwise Hamming distances, indexed by distance.
+pub fn histogram(hashes: &[i64], bits: u32) -> Vec<u64> {
+    let mut histogram = vec![0; bits as usize + 1];
+
+    for (i, a) in hashes.iter().enumerate() {
+        for b in &hashes[i + 1..] {
+            let distance = (a ^ b).count_ones() as usize;
+            histogram[distance.min(bits as usize)] += 1;
+        }
+    }
+
+    histogram
+}
+
+/// Picks the emptiest distance between the duplicate mode near zero and the mode of unrelated
+/// images, which sits around half the hash width.
+pub fn suggest_threshold(histogram: &[u64]) -> Option<u8> {
+    let peak = histogram
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, count)| **count)?
+        .0;
+
+    // Smooth over neighbours so a single empty bucket in sparse data doesn't win.
+    let smoothed = |d: usize| {
+        let from = d.saturating_sub(1);
+        let to = (d + 1).min(histogram.len() - 1);
+        histogram[from..=to].iter().sum::<u64>()
+    };
+
+    (1..peak / 2)
+        .min_by_key(|d| (smoothed(*d), *d))
+        .map(|d| d as u8)
+}
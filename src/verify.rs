@@ -0,0 +1,80 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+
+/// Side length both images are scaled to before comparing.
+const SIZE: u32 = 128;
+/// SSIM is computed over non-overlapping windows of this size and averaged.
+const WINDOW: u32 = 8;
+
+/// Longest side of heatmaps.
+const HEATMAP_SIZE: u32 = 512;
+
+// Usual SSIM stabilizing constants for 8-bit images.
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Structural similarity of two images in `[-1, 1]`, 1 meaning identical. Both get scaled to
+/// the same size and converted to grayscale first, so this ignores aspect ratio and color.
+pub fn similarity(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    let a = a.resize_exact(SIZE, SIZE, FilterType::Triangle).to_luma8();
+    let b = b.resize_exact(SIZE, SIZE, FilterType::Triangle).to_luma8();
+
+    let mut total = 0.0;
+    let mut windows = 0;
+
+    for wy in (0..SIZE).step_by(WINDOW as usize) {
+        for wx in (0..SIZE).step_by(WINDOW as usize) {
+            let pixels = (0..WINDOW).flat_map(|y| (0..WINDOW).map(move |x| (wx + x, wy + y)));
+
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+            for (x, y) in pixels {
+                let pa = a.get_pixel(x, y)[0] as f64;
+                let pb = b.get_pixel(x, y)[0] as f64;
+
+                sum_a += pa;
+                sum_b += pb;
+                sum_aa += pa * pa;
+                sum_bb += pb * pb;
+                sum_ab += pa * pb;
+            }
+
+            let n = (WINDOW * WINDOW) as f64;
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+
+    total / windows as f64
+}
+
+/// Shows where `b` differs from `a`: `a` dimmed in grayscale, with differing pixels going from
+/// red to yellow the more they differ. Both are scaled to `a`'s aspect ratio, so a crop shows
+/// up as everything differing, a re-encode as faint noise and a true collision as whole
+/// shapes that don't line up.
+pub fn heatmap(a: &DynamicImage, b: &DynamicImage) -> RgbImage {
+    let a = a.resize(HEATMAP_SIZE, HEATMAP_SIZE, FilterType::Triangle);
+    let (width, height) = a.dimensions();
+    let a = a.to_luma8();
+    let b = b
+        .resize_exact(width, height, FilterType::Triangle)
+        .to_luma8();
+
+    RgbImage::from_fn(width, height, |x, y| {
+        let pa = a.get_pixel(x, y)[0];
+        let pb = b.get_pixel(x, y)[0];
+        let diff = pa.abs_diff(pb) as u32;
+        let base = pa as u32 / 3;
+
+        // Up to half the range fades in red, the rest turns it yellow.
+        let red = (base + diff * 4).min(255);
+        let green = (base + diff.saturating_sub(64) * 2).min(255);
+        Rgb([red as u8, green as u8, base as u8])
+    })
+}
@@ -0,0 +1,64 @@
+use crate::config::WebhookSettings;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{error, warn};
+
+/// Sent as JSON to every configured webhook when a duplicate is detected.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateEvent {
+    pub event: &'static str,
+    pub chat_id: i64,
+    pub chat_title: String,
+    pub message_id: i32,
+    pub original_message_id: i32,
+    pub distance: u8,
+    pub message_link: Option<String>,
+    pub original_link: Option<String>,
+}
+
+#[derive(Clone, Default)]
+pub struct Webhooks {
+    client: reqwest::Client,
+    hooks: Vec<WebhookSettings>,
+}
+
+impl Webhooks {
+    pub fn new(hooks: Vec<WebhookSettings>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            hooks,
+        }
+    }
+
+    /// Fires the event at all webhooks in the background, failures are only logged.
+    pub fn notify(&self, event: DuplicateEvent) {
+        for hook in &self.hooks {
+            let client = self.client.clone();
+            let hook = hook.clone();
+            let event = event.clone();
+
+            tokio::spawn(async move {
+                let mut request = client
+                    .post(&hook.url)
+                    .timeout(Duration::from_secs(hook.timeout_secs))
+                    .json(&event);
+
+                if let Some(secret) = &hook.secret {
+                    request = request.header("X-Dupfinder-Secret", secret);
+                }
+
+                match request.send().await {
+                    Ok(response) if !response.status().is_success() => {
+                        warn!(
+                            "Webhook {url} responded with {status}",
+                            url = hook.url,
+                            status = response.status()
+                        );
+                    }
+                    Ok(_) => (),
+                    Err(e) => error!("Webhook {url} failed: {e}", url = hook.url),
+                }
+            });
+        }
+    }
+}
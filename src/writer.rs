@@ -0,0 +1,142 @@
+use crate::config::BatchWriteSettings;
+use crate::database::{self, NewImage};
+use crate::messenger::ForwardOrigin;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, error};
+
+/// A batch is dropped after failing this many times in a row.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Indexes new images from a background task in batched inserts, so replies don't wait on
+/// the database and short stalls queue up here instead of in the dispatcher.
+///
+/// Images still queued when the process exits are lost, and an image isn't matched against
+/// until its batch is written.
+#[derive(Clone)]
+pub struct Writer {
+    tx: mpsc::Sender<PendingImage>,
+}
+
+/// Owned copy of a [`NewImage`] waiting in the queue.
+struct PendingImage {
+    chat_id: i64,
+    chat_title: String,
+    message_id: i32,
+    phash: i64,
+    alt_phash: Option<i64>,
+    media_key: Option<String>,
+    media_ref: Option<String>,
+    sender_id: Option<i64>,
+    forward: Option<ForwardOrigin>,
+    spoiler: bool,
+    low_entropy: bool,
+    source: String,
+    caption: Option<String>,
+}
+
+impl PendingImage {
+    fn image(&self) -> NewImage<'_> {
+        NewImage {
+            chat_id: self.chat_id,
+            chat_title: &self.chat_title,
+            message_id: self.message_id,
+            phash: self.phash,
+            alt_phash: self.alt_phash,
+            media_key: self.media_key.as_deref(),
+            media_ref: self.media_ref.as_deref(),
+            sender_id: self.sender_id,
+            forward: self.forward,
+            spoiler: self.spoiler,
+            low_entropy: self.low_entropy,
+            source: &self.source,
+            caption: self.caption.as_deref(),
+        }
+    }
+}
+
+impl Writer {
+    /// Spawns the writer task, which runs until every clone of the writer is dropped.
+    pub fn spawn(pool: PgPool, settings: BatchWriteSettings) -> Self {
+        let (tx, rx) = mpsc::channel(settings.queue_size);
+        tokio::spawn(run(pool, settings, rx));
+
+        Self { tx }
+    }
+
+    /// Queues the image, waiting for room if the queue is full.
+    pub async fn save(&self, image: &NewImage<'_>) {
+        let image = PendingImage {
+            chat_id: image.chat_id,
+            chat_title: image.chat_title.to_owned(),
+            message_id: image.message_id,
+            phash: image.phash,
+            alt_phash: image.alt_phash,
+            media_key: image.media_key.map(ToOwned::to_owned),
+            media_ref: image.media_ref.map(ToOwned::to_owned),
+            sender_id: image.sender_id,
+            forward: image.forward,
+            spoiler: image.spoiler,
+            low_entropy: image.low_entropy,
+            source: image.source.to_owned(),
+            caption: image.caption.map(ToOwned::to_owned),
+        };
+
+        if self.tx.send(image).await.is_err() {
+            error!("The batch writer is gone, dropping an image");
+        }
+    }
+}
+
+async fn run(pool: PgPool, settings: BatchWriteSettings, mut rx: mpsc::Receiver<PendingImage>) {
+    let max_delay = Duration::from_millis(settings.max_delay_ms);
+    let mut batch = Vec::with_capacity(settings.max_batch);
+
+    while let Some(image) = rx.recv().await {
+        batch.push(image);
+
+        // Collect whatever else arrives shortly after, up to a full batch.
+        let deadline = Instant::now() + max_delay;
+        while batch.len() < settings.max_batch {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(image)) => batch.push(image),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        write(&pool, &batch).await;
+        batch.clear();
+    }
+}
+
+/// Writes the batch, retrying with backoff while the database is unavailable.
+async fn write(pool: &PgPool, batch: &[PendingImage]) {
+    for attempt in 1..=MAX_ATTEMPTS {
+        match insert(pool, batch).await {
+            Ok(()) => {
+                debug!("Wrote a batch of {} images", batch.len());
+                return;
+            }
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                error!("Error writing a batch of {} images: {e}", batch.len());
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+            }
+            Err(e) => error!("Giving up on a batch of {} images: {e}", batch.len()),
+        }
+    }
+}
+
+async fn insert(pool: &PgPool, batch: &[PendingImage]) -> sqlx::Result<()> {
+    // An upsert can't touch the same row twice, the latest copy of a message wins.
+    let images = batch
+        .iter()
+        .map(|x| ((x.chat_id, x.message_id), x.image()))
+        .collect::<HashMap<_, _>>()
+        .into_values()
+        .collect::<Vec<_>>();
+
+    database::save_images(&mut *pool.acquire().await?, &images).await
+}
@@ -0,0 +1,776 @@
+//! Tests of the database queries against a real Postgres, via `sqlx::test`. Each test gets a
+//! fresh database with the migrations applied, which needs `DATABASE_URL` to be set.
+
+use dupfinder_tg::audit;
+use dupfinder_tg::backup;
+use dupfinder_tg::config::EvictionPolicy;
+use dupfinder_tg::database::{self, NewImage};
+use dupfinder_tg::messenger::MessageRef;
+use dupfinder_tg::notices;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::strategy::ValueTree;
+use proptest::test_runner::TestRunner;
+use serde_json::json;
+use sqlx::PgPool;
+
+const CHAT_ID: i64 = -100;
+
+/// A live image without a caption, for tests to override what they care about.
+fn image<'a>(chat_id: i64, message_id: i32, phash: i64) -> NewImage<'a> {
+    NewImage {
+        chat_id,
+        chat_title: "Test chat",
+        message_id,
+        phash,
+        alt_phash: None,
+        media_key: None,
+        media_ref: None,
+        sender_id: None,
+        forward: None,
+        spoiler: false,
+        low_entropy: false,
+        source: database::LIVE_SOURCE,
+        caption: None,
+    }
+}
+
+async fn save(pool: &PgPool, image: &NewImage<'_>) {
+    database::save_image(&mut pool.acquire().await.unwrap(), image)
+        .await
+        .unwrap();
+}
+
+async fn insert(pool: &PgPool, chat_id: i64, message_id: i32, phash: i64) {
+    save(pool, &image(chat_id, message_id, phash)).await;
+}
+
+/// Message ids of search results, sorted for comparing.
+fn sorted(ids: impl IntoIterator<Item = i32>) -> Vec<i32> {
+    let mut ids = ids.into_iter().collect::<Vec<_>>();
+    ids.sort();
+    ids
+}
+
+/// `(message_id, distance)` of the match, for comparing.
+async fn closest(
+    pool: &PgPool,
+    hash: i64,
+    threshold: u8,
+    exclude: Option<i32>,
+) -> Option<(i32, u8)> {
+    database::find_closest_match(
+        &mut pool.acquire().await.unwrap(),
+        CHAT_ID,
+        hash,
+        None,
+        threshold,
+        exclude,
+        false,
+    )
+    .await
+    .unwrap()
+    .map(|x| (x.message_id, x.distance))
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn nearest_is_selected(pool: PgPool) {
+    // Messages 1 and 3 are both 1 bit away, the lower message id wins the tie.
+    assert_eq!(closest(&pool, 0, 64, None).await, Some((1, 1)));
+    assert_eq!(closest(&pool, 7, 64, None).await, Some((2, 0)));
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn queried_message_is_excluded(pool: PgPool) {
+    assert_eq!(closest(&pool, 7, 64, Some(2)).await, Some((1, 2)));
+    assert_eq!(closest(&pool, 0, 64, Some(1)).await, Some((3, 1)));
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn threshold_is_inclusive(pool: PgPool) {
+    // Message 4 is 8 bits away from 511, everything else further.
+    assert_eq!(closest(&pool, 511, 1, None).await, Some((4, 1)));
+    assert_eq!(closest(&pool, 255 ^ 0b111, 3, None).await, Some((4, 3)));
+    assert_eq!(closest(&pool, 255 ^ 0b111, 2, None).await, None);
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn other_chats_are_ignored(pool: PgPool) {
+    // Message 5 in the other chat would be an exact match.
+    assert_eq!(closest(&pool, 0, 0, None).await, None);
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn networks_share_originals(pool: PgPool) {
+    database::set_chat_networks(&pool, &[("test", &[CHAT_ID, -200][..])])
+        .await
+        .unwrap();
+    let found = database::find_closest_match(
+        &mut pool.acquire().await.unwrap(),
+        CHAT_ID,
+        0,
+        None,
+        0,
+        None,
+        false,
+    )
+    .await
+    .unwrap()
+    .map(|x| (x.chat_id, x.message_id));
+    assert_eq!(found, Some((-200, 5)));
+
+    // Networks are replaced rather than added to.
+    database::set_chat_networks(&pool, &[]).await.unwrap();
+    assert_eq!(closest(&pool, 0, 0, None).await, None);
+}
+
+#[sqlx::test(fixtures("chats"))]
+async fn matching_reads_only_the_partitions_of_its_sources(pool: PgPool) {
+    insert(&pool, CHAT_ID, 1, 0).await;
+    insert(&pool, -200, 2, 0).await;
+
+    // Every partition a query reads stays locked until its transaction ends.
+    let mut tx = pool.begin().await.unwrap();
+    database::find_closest_match(&mut tx, CHAT_ID, 0, None, 0, None, false)
+        .await
+        .unwrap();
+    database::nearest_images(&mut tx, CHAT_ID, 0, None, None, 5)
+        .await
+        .unwrap();
+    let partitions: Vec<String> = sqlx::query_scalar(
+        r#"
+        SELECT relation::regclass::TEXT FROM pg_locks
+        WHERE pid = pg_backend_pid()
+            AND relation IN (SELECT inhrelid FROM pg_inherits WHERE inhparent = 'images'::regclass)
+        "#,
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .unwrap();
+
+    assert_eq!(partitions, ["images_n100"]);
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn quota_keeps_the_newest_images(pool: PgPool) {
+    let evicted = database::evict_over_quota(&pool, 2, EvictionPolicy::OldestFirst)
+        .await
+        .unwrap();
+    assert_eq!(evicted, 2);
+
+    // Messages 3 and 4 are left, the other chat is within its quota.
+    assert_eq!(closest(&pool, 0, 1, None).await, Some((3, 1)));
+    assert_eq!(closest(&pool, 7, 0, None).await, None);
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn forgetting_an_image_is_audited(pool: PgPool) {
+    let id = database::list_images(&pool, CHAT_ID, 1, 0).await.unwrap()[0].id;
+    // Only the chat the image is in can delete it.
+    assert!(
+        database::delete_image(&pool, -200, id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let message = database::delete_image(&pool, CHAT_ID, id)
+        .await
+        .unwrap()
+        .unwrap();
+    audit::record(
+        &pool,
+        Some(message.chat_id),
+        Some(42),
+        audit::Action::Forget,
+        json!({ "message_id": message.message_id }),
+    )
+    .await;
+
+    // Deleting it again does nothing, and neither does restoring it from another chat.
+    assert!(
+        database::delete_image(&pool, CHAT_ID, id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        database::restore_image(&pool, -200, id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let entries = database::audit_log(&pool, Some(CHAT_ID), 10).await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].actor_id, Some(42));
+    assert_eq!(entries[0].action, "forget");
+    assert_eq!(entries[0].payload["message_id"], message.message_id);
+
+    assert!(
+        database::audit_log(&pool, Some(-200), 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn deleting_a_chat_leaves_the_others(pool: PgPool) {
+    database::ensure_partition(&pool, CHAT_ID).await.unwrap();
+    assert_eq!(database::delete_chat(&pool, CHAT_ID).await.unwrap(), 4);
+    assert_eq!(closest(&pool, 0, 64, None).await, None);
+    assert!(
+        database::chat_title(&pool, CHAT_ID)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let other = database::find_closest_match(
+        &mut pool.acquire().await.unwrap(),
+        -200,
+        0,
+        None,
+        0,
+        None,
+        false,
+    )
+    .await
+    .unwrap()
+    .map(|x| x.message_id);
+    assert_eq!(other, Some(5));
+
+    // The chat can come back afterwards.
+    insert(&pool, CHAT_ID, 1, 0).await;
+    assert_eq!(closest(&pool, 0, 0, None).await, Some((1, 0)));
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn remapping_a_chat_moves_its_images(pool: PgPool) {
+    // Message 1 is in both, the one already under the new id stays.
+    insert(&pool, -200, 1, 12345).await;
+
+    let moved = database::remap_chat(&pool, CHAT_ID, -200).await.unwrap();
+    assert_eq!(moved, 3);
+
+    assert_eq!(
+        database::known_message_ids(&pool, CHAT_ID)
+            .await
+            .unwrap()
+            .len(),
+        0
+    );
+    assert_eq!(
+        database::known_message_ids(&pool, -200)
+            .await
+            .unwrap()
+            .len(),
+        5
+    );
+    assert_eq!(database::chat_title(&pool, CHAT_ID).await.unwrap(), None);
+    let phash: i64 =
+        sqlx::query_scalar("SELECT phash FROM images WHERE chat_id = -200 AND message_id = 1")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(phash, 12345);
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn chats_keep_their_images_while_removed(pool: PgPool) {
+    let chat = async |pool: &PgPool| {
+        database::chat_stats(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|x| x.id == CHAT_ID)
+            .unwrap()
+    };
+
+    database::chat_removed(&pool, CHAT_ID).await.unwrap();
+    let removed = chat(&pool).await;
+    assert!(removed.removed_at.is_some());
+    assert_eq!(removed.images, 4);
+
+    database::chat_added(&pool, CHAT_ID, "Renamed", "supergroup")
+        .await
+        .unwrap();
+    let added = chat(&pool).await;
+    assert!(added.removed_at.is_none());
+    assert_eq!(added.title, "Renamed");
+    assert_eq!(added.kind.as_deref(), Some("supergroup"));
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn sweep_probes_unchecked_images_first(pool: PgPool) {
+    let sample = database::sample_live_images(&pool, &[CHAT_ID], 4, true)
+        .await
+        .unwrap();
+    assert_eq!(sample.len(), 4);
+    for message in &sample[..3] {
+        database::mark_checked(&pool, *message).await.unwrap();
+    }
+
+    let next = database::sample_live_images(&pool, &[CHAT_ID], 1, true)
+        .await
+        .unwrap();
+    assert_eq!(next, [sample[3]]);
+}
+
+#[sqlx::test(fixtures("chats"))]
+async fn alternate_hash_counts_when_closer(pool: PgPool) {
+    let image = NewImage {
+        alt_phash: Some(0),
+        ..image(CHAT_ID, 1, -1)
+    };
+    save(&pool, &image).await;
+
+    let found = database::find_closest_match(
+        &mut pool.acquire().await.unwrap(),
+        CHAT_ID,
+        0,
+        Some(1),
+        5,
+        None,
+        false,
+    )
+    .await
+    .unwrap()
+    .map(|x| (x.message_id, x.distance));
+    assert_eq!(found, Some((1, 1)));
+
+    // Without an alternate on the query side only the main hashes are compared.
+    assert_eq!(closest(&pool, 0, 5, None).await, None);
+}
+
+#[sqlx::test(fixtures("chats"))]
+async fn saving_an_image_keeps_the_chat_title(pool: PgPool) {
+    database::set_chat_title(&pool, CHAT_ID, "Renamed")
+        .await
+        .unwrap();
+    insert(&pool, CHAT_ID, 1, 0).await;
+
+    let title = database::chat_title(&pool, CHAT_ID).await.unwrap();
+    assert_eq!(title.as_deref(), Some("Renamed"));
+}
+
+#[sqlx::test(fixtures("chats"))]
+async fn saving_a_message_again_updates_it(pool: PgPool) {
+    insert(&pool, CHAT_ID, 1, 0).await;
+    insert(&pool, CHAT_ID, 1, 255).await;
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM images WHERE chat_id = $1")
+        .bind(CHAT_ID)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+    assert_eq!(closest(&pool, 255, 0, None).await, Some((1, 0)));
+}
+
+#[sqlx::test(fixtures("chats"))]
+async fn importing_leaves_live_images_alone(pool: PgPool) {
+    insert(&pool, CHAT_ID, 1, 0).await;
+
+    let source = database::import_source("result.json");
+    let image = NewImage {
+        source: &source,
+        ..image(CHAT_ID, 1, 255)
+    };
+    save(&pool, &image).await;
+
+    assert_eq!(closest(&pool, 0, 0, None).await, Some((1, 0)));
+}
+
+#[sqlx::test(fixtures("chats"))]
+async fn pruning_a_source_leaves_it_to_the_purge(pool: PgPool) {
+    insert(&pool, CHAT_ID, 1, 0).await;
+    let source = database::import_source("result.json");
+    let image = NewImage {
+        source: &source,
+        ..image(CHAT_ID, 2, 255)
+    };
+    save(&pool, &image).await;
+
+    assert_eq!(database::delete_source(&pool, &source).await.unwrap(), 1);
+    assert_eq!(closest(&pool, 255, 0, None).await, None);
+    let sources = database::image_sources(&pool).await.unwrap();
+    assert_eq!(sources, [(database::LIVE_SOURCE.to_owned(), 1)]);
+
+    assert_eq!(database::purge_deleted_images(&pool, 0).await.unwrap(), 1);
+    assert_eq!(closest(&pool, 0, 0, None).await, Some((1, 0)));
+}
+
+#[sqlx::test(fixtures("chats"))]
+async fn undo_only_brings_back_forgotten_images(pool: PgPool) {
+    let source = database::import_source("result.json");
+    for message_id in [1, 2, 3] {
+        let imported = NewImage {
+            source: &source,
+            ..image(CHAT_ID, message_id, message_id.into())
+        };
+        save(&pool, &imported).await;
+    }
+
+    // Message 3 is forgotten, 2 found gone and 1 pruned along with its source.
+    let id = database::list_images(&pool, CHAT_ID, 1, 0).await.unwrap()[0].id;
+    database::delete_image(&pool, CHAT_ID, id).await.unwrap();
+    let gone = MessageRef {
+        chat_id: CHAT_ID,
+        message_id: 2,
+    };
+    database::mark_stale(&pool, gone, true).await.unwrap();
+    assert_eq!(database::delete_source(&pool, &source).await.unwrap(), 1);
+
+    assert_eq!(
+        database::undo_last_delete(&pool, CHAT_ID).await.unwrap(),
+        Some(3)
+    );
+    assert_eq!(
+        database::undo_last_delete(&pool, CHAT_ID).await.unwrap(),
+        None
+    );
+
+    assert_eq!(database::restore_source(&pool, &source).await.unwrap(), 1);
+    let ids = database::known_message_ids(&pool, CHAT_ID).await.unwrap();
+    assert_eq!(sorted(ids), [1, 3]);
+}
+
+#[sqlx::test(fixtures("chats"))]
+async fn importing_again_brings_back_a_pruned_source(pool: PgPool) {
+    insert(&pool, CHAT_ID, 1, 0).await;
+    let source = database::import_source("result.json");
+    let imported = NewImage {
+        source: &source,
+        ..image(CHAT_ID, 2, 255)
+    };
+    save(&pool, &imported).await;
+    database::delete_source(&pool, &source).await.unwrap();
+
+    let ids = database::known_message_ids(&pool, CHAT_ID).await.unwrap();
+    assert_eq!(ids.into_iter().collect::<Vec<_>>(), [1]);
+
+    let reimported = database::import_source("newer/result.json");
+    let again = NewImage {
+        source: &reimported,
+        ..image(CHAT_ID, 2, 255)
+    };
+    save(&pool, &again).await;
+
+    assert_eq!(closest(&pool, 255, 0, None).await, Some((2, 0)));
+    let sources = database::image_sources(&pool).await.unwrap();
+    assert_eq!(
+        sources,
+        [
+            (reimported.clone(), 1),
+            (database::LIVE_SOURCE.to_owned(), 1)
+        ]
+    );
+    assert_eq!(
+        database::delete_source(&pool, &reimported).await.unwrap(),
+        1
+    );
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn recording_a_sighting_again_keeps_one(pool: PgPool) {
+    let image = image(CHAT_ID, 10, 1);
+    let original = database::find_closest_match(
+        &mut pool.acquire().await.unwrap(),
+        CHAT_ID,
+        1,
+        None,
+        0,
+        None,
+        false,
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    for _ in 0..2 {
+        database::save_sighting(&pool, &image, &original)
+            .await
+            .unwrap();
+    }
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sightings WHERE message_id = 10")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn known_messages_are_those_of_the_chat(pool: PgPool) {
+    let ids = database::known_message_ids(&pool, CHAT_ID).await.unwrap();
+    assert_eq!(sorted(ids), [1, 2, 3, 4]);
+}
+
+/// The answer computed by brute force, with the same tie-breaking as the query.
+fn expected(hashes: &[i64], hash: i64, threshold: u8, exclude: Option<i32>) -> Option<(i32, u8)> {
+    hashes
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (i as i32 + 1, (x ^ hash).count_ones() as u8))
+        .filter(|(id, distance)| Some(*id) != exclude && *distance <= threshold)
+        .min_by_key(|(id, distance)| (*distance, *id))
+}
+
+#[sqlx::test(fixtures("chats"))]
+async fn matches_brute_force(pool: PgPool) {
+    // Hashes close to each other, so that there are ties and near misses to get wrong.
+    let base = any::<i64>();
+    let strategy = (
+        base,
+        vec(any::<u64>(), 1..40),
+        any::<u64>(),
+        0u8..=16,
+        any::<bool>(),
+    );
+
+    let mut runner = TestRunner::deterministic();
+    for case in 0..32 {
+        let (base, noise, query_noise, threshold, exclude) =
+            strategy.new_tree(&mut runner).unwrap().current();
+
+        // Flip a few random bits of the base for every hash.
+        let flip = |x: u64| base ^ (1i64 << (x % 64)) ^ (1i64 << ((x >> 8) % 64));
+        let hashes = noise.iter().map(|x| flip(*x)).collect::<Vec<_>>();
+        let query = flip(query_noise);
+        let exclude = exclude.then_some(1);
+
+        sqlx::query("DELETE FROM images")
+            .execute(&pool)
+            .await
+            .unwrap();
+        for (i, hash) in hashes.iter().enumerate() {
+            insert(&pool, CHAT_ID, i as i32 + 1, *hash).await;
+        }
+
+        assert_eq!(
+            closest(&pool, query, threshold, exclude).await,
+            expected(&hashes, query, threshold, exclude),
+            "case {case}: hashes {hashes:?}, query {query}, threshold {threshold}, exclude {exclude:?}"
+        );
+    }
+}
+
+#[sqlx::test(fixtures("chats"))]
+async fn images_are_found_by_caption(pool: PgPool) {
+    for (message_id, caption) in [(1, "Cat in a BOX"), (2, "100% cat"), (3, "a dog")] {
+        let image = NewImage {
+            caption: Some(caption),
+            ..image(CHAT_ID, message_id, 0)
+        };
+        save(&pool, &image).await;
+    }
+
+    let found = async |text: &str| {
+        let found = database::search_captions(&pool, CHAT_ID, text, 10)
+            .await
+            .unwrap();
+        sorted(found.into_iter().map(|x| x.message_id))
+    };
+    assert_eq!(found("cat").await, [1, 2]);
+    assert_eq!(found("box").await, [1]);
+    // Wildcards are taken literally.
+    assert_eq!(found("%").await, [2]);
+    assert_eq!(found("_").await, Vec::<i32>::new());
+
+    // Saving the message again without a caption keeps the one it had.
+    insert(&pool, CHAT_ID, 3, 0).await;
+    assert_eq!(found("dog").await, [3]);
+}
+
+#[sqlx::test(fixtures("chats"))]
+async fn archive_is_searched_by_caption_and_chat_title(pool: PgPool) {
+    // Saved first, so that it's the oldest.
+    let captions = [
+        (-200, 4, Some("cats")),
+        (CHAT_ID, 1, Some("funny cats")),
+        (CHAT_ID, 2, Some("a dog")),
+        (-200, 3, None),
+    ];
+    for (chat_id, message_id, caption) in captions {
+        save(
+            &pool,
+            &NewImage {
+                caption,
+                ..image(chat_id, message_id, 0)
+            },
+        )
+        .await;
+    }
+
+    let found = async |chat_id: Option<i64>, query: &str| {
+        let found = database::search_archive(&pool, chat_id, query, 10)
+            .await
+            .unwrap();
+        sorted(found.into_iter().map(|x| x.message_id))
+    };
+    assert_eq!(found(None, "Funny").await, [1]);
+    assert_eq!(found(None, "cats or dog").await, [1, 2, 4]);
+    assert_eq!(found(None, "\"funny dog\"").await, Vec::<i32>::new());
+    // The fixture's title of -200 is "Other chat".
+    assert_eq!(found(None, "other").await, [3, 4]);
+    assert_eq!(found(Some(CHAT_ID), "other").await, Vec::<i32>::new());
+
+    // Matching both the caption and the title ranks above either alone.
+    let best = database::search_archive(&pool, None, "other or cats", 10)
+        .await
+        .unwrap();
+    assert_eq!(best[0].message_id, 4);
+}
+
+#[sqlx::test]
+async fn backups_have_every_table(pool: PgPool) {
+    let tables: Vec<String> = sqlx::query_scalar(
+        r#"
+        SELECT t.tablename::TEXT FROM pg_tables t
+        JOIN pg_class c ON c.oid = format('%I.%I', t.schemaname, t.tablename)::regclass
+        WHERE t.schemaname = 'public'
+            AND NOT c.relispartition
+            AND t.tablename NOT IN ('_sqlx_migrations', 'claimed_messages', 'scheduled_jobs')
+        ORDER BY t.tablename
+        "#,
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+
+    let mut backed_up = backup::TABLES.to_vec();
+    backed_up.sort();
+    assert_eq!(tables, backed_up);
+}
+
+/// A path for a backup in the temporary directory, unique to the test.
+fn backup_path() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("dupfinder-{}.tar.zst", sqlx::types::Uuid::new_v4()))
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn backups_restore_into_an_empty_database(pool: PgPool) {
+    let path = backup_path();
+    backup::backup(&pool, None, &path).await.unwrap();
+
+    sqlx::query("DELETE FROM chats")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let restored = backup::restore(&pool, None, &path).await;
+    std::fs::remove_file(&path).unwrap();
+
+    restored.unwrap();
+    assert_eq!(closest(&pool, 0, 64, None).await, Some((1, 1)));
+}
+
+#[sqlx::test]
+async fn restoring_checks_the_columns(pool: PgPool) {
+    let manifest = json!({
+        "format": 1,
+        "schema_version": database::schema_version(),
+        "created_at": "2026-01-01T00:00:00Z",
+        "with_images": false,
+    });
+    let entries = [
+        ("manifest.json", manifest.to_string()),
+        (
+            "tables/chats.csv",
+            "id,title) FROM STDIN; DROP TABLE images; --\n-100,Test chat\n".to_owned(),
+        ),
+    ];
+
+    let path = backup_path();
+    let file = std::fs::File::create(&path).unwrap();
+    let mut tar = tar::Builder::new(zstd::Encoder::new(file, 0).unwrap().auto_finish());
+    for (name, data) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, name, data.as_bytes()).unwrap();
+    }
+    tar.into_inner().unwrap();
+
+    let restored = backup::restore(&pool, None, &path).await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(
+        matches!(restored, Err(backup::Error::Invalid(_))),
+        "{restored:?}"
+    );
+    assert_eq!(closest(&pool, 0, 64, None).await, None);
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn notices_lead_back_to_the_repost(pool: PgPool) {
+    let repost = NewImage {
+        media_key: Some("unique-repost"),
+        media_ref: Some("repost"),
+        sender_id: Some(42),
+        ..image(CHAT_ID, 11, 0)
+    };
+    let original = database::find_closest_match(
+        &mut pool.acquire().await.unwrap(),
+        CHAT_ID,
+        0,
+        None,
+        64,
+        None,
+        false,
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    database::save_sighting(&pool, &repost, &original)
+        .await
+        .unwrap();
+
+    // The notice replies to the original, but is about the repost.
+    let notice = MessageRef {
+        chat_id: CHAT_ID,
+        message_id: 12,
+    };
+    notices::Notices::new(pool.clone(), 1)
+        .record(
+            notice,
+            MessageRef {
+                chat_id: CHAT_ID,
+                message_id: 11,
+            },
+        )
+        .await
+        .unwrap();
+
+    let flagged = notices::flagged(&pool, notice).await.unwrap().unwrap();
+    assert_eq!(flagged.message_id, 11);
+    assert_eq!(flagged.sender_id, Some(42));
+    assert_eq!(flagged.media_ref.as_deref(), Some("repost"));
+
+    let other = MessageRef {
+        chat_id: CHAT_ID,
+        message_id: 13,
+    };
+    assert!(notices::flagged(&pool, other).await.unwrap().is_none());
+}
+
+#[sqlx::test(fixtures("chats", "images"))]
+async fn batches_add_their_chats_and_update_known_images(pool: PgPool) {
+    let images = [image(CHAT_ID, 1, 7), image(-300, 1, 0), image(-300, 2, 0)];
+    database::save_images(&mut pool.acquire().await.unwrap(), &images)
+        .await
+        .unwrap();
+
+    assert_eq!(closest(&pool, 7, 0, Some(2)).await, Some((1, 0)));
+    let titles: Vec<String> = sqlx::query_scalar("SELECT title FROM chats WHERE id = -300")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(titles, ["Test chat"]);
+    let images: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM images WHERE chat_id = -300")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(images, 2);
+}
@@ -0,0 +1,85 @@
+//! Tests of turning files into images before they're hashed: telling formats apart, and
+//! turning down ones too large to decode.
+
+use dupfinder_tg::config::HashingSettings;
+use dupfinder_tg::decode::{self, Format};
+use dupfinder_tg::hashing::{Error, Hasher};
+
+/// The start of a baseline JPEG whose frame header claims `width` x `height` pixels, with no
+/// image data after it.
+fn jpeg_header(width: u16, height: u16) -> Vec<u8> {
+    let mut data = vec![0xff, 0xd8, 0xff, 0xc0, 0x00, 0x11, 0x08];
+    data.extend(height.to_be_bytes());
+    data.extend(width.to_be_bytes());
+    data.push(3);
+    for component in 1..=3 {
+        data.extend([component, 0x11, 0x00]);
+    }
+    data
+}
+
+#[test]
+fn huge_images_are_turned_down_before_decoding() {
+    let hasher = Hasher::new(&HashingSettings::default()).unwrap();
+
+    // Decoding would fail on the missing data, the size is checked before that.
+    let result = hasher.hash_bytes(&jpeg_header(60000, 50000));
+    assert!(
+        matches!(
+            result,
+            Err(Error::ImageTooLarge {
+                width: 60000,
+                height: 50000,
+                ..
+            })
+        ),
+        "{result:?}"
+    );
+
+    let result = hasher.hash_bytes(&jpeg_header(600, 500));
+    assert!(matches!(result, Err(Error::Decode(_))), "{result:?}");
+}
+
+/// An ftyp box with the `major` brand and the `compatible` ones, as ISOBMFF files start.
+fn ftyp(major: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+    let size = 16 + 4 * compatible.len() as u32;
+    let mut data = size.to_be_bytes().to_vec();
+    data.extend(b"ftyp");
+    data.extend(major);
+    data.extend([0; 4]);
+    for brand in compatible {
+        data.extend(*brand);
+    }
+    // Where the meta box would follow.
+    data.extend([0, 0, 0, 8]);
+    data.extend(b"meta");
+    data
+}
+
+#[test]
+fn avif_is_told_by_any_of_its_brands() {
+    assert_eq!(
+        decode::sniff(&ftyp(b"avif", &[b"mif1", b"miaf"])),
+        Some(Format::Avif)
+    );
+    assert_eq!(
+        decode::sniff(&ftyp(b"mif1", &[b"avif", b"miaf"])),
+        Some(Format::Avif)
+    );
+    assert_eq!(
+        decode::sniff(&ftyp(b"msf1", &[b"miaf", b"avis"])),
+        Some(Format::Avif)
+    );
+
+    assert_eq!(
+        decode::sniff(&ftyp(b"mif1", &[b"heic", b"miaf"])),
+        Some(Format::Heif)
+    );
+    assert_eq!(decode::sniff(&ftyp(b"heic", &[])), Some(Format::Heif));
+    assert_eq!(decode::sniff(&ftyp(b"isom", &[b"mp41"])), None);
+
+    // Brands past the end of the ftyp box aren't its own.
+    let mut data = ftyp(b"mif1", &[]);
+    data.extend(b"avif");
+    assert_eq!(decode::sniff(&data), Some(Format::Heif));
+}
@@ -0,0 +1,67 @@
+//! Tests of how downloads are bounded overall and per chat.
+
+use dupfinder_tg::config::DownloadSettings;
+use dupfinder_tg::download_limiter::DownloadLimiter;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+fn limiter(max_concurrent: usize, max_per_chat: usize) -> DownloadLimiter {
+    DownloadLimiter::new(&DownloadSettings {
+        max_concurrent,
+        max_per_chat,
+        ..Default::default()
+    })
+}
+
+/// Runs `downloads` fake downloads from the chat at once, returning how many ran at the same
+/// time at most.
+async fn most_at_once(limiter: &DownloadLimiter, chat_id: i64, downloads: usize) -> usize {
+    let running = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+
+    let mut tasks = JoinSet::new();
+    for _ in 0..downloads {
+        let limiter = limiter.clone();
+        let running = running.clone();
+        let most = most.clone();
+        tasks.spawn(async move {
+            let _permit = limiter.acquire(chat_id).await;
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            running.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    tasks.join_all().await;
+
+    most.load(Ordering::SeqCst)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_burst_is_downloaded_a_few_at_a_time() {
+    let limiter = limiter(8, 2);
+    assert_eq!(most_at_once(&limiter, -100, 20).await, 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_overall_bound_comes_first() {
+    let limiter = limiter(3, 5);
+    assert_eq!(most_at_once(&limiter, -100, 20).await, 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_busy_chat_leaves_slots_for_the_others() {
+    let limiter = limiter(4, 2);
+    let busy = {
+        let limiter = limiter.clone();
+        tokio::spawn(async move { most_at_once(&limiter, -100, 50).await })
+    };
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let other = tokio::time::timeout(Duration::from_millis(100), limiter.acquire(-200)).await;
+    assert!(other.is_ok(), "the other chat had to wait for the busy one");
+    drop(other);
+    assert_eq!(busy.await.unwrap(), 2);
+}
@@ -0,0 +1 @@
+INSERT INTO chats (id, title) VALUES (-100, 'Test chat'), (-200, 'Other chat');
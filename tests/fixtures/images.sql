@@ -0,0 +1,8 @@
+-- Hashes at known distances from 0: message 1 is 1 bit away, 2 is 3 bits, 3 is 1 bit
+-- (a tie with 1), 4 is 8 bits. Message 5 is identical to 0 but in the other chat.
+INSERT INTO images (chat_id, message_id, phash) VALUES
+    (-100, 1, 1),
+    (-100, 2, 7),
+    (-100, 3, 2),
+    (-100, 4, 255),
+    (-200, 5, 0);
@@ -0,0 +1,101 @@
+//! Regression corpus for matching recompressed copies of an image. Each case renders a
+//! synthetic image, then produces what Telegram would: the original as a document and a
+//! downscaled JPEG as a photo. Both have to land within the default threshold of each other,
+//! while unrelated images must not.
+
+use dupfinder_tg::config::HashingSettings;
+use dupfinder_tg::hashing::Hasher;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+
+const THRESHOLD: u32 = 5;
+
+/// Procedural images with different structure: gradients, stripes, blobs.
+fn corpus() -> Vec<(&'static str, DynamicImage)> {
+    let (w, h) = (1600, 1200);
+
+    let gradient = RgbImage::from_fn(w, h, |x, y| {
+        Rgb([(x * 255 / w) as u8, (y * 255 / h) as u8, 128])
+    });
+    let stripes = RgbImage::from_fn(w, h, |x, y| {
+        let v = if (x / 100 + y / 150) % 2 == 0 {
+            230
+        } else {
+            30
+        };
+        Rgb([v, v / 2, 255 - v])
+    });
+    let circles = RgbImage::from_fn(w, h, |x, y| {
+        let (dx, dy) = (x as f64 - 500.0, y as f64 - 700.0);
+        let d = (dx * dx + dy * dy).sqrt();
+        let v = ((d / 40.0).sin() * 127.0 + 128.0) as u8;
+        Rgb([v, 255 - v, (x % 256) as u8])
+    });
+
+    vec![
+        ("gradient", DynamicImage::ImageRgb8(gradient)),
+        ("stripes", DynamicImage::ImageRgb8(stripes)),
+        ("circles", DynamicImage::ImageRgb8(circles)),
+    ]
+}
+
+fn encode(image: &DynamicImage, format: ImageOutputFormat) -> Vec<u8> {
+    let mut data = Vec::new();
+    image.write_to(&mut data, format).unwrap();
+    data
+}
+
+/// What Telegram makes of a photo: at most 1280 pixels per side, recompressed as JPEG.
+fn as_photo(image: &DynamicImage) -> Vec<u8> {
+    let image = image.resize(1280, 1280, FilterType::CatmullRom);
+    encode(&image, ImageOutputFormat::Jpeg(70))
+}
+
+fn hasher() -> Hasher {
+    Hasher::new(&HashingSettings {
+        normalize: true,
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+fn distance(a: i64, b: i64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[test]
+fn photo_matches_document() {
+    let hasher = hasher();
+
+    for (name, image) in corpus() {
+        let document = hasher
+            .hash_bytes(&encode(&image, ImageOutputFormat::Png))
+            .unwrap();
+        let photo = hasher.hash_bytes(&as_photo(&image)).unwrap();
+
+        let distance = distance(document, photo);
+        assert!(
+            distance <= THRESHOLD,
+            "{name}: photo and document are {distance} apart"
+        );
+    }
+}
+
+#[test]
+fn different_images_dont_match() {
+    let hasher = hasher();
+    let hashes = corpus()
+        .into_iter()
+        .map(|(name, image)| (name, hasher.hash_bytes(&as_photo(&image)).unwrap()))
+        .collect::<Vec<_>>();
+
+    for (i, (a_name, a)) in hashes.iter().enumerate() {
+        for (b_name, b) in &hashes[i + 1..] {
+            let distance = distance(*a, *b);
+            assert!(
+                distance > THRESHOLD,
+                "{a_name} and {b_name} are only {distance} apart"
+            );
+        }
+    }
+}
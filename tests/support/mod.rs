@@ -0,0 +1,324 @@
+//! Test support for running the real binary end-to-end: a fake Bot API server serving
+//! canned responses, and a throwaway Postgres database.
+//!
+//! Tests using the database need `DATABASE_URL` pointing at a server where the user may
+//! create databases, and are skipped without it outside of CI.
+
+#![allow(dead_code)]
+
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::{get, post};
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use serde_json::{Value, json};
+use sqlx::{Connection, PgConnection};
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::process::{Child, Command};
+
+pub const TOKEN: &str = "123456:TEST";
+
+#[derive(Default)]
+struct Telegram {
+    updates: Vec<Value>,
+    files: HashMap<String, Vec<u8>>,
+    /// Every sendMessage request, in order.
+    sent: Vec<Value>,
+    /// Every editMessageText request, in order.
+    edited: Vec<Value>,
+    /// How many of the next sendMessage requests are rejected as flood limited.
+    flood_limited: usize,
+}
+
+/// A fake Bot API. Unknown methods succeed with `true`, which covers everything the bot
+/// calls that the tests don't care about.
+#[derive(Clone)]
+pub struct MockTelegram {
+    pub url: String,
+    state: Arc<Mutex<Telegram>>,
+}
+
+impl MockTelegram {
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(Telegram::default()));
+
+        let app = Router::new()
+            .route("/{bot}/{method}", post(method).get(method))
+            .route("/file/{bot}/{*path}", get(file))
+            .with_state(Arc::clone(&state));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Self { url, state }
+    }
+
+    pub fn add_file(&self, file_id: &str, data: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        state.files.insert(file_id.to_owned(), data);
+    }
+
+    /// Queues an update, its `update_id` is assigned here.
+    pub fn push_update(&self, mut update: Value) {
+        let mut state = self.state.lock().unwrap();
+        update["update_id"] = json!(state.updates.len() + 1);
+        state.updates.push(update);
+    }
+
+    /// Rejects the next `count` messages the bot sends, asking it to wait a second.
+    pub fn flood_limit(&self, count: usize) {
+        self.state.lock().unwrap().flood_limited = count;
+    }
+
+    /// Waits until the bot has sent at least `count` messages.
+    pub async fn wait_for_messages(&self, count: usize, timeout: Duration) -> Vec<Value> {
+        let started = Instant::now();
+        loop {
+            let sent = self.state.lock().unwrap().sent.clone();
+            if sent.len() >= count || started.elapsed() > timeout {
+                return sent;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Waits until the bot has edited at least `count` messages.
+    pub async fn wait_for_edits(&self, count: usize, timeout: Duration) -> Vec<Value> {
+        let started = Instant::now();
+        loop {
+            let edited = self.state.lock().unwrap().edited.clone();
+            if edited.len() >= count || started.elapsed() > timeout {
+                return edited;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+async fn method(
+    State(state): State<Arc<Mutex<Telegram>>>,
+    Path((_, method)): Path<(String, String)>,
+    body: Bytes,
+) -> Response {
+    let params = serde_json::from_slice::<Value>(&body).unwrap_or(Value::Null);
+
+    // Method names are case-insensitive, teloxide sends them capitalized.
+    let result = match method.to_ascii_lowercase().as_str() {
+        "getme" => json!({
+            "id": 123456,
+            "is_bot": true,
+            "first_name": "Test",
+            "username": "test_bot",
+            "can_join_groups": true,
+            "can_read_all_group_messages": true,
+            "supports_inline_queries": false,
+            "has_main_web_app": false,
+        }),
+        "getupdates" => {
+            let offset = params["offset"].as_u64().unwrap_or(0) as usize;
+            let updates = state
+                .lock()
+                .unwrap()
+                .updates
+                .iter()
+                .skip(offset.saturating_sub(1))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            // Long polling, without making the bot spin.
+            if updates.is_empty() {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+
+            json!(updates)
+        }
+        "getfile" => {
+            let file_id = params["file_id"].as_str().unwrap_or_default();
+            let size = state
+                .lock()
+                .unwrap()
+                .files
+                .get(file_id)
+                .map(|x| x.len())
+                .unwrap_or_default();
+
+            json!({
+                "file_id": file_id,
+                "file_unique_id": format!("unique-{file_id}"),
+                "file_size": size,
+                "file_path": format!("photos/{file_id}"),
+            })
+        }
+        "sendmessage" => {
+            let mut state = state.lock().unwrap();
+            if state.flood_limited > 0 {
+                state.flood_limited -= 1;
+                return Json(json!({
+                    "ok": false,
+                    "error_code": 429,
+                    "description": "Too Many Requests: retry after 1",
+                    "parameters": {"retry_after": 1},
+                }))
+                .into_response();
+            }
+
+            state.sent.push(params.clone());
+
+            json!({
+                "message_id": 1000 + state.sent.len(),
+                "date": 1_700_000_000,
+                "chat": {"id": params["chat_id"], "type": "supergroup", "title": "Test"},
+                "text": params["text"],
+            })
+        }
+        "editmessagetext" => {
+            state.lock().unwrap().edited.push(params.clone());
+
+            json!({
+                "message_id": params["message_id"],
+                "date": 1_700_000_000,
+                "edit_date": 1_700_000_001,
+                "chat": {"id": params["chat_id"], "type": "supergroup", "title": "Test"},
+                "text": params["text"],
+            })
+        }
+        _ => json!(true),
+    };
+
+    Json(json!({"ok": true, "result": result})).into_response()
+}
+
+async fn file(
+    State(state): State<Arc<Mutex<Telegram>>>,
+    Path((_, path)): Path<(String, String)>,
+) -> Response {
+    let file_id = path.trim_start_matches("photos/");
+    match state.lock().unwrap().files.get(file_id) {
+        Some(data) => data.clone().into_response(),
+        None => axum::http::StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// A photo message in a group, with a single size.
+pub fn photo_update(chat_id: i64, message_id: i32, file_id: &str) -> Value {
+    json!({
+        "message": {
+            "message_id": message_id,
+            "date": 1_700_000_000,
+            "chat": {"id": chat_id, "type": "supergroup", "title": "Test group"},
+            "from": {"id": 42, "is_bot": false, "first_name": "Tester"},
+            "photo": [{
+                "file_id": file_id,
+                "file_unique_id": format!("unique-{file_id}"),
+                "width": 640,
+                "height": 480,
+                "file_size": 1000,
+            }],
+        }
+    })
+}
+
+/// A PNG that differs clearly between seeds: a 4x4 grid of black and white cells, picked by
+/// the seed's bits. Anything finer is averaged away before hashing.
+pub fn test_png(seed: u32) -> Vec<u8> {
+    let cells = seed.wrapping_mul(0x9e37_79b9) ^ seed.rotate_left(16);
+    let image = RgbImage::from_fn(640, 480, |x, y| {
+        let cell = y / 120 * 4 + x / 160;
+        let v = ((cells >> cell) & 1) as u8 * 255;
+        Rgb([v, v, v])
+    });
+
+    let mut data = Vec::new();
+    DynamicImage::ImageRgb8(image)
+        .write_to(&mut data, ImageOutputFormat::Png)
+        .unwrap();
+    data
+}
+
+/// A database of its own, created on the server `DATABASE_URL` points at.
+pub struct TestDatabase {
+    pub url: String,
+    admin_url: String,
+    name: String,
+}
+
+impl TestDatabase {
+    /// `None` if `DATABASE_URL` isn't set, except in CI, where the tests would pass without
+    /// running.
+    pub async fn create() -> Option<Self> {
+        let Ok(admin_url) = std::env::var("DATABASE_URL") else {
+            assert!(
+                std::env::var_os("CI").is_none(),
+                "DATABASE_URL has to be set in CI"
+            );
+            return None;
+        };
+        let name = format!("dupfinder_test_{}", sqlx::types::Uuid::new_v4().simple());
+
+        let mut conn = PgConnection::connect(&admin_url).await.unwrap();
+        sqlx::query(&format!("CREATE DATABASE {name}"))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        // Swap the database name at the end of the URL.
+        let (base, _) = admin_url
+            .split_once('?')
+            .map_or((admin_url.as_str(), ""), |x| x);
+        let base = base.rsplit_once('/').map_or(base, |(x, _)| x);
+
+        Some(Self {
+            url: format!("{base}/{name}"),
+            admin_url,
+            name,
+        })
+    }
+
+    pub async fn drop(self) {
+        let mut conn = PgConnection::connect(&self.admin_url).await.unwrap();
+        sqlx::query(&format!("DROP DATABASE {} WITH (FORCE)", self.name))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+}
+
+/// Runs `dupfinder-tg run` against the fake Bot API, with any `extra` config appended.
+/// The process is killed when the returned handle is dropped.
+pub fn run_bot(telegram: &MockTelegram, database: &TestDatabase, extra: &str) -> Child {
+    let config = format!(
+        r#"
+similarity-threshold = 5
+{extra}
+
+[telegram]
+token = "{TOKEN}"
+api-url = "{api_url}"
+
+[database]
+url = "{database_url}"
+"#,
+        api_url = telegram.url,
+        database_url = database.url,
+    );
+
+    let path = std::env::temp_dir().join(format!("{}.toml", database.name));
+    std::fs::write(&path, config).unwrap();
+
+    Command::new(env!("CARGO_BIN_EXE_dupfinder-tg"))
+        .arg("--config")
+        .arg(&path)
+        .arg("run")
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap()
+}
@@ -0,0 +1,218 @@
+//! End-to-end tests of the Telegram bot against a fake Bot API and a temporary database.
+
+mod support;
+
+use serde_json::json;
+use std::time::Duration;
+use support::{MockTelegram, TestDatabase, photo_update, run_bot, test_png};
+
+const CHAT_ID: i64 = -1001234567890;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn repost_gets_a_reply() {
+    let Some(database) = TestDatabase::create().await else {
+        eprintln!("DATABASE_URL isn't set, skipping");
+        return;
+    };
+
+    let telegram = MockTelegram::start().await;
+    telegram.add_file("first", test_png(1));
+    telegram.add_file("repost", test_png(1));
+    telegram.push_update(photo_update(CHAT_ID, 10, "first"));
+    telegram.push_update(photo_update(CHAT_ID, 11, "repost"));
+
+    let bot = run_bot(&telegram, &database, "");
+    let sent = telegram.wait_for_messages(1, Duration::from_secs(30)).await;
+    drop(bot);
+    database.drop().await;
+
+    assert_eq!(sent.len(), 1, "expected exactly one reply, got {sent:?}");
+    assert_eq!(sent[0]["chat_id"], CHAT_ID);
+    assert_eq!(sent[0]["reply_parameters"]["message_id"], 11);
+    assert!(
+        sent[0]["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("duplicate image")
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn repost_in_a_basic_group_is_pointed_out_on_the_original() {
+    let Some(database) = TestDatabase::create().await else {
+        eprintln!("DATABASE_URL isn't set, skipping");
+        return;
+    };
+
+    // Basic groups have no message links.
+    let chat_id = -4567;
+    let telegram = MockTelegram::start().await;
+    telegram.add_file("first", test_png(1));
+    telegram.add_file("repost", test_png(1));
+    telegram.push_update(photo_update(chat_id, 10, "first"));
+    telegram.push_update(photo_update(chat_id, 11, "repost"));
+
+    let bot = run_bot(&telegram, &database, "");
+    let sent = telegram.wait_for_messages(1, Duration::from_secs(30)).await;
+    drop(bot);
+    database.drop().await;
+
+    assert_eq!(sent.len(), 1, "expected exactly one reply, got {sent:?}");
+    assert_eq!(sent[0]["reply_parameters"]["message_id"], 10);
+    let text = sent[0]["text"].as_str().unwrap();
+    assert!(
+        text.starts_with("this image was just reposted by user 42 in message 11"),
+        "{text}"
+    );
+    assert!(text.contains("first sent by them on"), "{text}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn flood_limited_reply_is_sent_again() {
+    let Some(database) = TestDatabase::create().await else {
+        eprintln!("DATABASE_URL isn't set, skipping");
+        return;
+    };
+
+    let telegram = MockTelegram::start().await;
+    telegram.flood_limit(1);
+    telegram.add_file("first", test_png(1));
+    telegram.add_file("repost", test_png(1));
+    telegram.push_update(photo_update(CHAT_ID, 10, "first"));
+    telegram.push_update(photo_update(CHAT_ID, 11, "repost"));
+
+    let bot = run_bot(&telegram, &database, "");
+    let sent = telegram.wait_for_messages(1, Duration::from_secs(30)).await;
+    drop(bot);
+    database.drop().await;
+
+    assert_eq!(
+        sent.len(),
+        1,
+        "expected the reply to be sent again, got {sent:?}"
+    );
+    assert_eq!(sent[0]["reply_parameters"]["message_id"], 11);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn different_images_are_left_alone() {
+    let Some(database) = TestDatabase::create().await else {
+        eprintln!("DATABASE_URL isn't set, skipping");
+        return;
+    };
+
+    let telegram = MockTelegram::start().await;
+    telegram.add_file("first", test_png(1));
+    telegram.add_file("second", test_png(7));
+    telegram.push_update(photo_update(CHAT_ID, 10, "first"));
+    telegram.push_update(photo_update(CHAT_ID, 11, "second"));
+
+    let bot = run_bot(&telegram, &database, "");
+    let sent = telegram.wait_for_messages(1, Duration::from_secs(5)).await;
+    drop(bot);
+    database.drop().await;
+
+    assert!(sent.is_empty(), "expected no replies, got {sent:?}");
+}
+
+/// Posts an image and two copies of it in a row with `extra` config, expecting the copies to
+/// share one notice. Returns the text it was edited to, `None` without a database.
+async fn shared_notice(extra: &str) -> Option<String> {
+    let Some(database) = TestDatabase::create().await else {
+        eprintln!("DATABASE_URL isn't set, skipping");
+        return None;
+    };
+
+    let telegram = MockTelegram::start().await;
+    telegram.add_file("first", test_png(1));
+    telegram.add_file("repost", test_png(1));
+    telegram.add_file("again", test_png(1));
+    telegram.push_update(photo_update(CHAT_ID, 10, "first"));
+    telegram.push_update(photo_update(CHAT_ID, 11, "repost"));
+    telegram.push_update(photo_update(CHAT_ID, 12, "again"));
+
+    let bot = run_bot(&telegram, &database, extra);
+    let edited = telegram.wait_for_edits(1, Duration::from_secs(30)).await;
+    let sent = telegram.wait_for_messages(2, Duration::from_secs(2)).await;
+    drop(bot);
+    database.drop().await;
+
+    assert_eq!(sent.len(), 1, "expected a single notice, got {sent:?}");
+    assert_eq!(
+        edited.len(),
+        1,
+        "expected the notice to be edited, got {edited:?}"
+    );
+    assert_eq!(edited[0]["message_id"], 1001);
+    Some(edited[0]["text"].as_str().unwrap().to_owned())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reposts_in_a_row_share_a_notice() {
+    if let Some(text) = shared_notice("aggregate-notices = true").await {
+        assert!(text.starts_with("2 reposts in the last minute"), "{text}");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn duplicates_in_a_burst_share_a_notice() {
+    if let Some(text) = shared_notice("burst-summaries = { images = 2 }").await {
+        assert!(
+            text.starts_with("2 duplicates among the images just sent"),
+            "{text}"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn query_answers_about_an_image_from_another_chat() {
+    let Some(database) = TestDatabase::create().await else {
+        eprintln!("DATABASE_URL isn't set, skipping");
+        return;
+    };
+
+    let telegram = MockTelegram::start().await;
+    telegram.add_file("first", test_png(1));
+    telegram.add_file("elsewhere", test_png(1));
+    telegram.push_update(photo_update(CHAT_ID, 10, "first"));
+    telegram.push_update(json!({
+        "message": {
+            "message_id": 11,
+            "date": 1_700_000_000,
+            "chat": {"id": CHAT_ID, "type": "supergroup", "title": "Test group"},
+            "from": {"id": 43, "is_bot": false, "first_name": "Asker"},
+            "text": "dup?",
+            "external_reply": {
+                "origin": {
+                    "type": "channel",
+                    "date": 1_700_000_000,
+                    "chat": {"id": -1009876543210_i64, "type": "channel", "title": "Other"},
+                    "message_id": 5,
+                },
+                "chat": {"id": -1009876543210_i64, "type": "channel", "title": "Other"},
+                "message_id": 5,
+                "photo": [{
+                    "file_id": "elsewhere",
+                    "file_unique_id": "unique-elsewhere",
+                    "width": 640,
+                    "height": 480,
+                    "file_size": 1000,
+                }],
+            },
+        }
+    }));
+
+    let bot = run_bot(&telegram, &database, "");
+    let sent = telegram.wait_for_messages(1, Duration::from_secs(30)).await;
+    drop(bot);
+    database.drop().await;
+
+    assert_eq!(sent.len(), 1, "expected an answer, got {sent:?}");
+    assert_eq!(sent[0]["reply_parameters"]["message_id"], 11);
+    assert!(
+        sent[0]["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("closest match")
+    );
+}